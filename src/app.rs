@@ -1,37 +1,148 @@
 use crate::{
+    bulk_rename,
+    details::{Column, DetailView},
+    dir_size::DirSizer,
+    disk_usage::DiskUsage,
+    error::{Error, PathContext, Result},
+    file_ops::{format_size, free_name, Resolution, Transfer, TransferKind},
+    file_tree_state::VisualKind,
     file_tree_widget::FileTreeWidget,
+    jobs::{format_duration, FinishedJob, Job, JobId, JobQueue, JobState},
+    journal::{Journal, Operation, Replay},
+    line_editor_widget::{EditorEvent, LineEditor},
     preview_pane_widget::PreviewPane,
+    sort::SortKey,
     status_bar_widget::StatusBar,
-    tree::{FileObj, FileObjType, FileTree, NavDirection},
+    trash::{claim_slot, remove_info},
+    tree::{display_name, parse_name, rebase_paths, FileObjType, FileTree, NavDirection},
     tui,
+    watcher::TreeWatcher,
 };
-use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use chrono::{DateTime, Local};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
 use log::error;
 use ratatui::{
     backend::CrosstermBackend,
     buffer::Buffer,
     layout::{Constraint, Layout, Rect},
-    style::{Color, Style},
-    widgets::{Block, Widget},
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, Clear, List, ListItem, Paragraph, Widget, Wrap},
     Terminal,
 };
 use ratatui::{prelude::StatefulWidget, text::Text};
-use std::{fs, io};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io,
+    path::{Component, Path, PathBuf},
+    sync::atomic::Ordering,
+    time::Duration,
+};
 use tui::Tui;
 
+/// Number of messages kept for `:messages`
+const MESSAGE_HISTORY: usize = 500;
+/// How often the main loop wakes up to check for changes on disk while nothing else happens
+const IDLE_INTERVAL: Duration = Duration::from_millis(250);
+/// How often it wakes up while jobs run or changes on disk are about to be applied
+const BUSY_INTERVAL: Duration = Duration::from_millis(100);
+
 #[derive(Default, Debug)]
 pub struct App {
     pub exit: bool,
     pub tree: FileTree,
     pub preview_pane: PreviewPane,
     pub app_action: AppAction,
-    pub notify: String,
+    /// Message shown at the bottom of the screen until the next one, or until dismissed
+    pub message: Option<Message>,
+    /// Every message shown this session, for `:messages`
+    pub messages: Vec<Message>,
+    /// Copies and moves running in the background
+    pub jobs: JobQueue,
+    pub mode: AppMode,
+    /// First key of a two key command like `dd`
+    pub pending_key: Option<char>,
+    /// Completed file operations, for undo/redo
+    pub journal: Journal,
+    /// Text previously entered in rename and create prompts
+    pub prompt_history: Vec<String>,
+    /// Commands previously entered at the `:` prompt
+    pub command_history: Vec<String>,
+    /// Paths to bulk rename in an external editor, picked up by the main loop
+    pub bulk_rename: Option<Vec<PathBuf>>,
+    /// Keeps the tree up to date with changes made outside the app
+    pub watcher: Option<TreeWatcher>,
+    /// Size, time, permission and owner columns next to the names
+    pub details: DetailView,
+    /// Recursive sizes of directories, shown in the size column
+    pub dir_sizer: DirSizer,
+    /// What takes up the space under the root, for the disk usage view
+    pub disk_usage: DiskUsage,
+    /// What the jobs that aren't pastes are for
+    job_purposes: HashMap<JobId, JobPurpose>,
+}
+
+/// What key presses are currently being used for
+#[derive(Debug, Default)]
+pub enum AppMode {
+    #[default]
+    Normal,
+    /// Waiting on y/N before permanently deleting paths
+    ConfirmDelete(Vec<PathBuf>),
+    /// A job is paused on a destination that already exists
+    ResolveConflict { job: JobId, apply_to_all: bool },
+    /// Showing the jobs panel, `j`/`k` pick the job to pause or cancel
+    Jobs { selected: usize },
+    /// Editing the name of `path` in its tree row
+    Rename { editor: LineEditor, path: PathBuf },
+    /// Typing the relative path of a new file or directory to create in `dir`
+    Create {
+        editor: LineEditor,
+        dir: PathBuf,
+        is_dir: bool,
+    },
+    /// Picking how directories are sorted
+    SortMenu,
+    /// Turning detail view columns on and off
+    ColumnsMenu,
+    /// Showing the disk usage view in place of the tree
+    DiskUsage,
+    /// Typing a command at the `:` prompt
+    Command(LineEditor),
+    /// Showing a list of results until any key is pressed, `j`/`k` scroll it
+    Report {
+        title: String,
+        lines: Vec<String>,
+        scroll: u16,
+    },
+}
+
+/// A status or error message for the user
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub text: String,
+    pub is_error: bool,
+    pub time: DateTime<Local>,
+}
+
+/// What a job other than a paste is for, which decides what's done once it finishes
+#[derive(Debug)]
+enum JobPurpose {
+    /// Moving entries to the spots claimed for them in the trash
+    Trash,
+    /// Deleting entries permanently
+    Delete,
+    /// Carrying out the operations being undone, one per item of the transfer
+    Undo(Vec<Operation>),
+    /// Carrying out the operations being redone, one per item of the transfer
+    Redo(Vec<Operation>),
 }
 
 #[derive(Debug, Default)]
 pub enum AppAction {
-    Copying(FileObj),
-    Moving(FileObj),
+    Copying(Vec<PathBuf>),
+    Moving(Vec<PathBuf>),
     #[default]
     None,
 }
@@ -41,12 +152,51 @@ impl App {
     pub fn run(&mut self, terminal: Terminal<CrosstermBackend<io::Stderr>>) -> io::Result<()> {
         let mut tui = Tui::new(terminal);
         tui.enter()?;
-        self.tree = FileTree::new();
+        self.tree = FileTree::new()?;
+        match TreeWatcher::new() {
+            Ok(watcher) => self.watcher = Some(watcher),
+            Err(e) => self.report("Watching for changes", e),
+        }
+        let mut redraw = true;
         // main loop
         while !self.exit {
-            // receives ref to app for its state data
-            tui.draw(self)?;
-            self.handle_events()?;
+            self.report_skipped();
+            if redraw {
+                // receives ref to app for its state data
+                tui.draw(self)?;
+            }
+            if let Some(paths) = self.bulk_rename.take() {
+                tui.suspend()?;
+                let names = bulk_rename::edit_names(&paths);
+                tui.resume()?;
+                self.apply_bulk_rename(&paths, names);
+                redraw = true;
+                continue;
+            }
+            // job and scan progress gets drawn as it goes, otherwise only redraw when something
+            // happened
+            redraw = self.jobs.is_busy() || self.disk_usage.is_scanning();
+            let pending = self.watcher.as_ref().is_some_and(TreeWatcher::is_pending)
+                || self.tree.is_loading()
+                || self.dir_sizer.is_busy()
+                || self.preview_pane.is_loading()
+                || self.journal.is_checking();
+            let timeout = if redraw || pending {
+                BUSY_INTERVAL
+            } else {
+                IDLE_INTERVAL
+            };
+            if event::poll(timeout)? {
+                self.handle_events()?;
+                redraw = true;
+            }
+            self.check_jobs();
+            redraw |= self.check_replay();
+            redraw |= self.tree.poll_loads();
+            redraw |= self.check_dir_sizes();
+            redraw |= self.disk_usage.poll();
+            redraw |= self.preview_pane.poll();
+            redraw |= self.check_watcher();
         }
         tui.exit()?;
         Ok(())
@@ -64,104 +214,1163 @@ impl App {
     }
 
     fn handle_key_event(&mut self, key_event: KeyEvent) {
+        match self.mode {
+            AppMode::ConfirmDelete(_) => return self.handle_confirm_key(key_event),
+            AppMode::ResolveConflict { .. } => return self.handle_conflict_key(key_event),
+            AppMode::Rename { .. } => return self.handle_rename_key(key_event),
+            AppMode::Report { .. } => return self.handle_report_key(key_event),
+            AppMode::Create { .. } => return self.handle_create_key(key_event),
+            AppMode::Jobs { .. } => return self.handle_jobs_key(key_event),
+            AppMode::Command(_) => return self.handle_command_key(key_event),
+            AppMode::SortMenu => return self.handle_sort_key(key_event),
+            AppMode::ColumnsMenu => return self.handle_columns_key(key_event),
+            AppMode::DiskUsage => return self.handle_disk_usage_key(key_event),
+            AppMode::Normal => {}
+        }
+
+        let pending_key = self.pending_key.take();
+        if key_event.modifiers.contains(KeyModifiers::CONTROL) {
+            if key_event.code == KeyCode::Char('r') {
+                self.redo();
+            }
+            return;
+        }
         match key_event.code {
-            KeyCode::Char('q') => self.exit(),
-            KeyCode::Char('j') => self.tree.ft_move(NavDirection::Down),
-            KeyCode::Char('k') => self.tree.ft_move(NavDirection::Up),
-            KeyCode::Char('h') => self.tree.ft_move(NavDirection::OutOfDir),
-            KeyCode::Char('l') => self.tree.ft_move(NavDirection::IntoDir),
-            KeyCode::Char('L') => self.tree.ft_move(NavDirection::ZoomIn),
-            KeyCode::Char(' ') => self
-                .tree
-                .try_toggle_collapse()
-                .expect("File Error TODO move lol"),
-            KeyCode::Char('y') => {
-                self.app_action = AppAction::Copying(self.tree.get_selected_item().clone())
+            KeyCode::Char('q') => {
+                self.quit();
+            }
+            KeyCode::Char('j') => self.move_cursor(NavDirection::Down),
+            KeyCode::Char('k') => self.move_cursor(NavDirection::Up),
+            KeyCode::Char('h') => self.move_cursor(NavDirection::OutOfDir),
+            KeyCode::Char('l') if pending_key == Some('g') => {
+                if let Err(e) = self.tree.jump_to_link_target() {
+                    self.report("Jumping to the link target", e);
+                }
+            }
+            KeyCode::Char('l') => self.move_cursor(NavDirection::IntoDir),
+            KeyCode::Char('L') => self.move_cursor(NavDirection::ZoomIn),
+            KeyCode::Char(' ') => {
+                if let Err(e) = self.tree.try_toggle_collapse() {
+                    self.report("Opening the directory", e);
+                }
+            }
+            KeyCode::Char(':') => {
+                self.mode =
+                    AppMode::Command(LineEditor::new("").history(self.command_history.clone()))
+            }
+            KeyCode::Char('v') => self.tree.toggle_visual(VisualKind::Entries),
+            KeyCode::Char('V') => self.tree.toggle_visual(VisualKind::Lines),
+            KeyCode::Esc => {
+                // an error stays up until dismissed, before anything else
+                if self
+                    .message
+                    .as_ref()
+                    .is_some_and(|message| message.is_error)
+                {
+                    self.message = None;
+                } else {
+                    self.tree.state.clear_selection();
+                }
             }
+            KeyCode::Char('y') => self.app_action = AppAction::Copying(self.tree.take_selection()),
             KeyCode::Char('P') => self.paste_file(true),
             KeyCode::Char('p') => self.paste_file(false),
+            KeyCode::Char('x') => self.app_action = AppAction::Moving(self.tree.take_selection()),
+            KeyCode::Char('d') => {
+                if pending_key == Some('d') {
+                    self.trash_selected();
+                } else {
+                    self.pending_key = Some('d');
+                }
+            }
+            KeyCode::Char('D') => self.mode = AppMode::ConfirmDelete(self.tree.take_selection()),
+            KeyCode::Char('u') => self.undo(),
+            KeyCode::Char('r') => self.start_rename(false),
+            KeyCode::Char('c') => self.pending_key = Some('c'),
+            KeyCode::Char('g') => self.pending_key = Some('g'),
+            KeyCode::Char('w') if pending_key == Some('c') => self.start_rename(true),
+            KeyCode::Char('a') => self.start_create(false),
+            KeyCode::Char('A') => self.start_create(true),
+            KeyCode::Char('J') => self.mode = AppMode::Jobs { selected: 0 },
+            KeyCode::Char('s') => self.mode = AppMode::SortMenu,
+            KeyCode::Char('m') => self.details.toggle(),
+            KeyCode::Char('C') => self.mode = AppMode::ColumnsMenu,
+            KeyCode::Char(']') => self.preview_pane.scroll(1),
+            KeyCode::Char('[') => self.preview_pane.scroll(-1),
+            KeyCode::Char('T') => {
+                let theme = self.preview_pane.next_theme().to_string();
+                self.notify(format!("Preview theme: {}", theme));
+            }
+            KeyCode::Char('z') => {
+                let dir = match self.tree.get_selected_item() {
+                    Ok(item) if matches!(item.object_type, FileObjType::Directory(_)) => {
+                        item.path.clone()
+                    }
+                    Ok(item) => match item.path.parent() {
+                        Some(parent) => parent.to_path_buf(),
+                        None => return,
+                    },
+                    Err(_) => self.tree.root_path().to_path_buf(),
+                };
+                self.measure_dirs(vec![dir]);
+            }
+            KeyCode::Char('Z') => self.measure_dirs(self.tree.dir_rows()),
+            KeyCode::Char('U') => {
+                // keep the last scan if it was of the same directory
+                if self.disk_usage.scanned_dir() != Some(self.tree.root_path()) {
+                    self.disk_usage.scan(self.tree.root_path().to_path_buf());
+                }
+                self.mode = AppMode::DiskUsage;
+            }
+            KeyCode::Char('.') => {
+                if let Err(e) = self.tree.toggle_dotfiles() {
+                    self.report("Listing", e);
+                }
+            }
+            KeyCode::Char('i') => {
+                if let Err(e) = self.tree.toggle_ignored() {
+                    self.report("Listing", e);
+                }
+            }
+            KeyCode::Char('R') => match self.tree.refresh() {
+                Ok(_) => {
+                    self.dir_sizer.clear();
+                    self.notify("Refreshed");
+                }
+                Err(e) => self.report("Refresh", e),
+            },
+            KeyCode::Char('b') => {
+                // the marked entries, or everything next to the cursor
+                self.bulk_rename = Some(if self.tree.state.has_selection() {
+                    self.tree.take_selection()
+                } else {
+                    self.tree.sibling_paths()
+                });
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_confirm_key(&mut self, key_event: KeyEvent) {
+        let paths = match std::mem::take(&mut self.mode) {
+            AppMode::ConfirmDelete(paths) => paths,
+            other => {
+                self.mode = other;
+                return;
+            }
+        };
+        match key_event.code {
+            KeyCode::Char('y') | KeyCode::Char('Y') => match Transfer::delete(paths) {
+                Ok(transfer) => {
+                    let id = self.jobs.submit(transfer);
+                    self.job_purposes.insert(id, JobPurpose::Delete);
+                }
+                Err(e) => self.report("Delete", e),
+            },
+            _ => self.notify("Delete cancelled"),
+        }
+    }
+
+    fn handle_conflict_key(&mut self, key_event: KeyEvent) {
+        let AppMode::ResolveConflict { job, apply_to_all } = self.mode else {
+            return;
+        };
+        let resolution = match key_event.code {
+            KeyCode::Char('o') => Resolution::Overwrite,
+            KeyCode::Char('s') => Resolution::Skip,
+            KeyCode::Char('r') => Resolution::RenameWithSuffix,
+            KeyCode::Char('n') => Resolution::OverwriteIfNewer,
+            KeyCode::Char('a') => {
+                self.mode = AppMode::ResolveConflict {
+                    job,
+                    apply_to_all: !apply_to_all,
+                };
+                return;
+            }
+            KeyCode::Esc => {
+                self.mode = AppMode::Normal;
+                self.jobs.cancel(job);
+                return;
+            }
+            _ => return,
+        };
+        self.mode = AppMode::Normal;
+        self.jobs.resolve(job, resolution, apply_to_all);
+    }
+
+    fn handle_jobs_key(&mut self, key_event: KeyEvent) {
+        let AppMode::Jobs { selected } = &mut self.mode else {
+            return;
+        };
+        let count = self.jobs.jobs().len();
+        let id = self.jobs.jobs().get(*selected).map(|job| job.id);
+        match key_event.code {
+            KeyCode::Char('j') | KeyCode::Down => {
+                *selected = (*selected + 1).min(count.saturating_sub(1))
+            }
+            KeyCode::Char('k') | KeyCode::Up => *selected = selected.saturating_sub(1),
+            KeyCode::Char('p') => {
+                if let Some(id) = id {
+                    self.jobs.toggle_pause(id);
+                }
+            }
+            KeyCode::Char('c') => {
+                if let Some(id) = id {
+                    self.jobs.cancel(id);
+                }
+            }
             KeyCode::Char('x') => {
-                self.app_action = AppAction::Moving(self.tree.get_selected_item().clone())
+                self.jobs.clear_inactive();
+                *selected = 0;
             }
+            KeyCode::Esc | KeyCode::Char('J') | KeyCode::Char('q') => self.mode = AppMode::Normal,
             _ => {}
         }
     }
-    // todo some test stuff from the example page, might be cool to look at
-    // https://ratatui.rs/tutorials/counter-app/basic-app/
 
-    fn paste_file(&mut self, inside: bool) {
-        if let AppAction::None = self.app_action {
+    fn handle_disk_usage_key(&mut self, key_event: KeyEvent) {
+        let pending_key = self.pending_key.take();
+        match key_event.code {
+            KeyCode::Char('j') | KeyCode::Down => self.disk_usage.move_cursor(true),
+            KeyCode::Char('k') | KeyCode::Up => self.disk_usage.move_cursor(false),
+            KeyCode::Char('l') | KeyCode::Right | KeyCode::Enter => self.disk_usage.enter(),
+            KeyCode::Char('h') | KeyCode::Left => self.disk_usage.leave(),
+            KeyCode::Char('d') if pending_key == Some('d') => self.trash_usage_entry(),
+            KeyCode::Char('d') => self.pending_key = Some('d'),
+            KeyCode::Char('r') => self.disk_usage.scan(self.tree.root_path().to_path_buf()),
+            KeyCode::Esc | KeyCode::Char('U') | KeyCode::Char('q') => self.mode = AppMode::Normal,
+            _ => {}
+        }
+    }
+
+    /// Move the entry under the cursor in the disk usage view into the trash
+    fn trash_usage_entry(&mut self) {
+        let Some((path, _)) = self.disk_usage.selected() else {
             return;
+        };
+        self.trash(vec![path]);
+    }
+
+    /// Open the line editor on the selected item's name. The cursor starts before the extension,
+    /// or with `clear_stem` everything but the extension is cleared (like vim's `cw`).
+    fn start_rename(&mut self, clear_stem: bool) {
+        let item = match self.tree.get_selected_item() {
+            Ok(item) => item,
+            Err(e) => return self.report("Rename", e),
+        };
+        let name = item.display_name();
+        // a leading dot is part of the name, not an extension
+        let stem_len = match name.rfind('.') {
+            Some(dot) if dot > 0 && !item.is_dir() => name[..dot].chars().count(),
+            _ => name.chars().count(),
+        };
+        let editor = if clear_stem {
+            let ext: String = name.chars().skip(stem_len).collect();
+            LineEditor::new(&ext).cursor(0)
+        } else {
+            LineEditor::new(&name).cursor(stem_len)
+        };
+        self.mode = AppMode::Rename {
+            editor: editor
+                .history(self.prompt_history.clone())
+                .style(Style::default().fg(Color::Black).bg(Color::White)),
+            path: item.path.clone(),
+        };
+    }
+
+    fn handle_rename_key(&mut self, key_event: KeyEvent) {
+        let AppMode::Rename { editor, path } = &mut self.mode else {
+            return;
+        };
+        match editor.handle_key(key_event) {
+            EditorEvent::None => {}
+            EditorEvent::Cancel => self.mode = AppMode::Normal,
+            EditorEvent::Submit(name) => {
+                let path = path.clone();
+                self.mode = AppMode::Normal;
+                self.prompt_history.retain(|old| *old != name);
+                self.prompt_history.push(name.clone());
+                self.rename(&path, &name);
+            }
         }
+    }
 
-        // setup destination
-        let file = match &self.app_action {
-            AppAction::Copying(file) | AppAction::Moving(file) => file,
-            AppAction::None => {
-                // impossible to reach anyway
+    /// Rename `path` to `name` within the same directory, with `name` written the way
+    /// `display_name` shows it
+    fn rename(&mut self, path: &Path, text: &str) {
+        let name = parse_name(text);
+        if name.is_empty() || name == "." || name == ".." || name.as_encoded_bytes().contains(&b'/')
+        {
+            return self.report("Rename", Error::InvalidName(text.to_string()));
+        }
+        let new_path = path.with_file_name(name);
+        if new_path == path {
+            return;
+        }
+        if fs::symlink_metadata(&new_path).is_ok() {
+            return self.report("Rename", Error::AlreadyExists(new_path));
+        }
+        match fs::rename(path, &new_path).at(path) {
+            Ok(_) => {
+                self.notify(format!("Renamed {:?} to {:?}", path, new_path));
+                self.entry_renamed(path, &new_path);
+                self.journal.record(vec![Operation::Rename {
+                    from: path.to_path_buf(),
+                    to: new_path,
+                }]);
+            }
+            Err(e) => self.report("Rename", e),
+        }
+    }
+
+    /// Prompt for a new entry inside the selected directory, or next to the selected file
+    fn start_create(&mut self, is_dir: bool) {
+        let dir = match self.tree.get_selected_item() {
+            Ok(item) if item.is_dir() => item.path.clone(),
+            Ok(item) => match item.path.parent() {
+                Some(parent) => parent.to_path_buf(),
+                None => return,
+            },
+            // empty directory
+            Err(_) => self.tree.root_path().to_path_buf(),
+        };
+        self.mode = AppMode::Create {
+            editor: LineEditor::new("")
+                .history(self.prompt_history.clone())
+                .style(Style::default().fg(Color::Black).bg(Color::White)),
+            dir,
+            is_dir,
+        };
+    }
+
+    fn handle_create_key(&mut self, key_event: KeyEvent) {
+        let AppMode::Create {
+            editor,
+            dir,
+            is_dir,
+        } = &mut self.mode
+        else {
+            return;
+        };
+        match editor.handle_key(key_event) {
+            EditorEvent::None => {}
+            EditorEvent::Cancel => self.mode = AppMode::Normal,
+            EditorEvent::Submit(input) => {
+                let (dir, is_dir) = (dir.clone(), *is_dir);
+                self.mode = AppMode::Normal;
+                self.prompt_history.retain(|old| *old != input);
+                self.prompt_history.push(input.clone());
+                self.create(&dir, &input, is_dir);
+            }
+        }
+    }
+
+    /// Create `input` relative to `dir`, along with any directories on the way there. A trailing
+    /// `/` makes a directory too.
+    fn create(&mut self, dir: &Path, input: &str, is_dir: bool) {
+        let is_dir = is_dir || input.ends_with('/');
+        let rel = Path::new(input.trim_end_matches('/'));
+        let components: Vec<_> = rel.components().collect();
+        if components.is_empty()
+            || components
+                .iter()
+                .any(|component| !matches!(component, Component::Normal(_)))
+        {
+            return self.report("Create", Error::InvalidName(input.to_string()));
+        }
+        let path = dir.join(rel);
+        if fs::symlink_metadata(&path).is_ok() {
+            return self.report("Create", Error::AlreadyExists(path));
+        }
+
+        let mut ops = Vec::new();
+        let mut ancestor = dir.to_path_buf();
+        let mut result = Ok(());
+        for component in &components[..components.len() - 1] {
+            ancestor.push(component);
+            if ancestor.is_dir() {
+                continue;
+            }
+            result = fs::create_dir(&ancestor).at(&ancestor);
+            if result.is_err() {
+                break;
+            }
+            ops.push(Operation::Create {
+                path: ancestor.clone(),
+                is_dir: true,
+            });
+        }
+        if result.is_ok() {
+            result = if is_dir {
+                fs::create_dir(&path)
+            } else {
+                File::create_new(&path).map(|_| ())
+            }
+            .at(&path);
+        }
+        let shown = match result {
+            Ok(_) => {
+                self.notify(format!("Created {:?}", path));
+                ops.push(Operation::Create {
+                    path: path.clone(),
+                    is_dir,
+                });
+                self.tree
+                    .reveal(&path)
+                    .map(|_| self.tree.select_path(&path))
+            }
+            Err(e) => {
+                self.report("Create", e);
+                match ops.first() {
+                    Some(Operation::Create { path, .. }) => self.tree.reveal(path).map(|_| ()),
+                    _ => Ok(()),
+                }
+            }
+        };
+        if let Err(e) = shown {
+            self.report("Showing the new entry", e);
+        }
+        self.journal.record(ops);
+    }
+
+    fn handle_report_key(&mut self, key_event: KeyEvent) {
+        let AppMode::Report { scroll, .. } = &mut self.mode else {
+            return;
+        };
+        match key_event.code {
+            KeyCode::Char('j') | KeyCode::Down => *scroll = scroll.saturating_add(1),
+            KeyCode::Char('k') | KeyCode::Up => *scroll = scroll.saturating_sub(1),
+            _ => self.mode = AppMode::Normal,
+        }
+    }
+
+    /// Apply names edited in the external editor and report what happened to each file
+    fn apply_bulk_rename(&mut self, paths: &[PathBuf], names: Result<Vec<String>>) {
+        let names = match names {
+            Ok(names) => names,
+            Err(e) => return self.report("Bulk rename", e),
+        };
+        let changes = match bulk_rename::plan(paths, &names) {
+            Ok(changes) => changes,
+            Err(problems) => {
+                self.mode = AppMode::Report {
+                    title: "Bulk rename cancelled".to_string(),
+                    lines: problems,
+                    scroll: 0,
+                };
                 return;
             }
         };
+        if changes.is_empty() {
+            self.notify("Bulk rename: nothing changed");
+            return;
+        }
 
-        let item = self.tree.get_selected_item();
-        let mut dest = if inside && matches!(item.object_type, FileObjType::Directory(_)) {
-            // try to paste inside the directory currently selected
-            item.path.to_path_buf()
+        let results = bulk_rename::apply(&changes);
+        let mut renames = Vec::new();
+        let mut summary = Vec::new();
+        let mut failed = 0;
+        for (renamed, (_, to)) in results.into_iter().zip(&changes) {
+            if renamed.at != renamed.from {
+                renames.push((renamed.from.clone(), renamed.at.clone()));
+            }
+            match renamed.error {
+                None => summary.push(format!("{:?} -> {:?}", renamed.from, to)),
+                Some(e) => {
+                    failed += 1;
+                    let mut line = format!("FAILED {:?} -> {:?}: {}", renamed.from, to, e);
+                    if renamed.at != renamed.from {
+                        line.push_str(&format!(", left at {:?}", renamed.at));
+                    }
+                    summary.push(line);
+                }
+            }
+        }
+        self.entries_renamed(&renames);
+        if !renames.is_empty() {
+            self.journal.record(vec![Operation::BulkRename { renames }]);
+        }
+        let message = format!(
+            "Bulk rename: {} renamed, {} failed",
+            summary.len() - failed,
+            failed
+        );
+        if failed > 0 {
+            self.error(message);
+        } else {
+            self.notify(message);
+        }
+        self.mode = AppMode::Report {
+            title: "Bulk rename".to_string(),
+            lines: summary,
+            scroll: 0,
+        };
+    }
+
+    /// Measure the total size of everything under `dirs`, showing the size column for it
+    fn measure_dirs(&mut self, dirs: Vec<PathBuf>) {
+        if !self.details.shown {
+            self.details.toggle();
+        }
+        for dir in dirs {
+            self.dir_sizer.measure(dir);
+        }
+    }
+
+    /// Take in measured directory sizes, and measure new directories when that is done
+    /// automatically. Returns whether any sizes changed.
+    fn check_dir_sizes(&mut self) -> bool {
+        if self.dir_sizer.auto && self.details.shown {
+            for dir in self.tree.dir_rows() {
+                self.dir_sizer.measure_once(dir);
+            }
+        }
+        self.dir_sizer.poll()
+    }
+
+    /// Apply changes made on disk outside the app to the tree, and watch any directory that was
+    /// opened since. Returns whether anything changed.
+    fn check_watcher(&mut self) -> bool {
+        let Some(changes) = self.watcher.as_mut().and_then(TreeWatcher::poll) else {
+            self.sync_watcher();
+            return false;
+        };
+        for (from, to) in &changes.renames {
+            // renames done by the app itself are already in the tree
+            if self.tree.nodes.find(from).is_some() {
+                self.tree.remove_entry(to);
+                self.entry_renamed(from, to);
+            }
+        }
+        if changes.everything || (changes.ignore_rules && self.tree.filter.hide_ignored) {
+            if let Err(e) = self.tree.refresh() {
+                self.report("Refresh", e);
+            }
         } else {
+            if changes.ignore_rules {
+                self.tree.filter.clear_cache();
+            }
+            for dir in changes.dirs {
+                if let Err(e) = self.tree.reconcile_dir(&dir) {
+                    self.report("Listing", e);
+                }
+            }
+        }
+        self.sync_watcher();
+        true
+    }
+
+    /// Watch the directories currently shown in the tree
+    fn sync_watcher(&mut self) {
+        let Some(watcher) = &mut self.watcher else {
+            return;
+        };
+        if let Err(e) = watcher.sync(self.tree.shown_dirs()) {
+            self.report("Watching for changes", e);
+        }
+    }
+
+    /// Update the tree and any pending action after `from` was renamed to `to`
+    fn entry_renamed(&mut self, from: &Path, to: &Path) {
+        self.entries_renamed(&[(from.to_path_buf(), to.to_path_buf())]);
+    }
+
+    /// Update the tree and any pending action after `renames` were done together
+    fn entries_renamed(&mut self, renames: &[(PathBuf, PathBuf)]) {
+        self.tree.rename_entries(renames);
+        if let AppAction::Copying(paths) | AppAction::Moving(paths) = &mut self.app_action {
+            for pending in paths.iter_mut() {
+                if let Some(new_path) = rebase_paths(pending, renames) {
+                    *pending = new_path;
+                }
+            }
+        }
+    }
+
+    /// Move the selected items into the trash
+    fn trash_selected(&mut self) {
+        let paths = self.tree.take_selection();
+        self.trash(paths);
+    }
+
+    /// Start a job moving `paths` into the trash
+    fn trash(&mut self, paths: Vec<PathBuf>) {
+        let mut pairs = Vec::new();
+        for path in paths {
+            match claim_slot(&path) {
+                Ok(trashed) => pairs.push((path, trashed)),
+                Err(e) => self.report("Trash", e),
+            }
+        }
+        if pairs.is_empty() {
+            return;
+        }
+        let slots: Vec<PathBuf> = pairs.iter().map(|(_, trashed)| trashed.clone()).collect();
+        match Transfer::batch(TransferKind::Move, pairs) {
+            Ok(transfer) => {
+                let id = self.jobs.submit(transfer);
+                self.job_purposes.insert(id, JobPurpose::Trash);
+            }
+            Err(e) => {
+                for trashed in slots {
+                    let _ = remove_info(&trashed);
+                }
+                self.report("Trash", e);
+            }
+        }
+    }
+
+    /// Take in a finished trash job: what made it into the trash is gone from the tree and can
+    /// be restored with undo, the rest gives back the spot claimed for it
+    fn finish_trash(&mut self, transfer: &Transfer, result: Result<()>, cancelled: bool) {
+        let completed = completed_items(transfer, result.is_ok() && !cancelled);
+        let mut trashed = Vec::new();
+        let mut ops = Vec::new();
+        for (item, done) in transfer.items.iter().zip(completed) {
+            if !done {
+                let _ = remove_info(&item.dest);
+                continue;
+            }
+            self.entry_removed(&item.src);
+            self.disk_usage.remove(&item.src);
+            trashed.push(item.src.clone());
+            if !item.clobbered {
+                ops.push(Operation::Trash {
+                    path: item.src.clone(),
+                    trashed: item.dest.clone(),
+                });
+            }
+        }
+        self.journal.record(ops);
+        self.report_batch("Trash", "Trashed", &trashed, job_errors(result, cancelled));
+    }
+
+    /// Take in a finished delete job, dropping whatever is gone from the tree
+    fn finish_delete(&mut self, transfer: &Transfer, result: Result<()>, cancelled: bool) {
+        let completed = completed_items(transfer, result.is_ok() && !cancelled);
+        let mut deleted = Vec::new();
+        for (item, done) in transfer.items.iter().zip(completed) {
+            if done {
+                self.entry_removed(&item.src);
+                self.disk_usage.remove(&item.src);
+                deleted.push(item.src.clone());
+            }
+        }
+        self.report_batch("Delete", "Deleted", &deleted, job_errors(result, cancelled));
+    }
+
+    /// Update the tree and any pending action after `path` was removed from disk
+    fn entry_removed(&mut self, path: &Path) {
+        self.tree.remove_entry(path);
+        if let AppAction::Copying(paths) | AppAction::Moving(paths) = &mut self.app_action {
+            paths.retain(|pending| !pending.starts_with(path));
+            if paths.is_empty() {
+                self.app_action = AppAction::None;
+            }
+        }
+    }
+    // todo some test stuff from the example page, might be cool to look at
+    // https://ratatui.rs/tutorials/counter-app/basic-app/
+
+    fn paste_file(&mut self, inside: bool) {
+        // setup destination
+        let (kind, paths) = match std::mem::take(&mut self.app_action) {
+            AppAction::Copying(paths) => (TransferKind::Copy, paths),
+            AppAction::Moving(paths) => (TransferKind::Move, paths),
+            AppAction::None => return,
+        };
+
+        let dest_dir = match self.tree.get_selected_item() {
+            // try to paste inside the directory currently selected
+            Ok(item) if inside && item.is_dir() => item.path.to_path_buf(),
             // else: either not a dir, or we don't want to paste inside, paste here
-            item.path.parent().unwrap().to_path_buf()
-        };
-        dest.push(&file.name);
-
-        // final action
-        match &self.app_action {
-            AppAction::Copying(file) => {
-                let copy_result = fs::copy(file.path.clone(), &dest);
-                match copy_result {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("{}", e)
-                    }
+            Ok(item) => match item.path.parent() {
+                Some(parent) => parent.to_path_buf(),
+                None => return,
+            },
+            // empty directory
+            Err(_) => self.tree.root_path().to_path_buf(),
+        };
+
+        let mut pairs = Vec::new();
+        for path in paths {
+            let Some(name) = path.file_name() else {
+                continue;
+            };
+            let mut dest = dest_dir.join(name);
+            if dest == path {
+                if kind == TransferKind::Copy {
+                    // pasting a copy right next to the original makes a duplicate
+                    dest = free_name(&dest, path.is_dir());
+                } else {
+                    // already there
+                    continue;
+                }
+            }
+            pairs.push((path, dest));
+        }
+        if pairs.is_empty() {
+            self.notify("Nothing to move, already there");
+            return;
+        }
+
+        match Transfer::batch(kind, pairs) {
+            Ok(transfer) => {
+                self.jobs.submit(transfer);
+            }
+            Err(e) => self.report("Paste", e),
+        }
+    }
+
+    /// Pick up jobs that are no longer running, and ask about the next conflict a job is stuck
+    /// on
+    fn check_jobs(&mut self) {
+        for finished in self.jobs.poll_finished() {
+            let FinishedJob {
+                id,
+                transfer,
+                result,
+                cancelled,
+            } = finished;
+            match self.job_purposes.remove(&id) {
+                Some(JobPurpose::Trash) => {
+                    self.finish_trash(&transfer, result, cancelled);
+                    continue;
+                }
+                Some(JobPurpose::Delete) => {
+                    self.finish_delete(&transfer, result, cancelled);
+                    continue;
                 }
-                self.notify = format!("Pasted {:?} to {:?}", file.path, dest);
+                Some(JobPurpose::Undo(ops)) => {
+                    self.finish_replay(&transfer, result, cancelled, ops, true);
+                    continue;
+                }
+                Some(JobPurpose::Redo(ops)) => {
+                    self.finish_replay(&transfer, result, cancelled, ops, false);
+                    continue;
+                }
+                None => {}
             }
-            AppAction::Moving(file) => {
-                let move_result = fs::rename(file.path.clone(), &dest);
-                match move_result {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("{}", e)
+            match result {
+                Ok(_) if !cancelled => self.finish_transfer(transfer),
+                result => {
+                    match result {
+                        Err(e) => self.report(&format!("Paste of {}", transfer.describe()), e),
+                        Ok(_) => self.notify(format!("Cancelled paste of {}", transfer.describe())),
                     }
+                    self.transfer_interrupted(&transfer);
                 }
-                self.notify = format!("Moved {:?} to {:?}", file.path, dest);
             }
-            AppAction::None => {}
         }
-        self.app_action = AppAction::None;
+        if matches!(self.mode, AppMode::Normal) {
+            if let Some((job, _)) = self.jobs.next_conflict() {
+                self.mode = AppMode::ResolveConflict {
+                    job,
+                    apply_to_all: false,
+                };
+            }
+        }
+    }
+
+    /// Show whatever a transfer got done before it stopped. None of it is recorded for undo as
+    /// it may be incomplete.
+    fn transfer_interrupted(&mut self, transfer: &Transfer) {
+        for item in transfer.items.iter().filter(|item| !item.skipped) {
+            if transfer.kind == TransferKind::Move && fs::symlink_metadata(&item.src).is_err() {
+                self.entry_removed(&item.src);
+            }
+            if fs::symlink_metadata(&item.dest).is_ok() {
+                self.tree.insert_entry(&item.dest);
+            }
+        }
+    }
+
+    fn finish_transfer(&mut self, transfer: Transfer) {
+        let done: Vec<_> = transfer.items.iter().filter(|item| !item.skipped).collect();
+        let verb = match transfer.kind {
+            TransferKind::Copy => "Pasted",
+            TransferKind::Move => "Moved",
+            TransferKind::Delete => "Deleted",
+        };
+        let mut message = match done.as_slice() {
+            [] => format!("Skipped {}", transfer.describe()),
+            [item] => format!("{} {:?} to {:?}", verb, item.src, item.dest),
+            items => format!("{} {} items", verb, items.len()),
+        };
+        if done.iter().any(|item| item.clobbered) {
+            message.push_str(" (replaced existing files, this can't be undone)");
+        }
+        self.notify(message);
+
+        for item in &done {
+            if transfer.kind == TransferKind::Move {
+                self.entry_removed(&item.src);
+            }
+            self.tree.insert_entry(&item.dest);
+        }
+
+        let ops = done
+            .into_iter()
+            .filter(|item| !item.clobbered)
+            .filter_map(|item| {
+                let (src, dest) = (item.src.clone(), item.dest.clone());
+                match transfer.kind {
+                    TransferKind::Copy => Some(Operation::Copy { src, dest }),
+                    TransferKind::Move => Some(Operation::Move { src, dest }),
+                    // nothing to bring it back from
+                    TransferKind::Delete => None,
+                }
+            })
+            .collect();
+        self.journal.record(ops);
+    }
+
+    fn undo(&mut self) {
+        if let Err(e) = self.journal.undo() {
+            self.replayed("undo", "Undid", &[], Err(e));
+        }
+    }
+
+    fn redo(&mut self) {
+        if let Err(e) = self.journal.redo() {
+            self.replayed("redo", "Redid", &[], Err(e));
+        }
+    }
+
+    /// Carry on with an undo or redo once the journal has checked nothing changed. Returns
+    /// whether one went ahead.
+    fn check_replay(&mut self) -> bool {
+        let Some(replay) = self.journal.poll() else {
+            return false;
+        };
+        for op in &replay.done {
+            if replay.undo {
+                self.operation_reverted(op);
+            } else {
+                self.operation_redone(op);
+            }
+        }
+        self.start_replay(replay);
+        true
+    }
+
+    /// Submit the transfers an undo or redo still needs, and report on what's done so far. The
+    /// transfers are reported on as they finish.
+    fn start_replay(&mut self, replay: Replay) {
+        let Replay {
+            undo,
+            done,
+            result,
+            transfers,
+        } = replay;
+        let waiting = !transfers.is_empty();
+        for (transfer, ops) in transfers {
+            let id = self.jobs.submit(transfer);
+            let purpose = if undo {
+                JobPurpose::Undo(ops)
+            } else {
+                JobPurpose::Redo(ops)
+            };
+            self.job_purposes.insert(id, purpose);
+        }
+        if !done.is_empty() || result.is_err() || !waiting {
+            let (action, verb) = if undo {
+                ("undo", "Undid")
+            } else {
+                ("redo", "Redid")
+            };
+            self.replayed(action, verb, &done, result);
+        }
+    }
+
+    /// Take in a finished undo or redo job, handing back to the journal which of `ops` it
+    /// carried out
+    fn finish_replay(
+        &mut self,
+        transfer: &Transfer,
+        result: Result<()>,
+        cancelled: bool,
+        ops: Vec<Operation>,
+        undo: bool,
+    ) {
+        let completed = completed_items(transfer, result.is_ok() && !cancelled);
+        let mut done = Vec::new();
+        let mut remaining = Vec::new();
+        for ((item, op), completed) in transfer.items.iter().zip(ops).zip(completed) {
+            match (&op, undo, completed) {
+                // restored, so its entry in the trash is gone
+                (Operation::Trash { trashed, .. }, true, true)
+                // didn't make it into the spot claimed for it
+                | (Operation::Trash { trashed, .. }, false, false) => {
+                    let _ = remove_info(trashed);
+                }
+                _ => {}
+            }
+            if !completed {
+                remaining.push(op);
+                continue;
+            }
+            if undo {
+                self.operation_reverted(&op);
+            } else {
+                self.operation_redone(&op);
+            }
+            // what it overwrote can't be brought back
+            if !item.clobbered {
+                done.push(op);
+            }
+        }
+        let result = match result {
+            Ok(_) if cancelled => Err(Error::Other("cancelled".to_string())),
+            result => result,
+        };
+        let (action, verb) = if undo {
+            self.journal.finish_undo(done.clone(), remaining);
+            ("undo", "Undid")
+        } else {
+            self.journal.finish_redo(done.clone(), remaining);
+            ("redo", "Redid")
+        };
+        self.replayed(action, verb, &done, result);
+    }
+
+    /// Bring the tree in line with the filesystem after redoing `op`
+    fn operation_redone(&mut self, op: &Operation) {
+        match op {
+            Operation::Rename { from, to } => self.entry_renamed(from, to),
+            Operation::BulkRename { renames } => self.entries_renamed(renames),
+            Operation::Move { src, dest } => {
+                self.entry_removed(src);
+                self.tree.insert_entry(dest);
+            }
+            Operation::Trash { path, .. } => self.entry_removed(path),
+            Operation::Copy { dest: path, .. } | Operation::Create { path, .. } => {
+                self.tree.insert_entry(path);
+            }
+        }
+    }
+
+    /// Bring the tree in line with the filesystem after undoing `op`
+    fn operation_reverted(&mut self, op: &Operation) {
+        match op {
+            Operation::Copy { dest: path, .. } | Operation::Create { path, .. } => {
+                self.entry_removed(path)
+            }
+            Operation::Move { src, dest } => {
+                self.entry_removed(dest);
+                self.tree.insert_entry(src);
+            }
+            Operation::Rename { from, to } => self.entry_renamed(to, from),
+            Operation::BulkRename { renames } => {
+                let back: Vec<_> = renames
+                    .iter()
+                    .map(|(from, to)| (to.clone(), from.clone()))
+                    .collect();
+                self.entries_renamed(&back);
+            }
+            Operation::Trash { path, .. } => {
+                self.tree.insert_entry(path);
+            }
+        }
     }
 
     fn exit(&mut self) {
         self.exit = true;
     }
 
+    /// Exit, unless that would cut a copy or move short
+    fn quit(&mut self) {
+        if self.jobs.is_busy() {
+            self.error("Jobs are still running, cancel them from the jobs panel (J) first");
+        } else {
+            self.exit();
+        }
+    }
+
+    fn move_cursor(&mut self, direction: NavDirection) {
+        if let Err(e) = self.tree.ft_move(direction) {
+            self.report("Opening the directory", e);
+        }
+    }
+
+    /// Pick a sort key, which closes the menu, or toggle directories first / reverse
+    fn handle_sort_key(&mut self, key_event: KeyEvent) {
+        let mut sort = self.tree.sort;
+        match key_event.code {
+            KeyCode::Char('d') => sort.dirs_first = !sort.dirs_first,
+            KeyCode::Char('r') => sort.reverse = !sort.reverse,
+            KeyCode::Char(c) => match SortKey::ALL.iter().find(|key| key.shortcut() == c) {
+                Some(key) => {
+                    sort.key = *key;
+                    self.mode = AppMode::Normal;
+                }
+                None => return,
+            },
+            KeyCode::Esc => {
+                self.mode = AppMode::Normal;
+                return;
+            }
+            _ => return,
+        }
+        self.tree.set_sort(sort);
+        self.notify(format!("Sorted by {}", sort.describe()));
+    }
+
+    fn handle_columns_key(&mut self, key_event: KeyEvent) {
+        match key_event.code {
+            KeyCode::Char('a') => {
+                self.dir_sizer.auto = !self.dir_sizer.auto;
+                if self.dir_sizer.auto && !self.details.shown {
+                    self.details.toggle();
+                }
+            }
+            KeyCode::Char(c) => {
+                if let Some(column) = Column::ALL.iter().find(|column| column.shortcut() == c) {
+                    self.details.toggle_column(*column);
+                    // picking columns is pointless if they aren't shown
+                    if !self.details.shown {
+                        self.details.toggle();
+                    }
+                }
+            }
+            KeyCode::Esc | KeyCode::Enter => self.mode = AppMode::Normal,
+            _ => {}
+        }
+    }
+
+    fn handle_command_key(&mut self, key_event: KeyEvent) {
+        let AppMode::Command(editor) = &mut self.mode else {
+            return;
+        };
+        match editor.handle_key(key_event) {
+            EditorEvent::None => {}
+            EditorEvent::Cancel => self.mode = AppMode::Normal,
+            EditorEvent::Submit(command) => {
+                self.mode = AppMode::Normal;
+                let command = command.trim().to_string();
+                if command.is_empty() {
+                    return;
+                }
+                self.command_history.retain(|old| *old != command);
+                self.command_history.push(command.clone());
+                self.run_command(&command);
+            }
+        }
+    }
+
+    /// Run a command entered at the `:` prompt
+    fn run_command(&mut self, command: &str) {
+        match command {
+            "messages" | "mes" => {
+                let lines: Vec<String> = self
+                    .messages
+                    .iter()
+                    .map(|message| {
+                        format!(
+                            "{} {}{}",
+                            message.time.format("%H:%M:%S"),
+                            if message.is_error { "E " } else { "" },
+                            message.text
+                        )
+                    })
+                    .collect();
+                self.mode = AppMode::Report {
+                    title: "Messages".to_string(),
+                    // start at the newest
+                    scroll: lines.len().saturating_sub(18) as u16,
+                    lines,
+                };
+            }
+            "q" | "quit" => self.quit(),
+            _ => self.error(format!("Not a command: {}", command)),
+        }
+    }
+
+    /// Show a status message
+    fn notify(&mut self, text: impl Into<String>) {
+        self.push_message(text.into(), false);
+    }
+
+    /// Show an error message, which stays up until dismissed or replaced
+    fn error(&mut self, text: impl Into<String>) {
+        let text = text.into();
+        error!("{}", text);
+        self.push_message(text, true);
+    }
+
+    /// Show an error from `action`, e.g. "Rename failed: ..."
+    fn report(&mut self, action: &str, error: Error) {
+        self.error(format!("{} failed: {}", action, error));
+    }
+
+    fn push_message(&mut self, text: String, is_error: bool) {
+        let message = Message {
+            text,
+            is_error,
+            time: Local::now(),
+        };
+        if self.messages.len() >= MESSAGE_HISTORY {
+            self.messages.remove(0);
+        }
+        self.messages.push(message.clone());
+        self.message = Some(message);
+    }
+
+    /// Report entries the tree had to leave out while listing directories
+    fn report_skipped(&mut self) {
+        for error in std::mem::take(&mut self.tree.skipped) {
+            self.error(error.to_string());
+        }
+    }
+
+    /// Show the outcome of acting on several paths: which ones `verb` applied to, and the errors
+    /// from the rest
+    fn report_batch(&mut self, action: &str, verb: &str, done: &[PathBuf], errors: Vec<Error>) {
+        let failed = errors.len();
+        for error in errors {
+            self.report(action, error);
+        }
+        match (done, failed) {
+            ([], _) => {}
+            (done, 0) => self.notify(format!("{} {}", verb, describe_paths(done))),
+            (done, failed) => self.error(format!(
+                "{} {}, {} failed",
+                verb,
+                describe_paths(done),
+                failed
+            )),
+        }
+    }
+
+    /// Show the outcome of undoing or redoing a batch of operations
+    fn replayed(&mut self, action: &str, verb: &str, ops: &[Operation], result: Result<()>) {
+        match (result, ops) {
+            (Err(e), []) => self.error(format!("Can't {}: {}", action, e)),
+            (Err(e), ops) => self.error(format!(
+                "{} {} operations, then failed: {}",
+                verb,
+                ops.len(),
+                e
+            )),
+            (Ok(_), [op]) => self.notify(format!("{} {}", verb, op.describe())),
+            (Ok(_), ops) => self.notify(format!("{} {} operations", verb, ops.len())),
+        }
+    }
+
     fn set_preview_contents(&mut self) {
         if self.tree.state.index_changed() {
             // generate new contents
-            let current_item = self.tree.get_selected_item();
-            self.preview_pane.preview_contents = match fs::read_to_string(current_item.path.clone())
-            {
-                Ok(text) => {
-                    // error!("Text generated: {}", text);
-                    self.preview_pane.is_available = true;
-                    str::replace(&text, "\t", "    ")
-                }
-                Err(_) => {
-                    // assuming it's just a dir, we will skip it
-                    // error!("Text not generated!");
-                    self.preview_pane.is_available = false;
-                    "".to_string()
-                }
+            let Ok(current_item) = self.tree.get_selected_item() else {
+                self.preview_pane.clear();
+                return;
+            };
+            if current_item.is_dir() {
+                self.preview_pane.clear();
+                return;
             }
+            // read on the preview worker, this only asks for it
+            let path = current_item.path.clone();
+            self.preview_pane.open(&path);
         }
     }
 }
@@ -173,26 +1382,394 @@ impl Widget for &mut App {
             // Layout::vertical([Constraint::Min(2), Constraint::Percentage(100)]).split(area);
             Layout::vertical([Constraint::Length(3), Constraint::Fill(1), Constraint::Length(1)]).split(area);
 
-        let status = StatusBar::new();
+        let status = StatusBar::new().filters(self.tree.filter.describe());
         status.render(main_chunks[0], buf);
-        self.notify = match &self.app_action {
-            AppAction::Copying(file) => format!("Copying {:?}", file.path),
-            AppAction::Moving(file) => format!("Moving {:?}", file.path),
-            AppAction::None => "".to_string(),
-        };
-        let notify_text = Text::from(self.notify.clone());
-        notify_text.render(main_chunks[2], buf);
+        self.render_status_line(main_chunks[2], buf);
+
+        if let AppMode::DiskUsage = self.mode {
+            return self.render_disk_usage(main_chunks[1], buf);
+        }
 
         let content_chunks =
             Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
                 .split(main_chunks[1]);
         // creating my custom widget and call its render method
-        let filetree_widget = FileTreeWidget::new(self.tree.linear_list.clone())
+        let filetree_widget = FileTreeWidget::new(&self.tree.nodes)
+            .details(&self.details)
+            .dir_sizes(&self.dir_sizer)
             .style(Style::default().fg(Color::Green))
-            .block(Block::bordered().title(format!("{}", self.tree.root_path.clone().display(),)));
+            .block(Block::bordered().title(format!("{}", self.tree.root_path().display(),)));
         filetree_widget.render(content_chunks[0], buf, &mut self.tree.state);
+        if let AppMode::Rename { editor, .. } = &self.mode {
+            self.render_rename_editor(editor, content_chunks[0], buf);
+        }
 
         self.set_preview_contents();
         self.preview_pane.render(content_chunks[1], buf);
+
+        if let AppMode::ConfirmDelete(paths) = &self.mode {
+            let popup = popup_area(main_chunks[1], 60, 5);
+            Clear.render(popup, buf);
+            Paragraph::new(format!(
+                "Permanently delete {}? (y/N)",
+                describe_paths(paths)
+            ))
+            .wrap(Wrap { trim: true })
+            .block(Block::bordered().title("Delete"))
+            .style(Style::default().fg(Color::Red))
+            .render(popup, buf);
+        }
+
+        if let AppMode::Create {
+            editor,
+            dir,
+            is_dir,
+        } = &self.mode
+        {
+            let popup = popup_area(main_chunks[1], 60, 3);
+            Clear.render(popup, buf);
+            let kind = if *is_dir { "directory" } else { "file" };
+            let block = Block::bordered().title(format!("New {} in {}/", kind, dir.display()));
+            let inner = block.inner(popup);
+            block.render(popup, buf);
+            editor.render(inner, buf);
+        }
+
+        if let AppMode::Report {
+            title,
+            lines,
+            scroll,
+        } = &self.mode
+        {
+            let popup = popup_area(main_chunks[1], main_chunks[1].width * 4 / 5, 20);
+            Clear.render(popup, buf);
+            Paragraph::new(lines.join("\n"))
+                .scroll((*scroll, 0))
+                .block(Block::bordered().title(title.as_str()))
+                .render(popup, buf);
+        }
+
+        if let AppMode::SortMenu = self.mode {
+            self.render_sort_menu(main_chunks[1], buf);
+        }
+
+        if let AppMode::ColumnsMenu = self.mode {
+            self.render_columns_menu(main_chunks[1], buf);
+        }
+
+        if let AppMode::Jobs { selected } = self.mode {
+            self.render_jobs_panel(selected, main_chunks[1], buf);
+        }
+
+        if let AppMode::ResolveConflict { job, apply_to_all } = &self.mode {
+            let popup = popup_area(main_chunks[1], 60, 7);
+            Clear.render(popup, buf);
+            let dest = self
+                .jobs
+                .jobs()
+                .iter()
+                .find(|other| other.id == *job)
+                .and_then(|job| job.conflict.clone())
+                .unwrap_or_default();
+            Paragraph::new(format!(
+                "{:?} already exists\n[o]verwrite  [s]kip  [r]ename  overwrite if [n]ewer\n[a]pply to all: {}  [Esc] cancel",
+                dest,
+                if *apply_to_all { "on" } else { "off" }
+            ))
+            .wrap(Wrap { trim: true })
+            .block(Block::bordered().title("Conflict"))
+            .style(Style::default().fg(Color::Yellow))
+            .render(popup, buf);
+        }
+    }
+}
+
+impl App {
+    /// Bottom line: the command prompt, an error, job progress, the pending paste or the last
+    /// message, whichever comes first
+    fn render_status_line(&self, area: Rect, buf: &mut Buffer) {
+        if let AppMode::Command(editor) = &self.mode {
+            Text::from(":").render(area, buf);
+            let area = Rect {
+                x: area.x + 1,
+                width: area.width.saturating_sub(1),
+                ..area
+            };
+            return editor.render(area, buf);
+        }
+        if let Some(message) = self.message.as_ref().filter(|message| message.is_error) {
+            return Text::from(format!("{} (Esc to dismiss)", message.text))
+                .style(Style::default().fg(Color::Red))
+                .render(area, buf);
+        }
+        let active: Vec<String> = self
+            .jobs
+            .jobs()
+            .iter()
+            .filter(|job| job.is_active())
+            .map(|job| describe_job(job))
+            .collect();
+        let line = match (active.as_slice(), &self.app_action) {
+            ([first], _) => first.clone(),
+            ([first, ..], _) => format!("{} (+{} more, J to show jobs)", first, active.len() - 1),
+            ([], AppAction::Copying(paths)) => format!("Copying {}", describe_paths(paths)),
+            ([], AppAction::Moving(paths)) => format!("Moving {}", describe_paths(paths)),
+            ([], AppAction::None) => self
+                .message
+                .as_ref()
+                .map(|message| message.text.clone())
+                .unwrap_or_default(),
+        };
+        Text::from(line).render(area, buf);
+    }
+
+    /// Sort keys with the current one marked, and the toggles
+    fn render_sort_menu(&self, area: Rect, buf: &mut Buffer) {
+        let sort = self.tree.sort;
+        let mark = |on: bool| if on { "*" } else { " " };
+        let mut lines: Vec<String> = SortKey::ALL
+            .iter()
+            .map(|key| {
+                format!(
+                    "{} {}  {}",
+                    mark(*key == sort.key),
+                    key.shortcut(),
+                    key.label()
+                )
+            })
+            .collect();
+        lines.push(String::new());
+        lines.push(format!("{} d  directories first", mark(sort.dirs_first)));
+        lines.push(format!("{} r  reverse", mark(sort.reverse)));
+        let popup = popup_area(area, 36, lines.len() as u16 + 2);
+        Clear.render(popup, buf);
+        Paragraph::new(lines.join("\n"))
+            .block(Block::bordered().title("Sort by"))
+            .render(popup, buf);
+    }
+
+    fn render_columns_menu(&self, area: Rect, buf: &mut Buffer) {
+        let mut lines: Vec<String> = Column::ALL
+            .iter()
+            .map(|column| {
+                let mark = if self.details.columns.contains(column) {
+                    "*"
+                } else {
+                    " "
+                };
+                format!("{} {}  {}", mark, column.shortcut(), column.label())
+            })
+            .collect();
+        lines.push(String::new());
+        lines.push(format!(
+            "{} a  measure directory sizes",
+            if self.dir_sizer.auto { "*" } else { " " }
+        ));
+        let popup = popup_area(area, 36, lines.len() as u16 + 2);
+        Clear.render(popup, buf);
+        Paragraph::new(lines.join("\n"))
+            .block(Block::bordered().title("Columns"))
+            .render(popup, buf);
+    }
+
+    /// List every job with a progress bar, the selected one highlighted
+    fn render_jobs_panel(&self, selected: usize, area: Rect, buf: &mut Buffer) {
+        let jobs = self.jobs.jobs();
+        let mut lines: Vec<Line> = jobs
+            .iter()
+            .enumerate()
+            .map(|(i, job)| {
+                let line = Line::from(format!(
+                    "#{} {} {}",
+                    job.id,
+                    progress_bar(job.progress.bytes_done, job.progress.bytes_total, 20),
+                    describe_job(job)
+                ));
+                if i == selected {
+                    line.reversed()
+                } else {
+                    line
+                }
+            })
+            .collect();
+        if lines.is_empty() {
+            lines.push(Line::from("No jobs"));
+        }
+        let popup = popup_area(area, area.width * 4 / 5, lines.len() as u16 + 2);
+        Clear.render(popup, buf);
+        Paragraph::new(lines)
+            .block(
+                Block::bordered()
+                    .title("Jobs")
+                    .title_bottom("[p]ause/resume  [c]ancel  [x] clear finished  [Esc] close"),
+            )
+            .render(popup, buf);
+    }
+
+    /// Entries of the directory shown in the disk usage view, largest first, with bars for their
+    /// share of it. Progress is shown instead while scanning.
+    fn render_disk_usage(&mut self, area: Rect, buf: &mut Buffer) {
+        let keys = "[l] open  [h] up  [dd] trash  [r] rescan  [Esc] back";
+        if let Some((dir, progress)) = self.disk_usage.progress() {
+            let text = format!(
+                "Scanning {:?}: {} files, {}",
+                dir,
+                progress.files.load(Ordering::Relaxed),
+                format_size(progress.bytes.load(Ordering::Relaxed))
+            );
+            return Paragraph::new(text)
+                .block(Block::bordered().title("Disk usage").title_bottom(keys))
+                .render(area, buf);
+        }
+        let Some((path, dir)) = self.disk_usage.dir() else {
+            return Paragraph::new("Nothing scanned")
+                .block(Block::bordered().title("Disk usage").title_bottom(keys))
+                .render(area, buf);
+        };
+        let items: Vec<ListItem> = dir
+            .children
+            .iter()
+            .map(|entry| {
+                let share = if dir.bytes == 0 {
+                    0.0
+                } else {
+                    entry.bytes as f64 / dir.bytes as f64 * 100.0
+                };
+                ListItem::new(format!(
+                    "{:>10} {:>5.1}% {} {}{}",
+                    format_size(entry.bytes),
+                    share,
+                    progress_bar(entry.bytes, dir.bytes.max(1), 20),
+                    display_name(&entry.name),
+                    if entry.is_dir { "/" } else { "" }
+                ))
+            })
+            .collect();
+        let mut title = format!("Disk usage: {:?} ({})", path, format_size(dir.bytes));
+        if self.disk_usage.skipped > 0 {
+            title.push_str(&format!(", {} unreadable", self.disk_usage.skipped));
+        }
+        let list = List::new(items)
+            .highlight_style(Style::default().fg(Color::Yellow).bg(Color::DarkGray))
+            .block(Block::bordered().title(title).title_bottom(keys))
+            .scroll_padding(3);
+        StatefulWidget::render(list, area, buf, &mut self.disk_usage.list_state);
+    }
+
+    /// Draw the rename editor over the name in the selected tree row
+    fn render_rename_editor(&self, editor: &LineEditor, tree_area: Rect, buf: &mut Buffer) {
+        let state = &self.tree.state;
+        let (Some(idx), offset) = (state.list_state.selected(), state.list_state.offset()) else {
+            return;
+        };
+        let Some(id) = self.tree.nodes.at(idx) else {
+            return;
+        };
+        // inside the block's border
+        let inner = Block::bordered().inner(tree_area);
+        let name_offset = FileTreeWidget::name_offset(self.tree.nodes.depth(id));
+        if idx < offset || (idx - offset) as u16 >= inner.height || name_offset >= inner.width {
+            return;
+        }
+        let area = Rect {
+            x: inner.x + name_offset,
+            y: inner.y + (idx - offset) as u16,
+            width: inner.width - name_offset,
+            height: 1,
+        };
+        Clear.render(area, buf);
+        editor.render(area, buf);
+    }
+}
+
+/// One line summary of a job's progress
+fn describe_job(job: &Job) -> String {
+    let verb = match job.kind {
+        TransferKind::Copy => "Copying",
+        TransferKind::Move => "Moving",
+        TransferKind::Delete => "Deleting",
+    };
+    let progress = &job.progress;
+    let mut line = format!(
+        "{} {}: {}/{} files, {} / {}",
+        verb,
+        job.description,
+        progress.files_done,
+        progress.files_total,
+        format_size(progress.bytes_done),
+        format_size(progress.bytes_total)
+    );
+    match &job.state {
+        JobState::Running => {
+            line.push_str(&format!(", {}/s", format_size(job.throughput() as u64)));
+            if let Some(eta) = job.eta() {
+                line.push_str(&format!(", ETA {}", format_duration(eta)));
+            }
+        }
+        JobState::Queued => line.push_str(" (queued)"),
+        JobState::Paused => line.push_str(" (paused)"),
+        JobState::WaitingOnConflict => line.push_str(" (waiting on a conflict)"),
+        JobState::Failed(e) => line.push_str(&format!(" (failed: {})", e)),
+        JobState::Cancelled => line.push_str(" (cancelled)"),
+        JobState::Done => line.push_str(" (done)"),
+    }
+    line
+}
+
+/// Text progress bar like `[#####     ]`
+fn progress_bar(done: u64, total: u64, width: usize) -> String {
+    let filled = if total == 0 {
+        width
+    } else {
+        (done.min(total) as f64 / total as f64 * width as f64) as usize
+    };
+    format!("[{}{}]", "#".repeat(filled), " ".repeat(width - filled))
+}
+
+/// Whether each item of `transfer` got done. When it was cut short that's told from what's on
+/// disk, and copies count as not done as there's no telling whether they are whole.
+fn completed_items(transfer: &Transfer, finished: bool) -> Vec<bool> {
+    transfer
+        .items
+        .iter()
+        .map(|item| {
+            let gone = fs::symlink_metadata(&item.src).is_err();
+            !item.skipped
+                && (finished
+                    || match transfer.kind {
+                        TransferKind::Copy => false,
+                        TransferKind::Move => gone && fs::symlink_metadata(&item.dest).is_ok(),
+                        TransferKind::Delete => gone,
+                    })
+        })
+        .collect()
+}
+
+/// What went wrong with a job that isn't a paste, to report along with what it got done
+fn job_errors(result: Result<()>, cancelled: bool) -> Vec<Error> {
+    match result {
+        Ok(_) if cancelled => vec![Error::Other("cancelled".to_string())],
+        Ok(_) => Vec::new(),
+        Err(e) => vec![e],
+    }
+}
+
+/// Short description of a list of paths for messages
+fn describe_paths(paths: &[PathBuf]) -> String {
+    match paths {
+        [path] => format!("{:?}", path),
+        paths => format!("{} items", paths.len()),
+    }
+}
+
+/// Centered area of at most `width` x `height` within `area`
+fn popup_area(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
     }
 }
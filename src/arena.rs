@@ -0,0 +1,629 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    ffi::OsString,
+    fs::Metadata,
+    path::{Path, PathBuf},
+};
+
+use crate::tree::{rebase_path, DirectoryStatus, FileObj, FileObjType};
+
+/// Index of a node in a `TreeArena`. Ids of removed nodes get reused.
+pub type NodeId = usize;
+
+/// A directory entry and its links within the tree
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub obj: FileObj,
+    pub parent: Option<NodeId>,
+    /// Entries of an open directory, in sorted order. Collapsed directories have none loaded.
+    pub children: Vec<NodeId>,
+    /// Contents are still being read, and a placeholder row is shown under it
+    pub loading: bool,
+    /// Number of rows below this node that belong to its contents, placeholders included
+    shown: usize,
+}
+
+/// A line of the tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Row {
+    Entry(NodeId),
+    /// Placeholder under a directory whose contents are still being read
+    Loading(NodeId),
+}
+
+/// Every loaded entry as a tree of nodes stored in one vector, with the rows it shows as cached.
+///
+/// Only open directories have their children loaded, so every node apart from the root is
+/// shown and `rows` is simply all of them in depth first order, with a placeholder first under
+/// each directory that is still loading. Each node keeps count of the rows its contents take up,
+/// so the row of a node is worked out from the counts of the siblings before it and those of its
+/// ancestors, without going through `rows`. Changes to a subtree only build that subtree's rows,
+/// then splice them into `rows` in one go.
+#[derive(Debug)]
+pub struct TreeArena {
+    nodes: Vec<Option<Node>>,
+    /// Slots of removed nodes, for reuse
+    free: Vec<NodeId>,
+    by_path: HashMap<PathBuf, NodeId>,
+    root: NodeId,
+    /// Lines in display order
+    rows: Vec<Row>,
+    /// Count of changes made, for telling when what's worked out from the rows is out of date
+    changes: u64,
+}
+
+impl Default for TreeArena {
+    fn default() -> Self {
+        Self::new(FileObj::new(
+            FileObjType::Directory(DirectoryStatus::Open),
+            OsString::new(),
+            PathBuf::new(),
+        ))
+    }
+}
+
+impl TreeArena {
+    /// Start a tree with just the `root` directory, which is always open and never shown
+    pub fn new(mut root: FileObj) -> Self {
+        root.object_type = FileObjType::Directory(DirectoryStatus::Open);
+        let mut arena = Self {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            by_path: HashMap::new(),
+            root: 0,
+            rows: Vec::new(),
+            changes: 0,
+        };
+        arena.root = arena.alloc(root, None);
+        arena
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn get(&self, id: NodeId) -> &Node {
+        self.nodes[id].as_ref().expect("node was removed")
+    }
+
+    /// Node `id` for changing, which counts as a change to the tree
+    fn get_mut(&mut self, id: NodeId) -> &mut Node {
+        self.changes += 1;
+        self.nodes[id].as_mut().expect("node was removed")
+    }
+
+    /// Number of changes made so far. It only goes up, so a different number means something
+    /// changed since.
+    pub fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    pub fn obj(&self, id: NodeId) -> &FileObj {
+        &self.get(id).obj
+    }
+
+    /// Whether `id` is a node that hasn't been removed
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.nodes.get(id).is_some_and(Option::is_some)
+    }
+
+    /// Node of the entry at `path`, if it is loaded
+    pub fn find(&self, path: &Path) -> Option<NodeId> {
+        self.by_path.get(path).copied()
+    }
+
+    /// Shown lines, top to bottom
+    pub fn rows(&self) -> &[Row] {
+        &self.rows
+    }
+
+    /// Row `id` is shown at, from the rows taken up by what comes before it in its directory and
+    /// in the directories above
+    pub fn row_of(&self, id: NodeId) -> Option<usize> {
+        let parent = self.get(id).parent?;
+        let parent_node = self.get(parent);
+        let pos = parent_node.children.iter().position(|child| *child == id)?;
+        let before: usize = parent_node.children[..pos]
+            .iter()
+            .map(|sibling| 1 + self.get(*sibling).shown)
+            .sum();
+        Some(self.first_row_under(parent) + usize::from(parent_node.loading) + before)
+    }
+
+    /// Node shown at `row`, if it is an entry
+    pub fn at(&self, row: usize) -> Option<NodeId> {
+        match self.rows.get(row)? {
+            Row::Entry(id) => Some(*id),
+            Row::Loading(_) => None,
+        }
+    }
+
+    /// How far `id` is indented, entries directly under the root being at 0
+    pub fn depth(&self, id: NodeId) -> usize {
+        self.ancestors(id).count().saturating_sub(1)
+    }
+
+    /// Parents of `id` up to and including the root, nearest first
+    pub fn ancestors(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(self.get(id).parent, |id| self.get(*id).parent)
+    }
+
+    /// Whether `id` is `ancestor` or inside it
+    pub fn is_within(&self, id: NodeId, ancestor: NodeId) -> bool {
+        id == ancestor || self.ancestors(id).any(|id| id == ancestor)
+    }
+
+    /// Every loaded node, in no particular order
+    pub fn ids(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(id, node)| node.as_ref().map(|_| id))
+    }
+
+    /// Sibling `offset` places after (or before, if negative) `id`
+    pub fn sibling(&self, id: NodeId, offset: isize) -> Option<NodeId> {
+        let siblings = &self.get(self.get(id).parent?).children;
+        let pos = siblings.iter().position(|sibling| *sibling == id)?;
+        siblings.get(pos.checked_add_signed(offset)?).copied()
+    }
+
+    /// Number of rows below `id` that belong to its contents
+    fn shown_len(&self, id: NodeId) -> usize {
+        self.get(id).shown
+    }
+
+    /// Count `delta` more rows under `id` and each directory it is in
+    fn grow(&mut self, id: NodeId, delta: isize) {
+        let mut next = Some(id);
+        while let Some(id) = next {
+            let node = self.get_mut(id);
+            node.shown = node
+                .shown
+                .checked_add_signed(delta)
+                .expect("row count went negative");
+            next = node.parent;
+        }
+    }
+
+    /// Rows of the contents of `id`, as they are shown below it
+    fn rows_under(&self, id: NodeId) -> Vec<Row> {
+        let node = self.get(id);
+        let mut rows = Vec::new();
+        if node.loading {
+            rows.push(Row::Loading(id));
+        }
+        for child in &node.children {
+            rows.push(Row::Entry(*child));
+            rows.extend(self.rows_under(*child));
+        }
+        rows
+    }
+
+    /// Row the contents of `id` start at
+    fn first_row_under(&self, id: NodeId) -> usize {
+        if id == self.root {
+            0
+        } else {
+            self.row_of(id).map_or(0, |row| row + 1)
+        }
+    }
+
+    fn alloc(&mut self, obj: FileObj, parent: Option<NodeId>) -> NodeId {
+        let id = self.free.pop().unwrap_or_else(|| {
+            self.nodes.push(None);
+            self.nodes.len() - 1
+        });
+        self.by_path.insert(obj.path.clone(), id);
+        self.nodes[id] = Some(Node {
+            obj,
+            parent,
+            children: Vec::new(),
+            loading: false,
+            shown: 0,
+        });
+        id
+    }
+
+    /// Drop `id` and everything under it from the arena. Rows and the parent's link are left to
+    /// the caller.
+    fn release(&mut self, id: NodeId) {
+        if let Some(node) = self.nodes[id].take() {
+            if self.by_path.get(&node.obj.path) == Some(&id) {
+                self.by_path.remove(&node.obj.path);
+            }
+            self.free.push(id);
+            for child in node.children {
+                self.release(child);
+            }
+        }
+    }
+
+    /// Open the collapsed directory `dir` with `entries` as its contents, in the order given
+    pub fn open(&mut self, dir: NodeId, entries: Vec<FileObj>) {
+        debug_assert!(self.get(dir).children.is_empty());
+        let children: Vec<NodeId> = entries
+            .into_iter()
+            .map(|obj| self.alloc(obj, Some(dir)))
+            .collect();
+        let start = self.first_row_under(dir);
+        self.rows
+            .splice(start..start, children.iter().map(|id| Row::Entry(*id)));
+        self.grow(dir, children.len() as isize);
+        let node = self.get_mut(dir);
+        node.obj.object_type.set_dir_status(DirectoryStatus::Open);
+        node.children = children;
+    }
+
+    /// Open the collapsed directory `dir` with just a placeholder, for its contents to be added
+    /// as they are read
+    pub fn start_loading(&mut self, dir: NodeId) {
+        let start = self.first_row_under(dir);
+        self.rows.insert(start, Row::Loading(dir));
+        self.grow(dir, 1);
+        let node = self.get_mut(dir);
+        node.obj.object_type.set_dir_status(DirectoryStatus::Open);
+        node.loading = true;
+    }
+
+    /// Merge `entries`, sorted by `compare`, into the contents of the open directory `dir`
+    pub fn add_sorted(
+        &mut self,
+        dir: NodeId,
+        entries: Vec<FileObj>,
+        mut compare: impl FnMut(&FileObj, &FileObj) -> Ordering,
+    ) {
+        let start = self.first_row_under(dir);
+        let len = self.shown_len(dir);
+        let added = entries.len();
+        let old = std::mem::take(&mut self.get_mut(dir).children);
+        let mut merged = Vec::with_capacity(old.len() + entries.len());
+        let mut old = old.into_iter().peekable();
+        for obj in entries {
+            while let Some(existing) = old.next_if(|id| compare(self.obj(*id), &obj).is_le()) {
+                merged.push(existing);
+            }
+            merged.push(self.alloc(obj, Some(dir)));
+        }
+        merged.extend(old);
+        self.get_mut(dir).children = merged;
+        let rows = self.rows_under(dir);
+        self.rows.splice(start..start + len, rows);
+        self.grow(dir, added as isize);
+    }
+
+    /// Drop the placeholder of `dir` once all its contents are in
+    pub fn finish_loading(&mut self, dir: NodeId) {
+        if std::mem::take(&mut self.get_mut(dir).loading) {
+            let start = self.first_row_under(dir);
+            self.rows.remove(start);
+            self.grow(dir, -1);
+        }
+    }
+
+    /// Collapse the open directory `dir`, unloading its contents
+    pub fn close(&mut self, dir: NodeId) {
+        let start = self.first_row_under(dir);
+        let len = self.shown_len(dir);
+        self.rows.drain(start..start + len);
+        self.grow(dir, -(len as isize));
+        self.get_mut(dir).loading = false;
+        for child in std::mem::take(&mut self.get_mut(dir).children) {
+            self.release(child);
+        }
+        if dir != self.root {
+            (self.get_mut(dir).obj.object_type).set_dir_status(DirectoryStatus::Collapsed);
+        }
+    }
+
+    /// Add `obj` as child number `pos` of the open directory `parent`
+    pub fn insert(&mut self, parent: NodeId, pos: usize, obj: FileObj) -> NodeId {
+        let row = match self.get(parent).children.get(pos) {
+            Some(next) => self.row_of(*next).unwrap_or(0),
+            None => self.first_row_under(parent) + self.shown_len(parent),
+        };
+        let id = self.alloc(obj, Some(parent));
+        self.get_mut(parent).children.insert(pos, id);
+        self.rows.insert(row, Row::Entry(id));
+        self.grow(parent, 1);
+        id
+    }
+
+    /// Remove `id` and its contents
+    pub fn remove(&mut self, id: NodeId) {
+        if id == self.root {
+            return;
+        }
+        if let Some(row) = self.row_of(id) {
+            let len = 1 + self.shown_len(id);
+            self.rows.drain(row..row + len);
+            if let Some(parent) = self.get(id).parent {
+                self.grow(parent, -(len as isize));
+            }
+        }
+        if let Some(parent) = self.get(id).parent {
+            self.get_mut(parent).children.retain(|child| *child != id);
+        }
+        self.release(id);
+    }
+
+    /// Make `id` child number `pos` of its parent, moving its contents along with it
+    pub fn move_to(&mut self, id: NodeId, pos: usize) {
+        let Some(parent) = self.get(id).parent else {
+            return;
+        };
+        let Some(row) = self.row_of(id) else {
+            return;
+        };
+        let block: Vec<Row> = self.rows.drain(row..row + 1 + self.shown_len(id)).collect();
+        self.get_mut(parent).children.retain(|child| *child != id);
+        self.grow(parent, -(block.len() as isize));
+        let new_row = match self.get(parent).children.get(pos) {
+            Some(next) => self.row_of(*next).unwrap_or(0),
+            None => self.first_row_under(parent) + self.shown_len(parent),
+        };
+        self.get_mut(parent).children.insert(pos, id);
+        self.grow(parent, block.len() as isize);
+        self.rows.splice(new_row..new_row, block);
+    }
+
+    /// Point each node and everything under it at its new location. Every old path is let go
+    /// of before any new one is taken, so nodes may swap or pass along paths.
+    pub fn rename(&mut self, renames: &[(NodeId, &Path)]) {
+        let mut moved = Vec::new();
+        for (id, to) in renames {
+            let from = self.obj(*id).path.clone();
+            let mut stack = vec![*id];
+            while let Some(id) = stack.pop() {
+                // borrowed from the field directly so `by_path` can change alongside
+                let node = self.nodes[id].as_ref().expect("node was removed");
+                let Some(new_path) = rebase_path(&node.obj.path, &from, to) else {
+                    continue;
+                };
+                stack.extend(node.children.iter().copied());
+                if self.by_path.get(&node.obj.path) == Some(&id) {
+                    self.by_path.remove(&node.obj.path);
+                }
+                moved.push((id, new_path));
+            }
+        }
+        for (id, new_path) in moved {
+            self.get_mut(id).obj.path = new_path.clone();
+            self.by_path.insert(new_path, id);
+        }
+        for (id, to) in renames {
+            if let Some(name) = to.file_name() {
+                self.get_mut(*id).obj.name = name.to_os_string();
+            }
+        }
+    }
+
+    /// Replace the metadata of `id` with a fresher read
+    pub fn set_metadata(&mut self, id: NodeId, metadata: Option<Metadata>) {
+        self.get_mut(id).obj.metadata = metadata;
+    }
+
+    /// Re-sort the contents of every open directory
+    pub fn sort_by(&mut self, mut compare: impl FnMut(&FileObj, &FileObj) -> Ordering) {
+        let dirs: Vec<NodeId> = self.ids().collect();
+        for dir in dirs {
+            let mut children = std::mem::take(&mut self.get_mut(dir).children);
+            children.sort_by(|a, b| compare(self.obj(*a), self.obj(*b)));
+            self.get_mut(dir).children = children;
+        }
+        self.rows = self.rows_under(self.root);
+    }
+
+    /// Move the root up to `parent`, with `entries` as its contents. The entry for the current
+    /// root is replaced by the root itself, so everything loaded under it stays.
+    pub fn reroot_up(&mut self, parent: FileObj, entries: Vec<FileObj>) {
+        let old_root = self.root;
+        let old_rows = std::mem::take(&mut self.rows);
+        let new_root = self.alloc(parent, None);
+        let mut children = Vec::new();
+        for obj in entries {
+            if obj.path == self.obj(old_root).path {
+                self.get_mut(old_root).parent = Some(new_root);
+                self.get_mut(old_root).obj.name = obj.name;
+                self.rows.push(Row::Entry(old_root));
+                self.rows.extend(&old_rows);
+                children.push(old_root);
+            } else {
+                let id = self.alloc(obj, Some(new_root));
+                self.rows.push(Row::Entry(id));
+                children.push(id);
+            }
+        }
+        let shown = self.rows.len();
+        let node = self.get_mut(new_root);
+        node.obj.object_type.set_dir_status(DirectoryStatus::Open);
+        node.children = children;
+        node.shown = shown;
+        self.root = new_root;
+    }
+
+    /// Make the open directory `dir` the root, unloading everything outside of it
+    pub fn zoom_into(&mut self, dir: NodeId) {
+        let (Some(parent), Some(row)) = (self.get(dir).parent, self.row_of(dir)) else {
+            return;
+        };
+        let len = self.shown_len(dir);
+        self.rows = self.rows[row + 1..row + 1 + len].to_vec();
+        self.get_mut(parent).children.retain(|child| *child != dir);
+        self.get_mut(dir).parent = None;
+        self.release(self.root);
+        self.root = dir;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(path: &str) -> FileObj {
+        obj(path, FileObjType::Directory(DirectoryStatus::Collapsed))
+    }
+
+    fn file(path: &str) -> FileObj {
+        obj(path, FileObjType::File)
+    }
+
+    fn obj(path: &str, object_type: FileObjType) -> FileObj {
+        let path = PathBuf::from(path);
+        let name = path.file_name().unwrap_or_default().to_os_string();
+        FileObj::new(object_type, name, path)
+    }
+
+    fn by_name(a: &FileObj, b: &FileObj) -> Ordering {
+        a.name.cmp(&b.name)
+    }
+
+    fn id(arena: &TreeArena, path: &str) -> NodeId {
+        arena.find(Path::new(path)).unwrap()
+    }
+
+    /// Paths of the shown rows, with placeholders as `...`
+    fn shown(arena: &TreeArena) -> Vec<String> {
+        check(arena);
+        arena
+            .rows()
+            .iter()
+            .map(|row| match row {
+                Row::Entry(id) => arena.obj(*id).path.display().to_string(),
+                Row::Loading(_) => "...".to_string(),
+            })
+            .collect()
+    }
+
+    /// Make sure what's cached agrees with the nodes: the rows, where each node is shown, how
+    /// many rows each has below it and the paths they're found by
+    fn check(arena: &TreeArena) {
+        assert_eq!(arena.rows, arena.rows_under(arena.root));
+        for (row, id) in arena.rows.iter().enumerate() {
+            if let Row::Entry(id) = id {
+                assert_eq!(arena.row_of(*id), Some(row));
+            }
+        }
+        for id in arena.ids() {
+            assert_eq!(arena.shown_len(id), arena.rows_under(id).len());
+            assert_eq!(arena.find(&arena.obj(id).path), Some(id));
+            for child in &arena.get(id).children {
+                assert_eq!(arena.get(*child).parent, Some(id));
+            }
+        }
+        assert_eq!(arena.by_path.len(), arena.ids().count());
+    }
+
+    /// `/r` holding directories `a` (open, holding `x` and `y`) and `b`, and a file `c`
+    fn arena() -> TreeArena {
+        let mut arena = TreeArena::new(dir("/r"));
+        let root = arena.root();
+        arena.open(root, vec![dir("/r/a"), dir("/r/b"), file("/r/c")]);
+        arena.open(id(&arena, "/r/a"), vec![file("/r/a/x"), file("/r/a/y")]);
+        arena
+    }
+
+    #[test]
+    fn opens_and_closes() {
+        let mut arena = arena();
+        assert_eq!(shown(&arena), ["/r/a", "/r/a/x", "/r/a/y", "/r/b", "/r/c"]);
+        assert_eq!(arena.depth(id(&arena, "/r/a/x")), 1);
+
+        arena.close(id(&arena, "/r/a"));
+        assert_eq!(shown(&arena), ["/r/a", "/r/b", "/r/c"]);
+        assert!(arena.find(Path::new("/r/a/x")).is_none());
+        assert!(!arena.obj(id(&arena, "/r/a")).object_type.is_open());
+    }
+
+    #[test]
+    fn loads_with_a_placeholder() {
+        let mut arena = arena();
+        let b = id(&arena, "/r/b");
+        arena.start_loading(b);
+        assert_eq!(
+            shown(&arena),
+            ["/r/a", "/r/a/x", "/r/a/y", "/r/b", "...", "/r/c"]
+        );
+        arena.add_sorted(b, vec![file("/r/b/m"), file("/r/b/o")], by_name);
+        arena.add_sorted(b, vec![file("/r/b/n")], by_name);
+        assert_eq!(
+            shown(&arena),
+            ["/r/a", "/r/a/x", "/r/a/y", "/r/b", "...", "/r/b/m", "/r/b/n", "/r/b/o", "/r/c"]
+        );
+        arena.finish_loading(b);
+        assert_eq!(arena.row_of(id(&arena, "/r/b/m")), Some(4));
+        assert_eq!(shown(&arena).len(), 8);
+    }
+
+    #[test]
+    fn inserts_and_removes() {
+        let mut arena = arena();
+        let a = id(&arena, "/r/a");
+        arena.insert(a, 1, file("/r/a/xx"));
+        assert_eq!(
+            shown(&arena),
+            ["/r/a", "/r/a/x", "/r/a/xx", "/r/a/y", "/r/b", "/r/c"]
+        );
+        arena.insert(arena.root(), 3, file("/r/d"));
+        assert_eq!(shown(&arena).last().unwrap(), "/r/d");
+
+        arena.remove(a);
+        assert_eq!(shown(&arena), ["/r/b", "/r/c", "/r/d"]);
+        assert!(arena.find(Path::new("/r/a/xx")).is_none());
+        // freed slots get reused
+        let slots = arena.nodes.len();
+        arena.insert(arena.root(), 0, file("/r/0"));
+        assert_eq!(arena.nodes.len(), slots);
+        assert_eq!(shown(&arena), ["/r/0", "/r/b", "/r/c", "/r/d"]);
+    }
+
+    #[test]
+    fn moves_with_contents() {
+        let mut arena = arena();
+        let a = id(&arena, "/r/a");
+        arena.move_to(a, 2);
+        assert_eq!(shown(&arena), ["/r/b", "/r/c", "/r/a", "/r/a/x", "/r/a/y"]);
+        arena.move_to(a, 1);
+        assert_eq!(shown(&arena), ["/r/b", "/r/a", "/r/a/x", "/r/a/y", "/r/c"]);
+        assert_eq!(arena.sibling(a, -1), Some(id(&arena, "/r/b")));
+        assert_eq!(arena.sibling(a, 1), Some(id(&arena, "/r/c")));
+    }
+
+    #[test]
+    fn renames_with_contents_and_swaps() {
+        let mut arena = arena();
+        let (a, b) = (id(&arena, "/r/a"), id(&arena, "/r/b"));
+        arena.rename(&[(a, Path::new("/r/b")), (b, Path::new("/r/a"))]);
+        assert_eq!(id(&arena, "/r/b"), a);
+        assert_eq!(id(&arena, "/r/a"), b);
+        assert_eq!(arena.obj(a).name, "b");
+        assert_eq!(shown(&arena), ["/r/b", "/r/b/x", "/r/b/y", "/r/a", "/r/c"]);
+        arena.sort_by(by_name);
+        assert_eq!(shown(&arena), ["/r/a", "/r/b", "/r/b/x", "/r/b/y", "/r/c"]);
+    }
+
+    #[test]
+    fn reroots_up_keeping_what_is_loaded() {
+        let mut arena = arena();
+        let old_root = arena.root();
+        arena.reroot_up(dir("/"), vec![dir("/q"), dir("/r"), file("/s")]);
+        assert_eq!(arena.get(old_root).parent, Some(arena.root()));
+        assert_eq!(
+            shown(&arena),
+            ["/q", "/r", "/r/a", "/r/a/x", "/r/a/y", "/r/b", "/r/c", "/s"]
+        );
+        assert_eq!(arena.depth(id(&arena, "/r/a/x")), 2);
+    }
+
+    #[test]
+    fn zooms_into_an_open_directory() {
+        let mut arena = arena();
+        let a = id(&arena, "/r/a");
+        arena.zoom_into(a);
+        assert_eq!(arena.root(), a);
+        assert_eq!(shown(&arena), ["/r/a/x", "/r/a/y"]);
+        assert!(arena.find(Path::new("/r/b")).is_none());
+        assert_eq!(arena.depth(id(&arena, "/r/a/x")), 0);
+    }
+}
@@ -0,0 +1,293 @@
+use std::{
+    collections::HashSet,
+    env,
+    fs::{self, File, OpenOptions},
+    hash::{BuildHasher, RandomState},
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    process::{self, Command},
+};
+
+use crate::{
+    error::{Error, PathContext, Result},
+    tree::{display_name, parse_name},
+};
+
+/// Open the names of `paths` in the user's editor, one per line, and return the edited lines.
+/// Names are written the way `display_name` shows them, so odd bytes and newlines survive.
+pub fn edit_names(paths: &[PathBuf]) -> Result<Vec<String>> {
+    let mut buffer = String::new();
+    for path in paths {
+        let name = display_name(path.file_name().unwrap_or_default());
+        buffer.push_str(&name);
+        buffer.push('\n');
+    }
+
+    let (file, mut handle) = create_temp_file()?;
+    handle.write_all(buffer.as_bytes()).at(&file)?;
+    drop(handle);
+    let result = run_editor(&file).and_then(|_| fs::read_to_string(&file).at(&file));
+    let _ = fs::remove_file(&file);
+    Ok(result?.lines().map(|line| line.to_string()).collect())
+}
+
+/// Make a new file only we can read, under a random name in the temp directory. It's created
+/// fresh rather than opened, so a file or symlink someone put there beforehand isn't written to.
+fn create_temp_file() -> Result<(PathBuf, File)> {
+    let dir = env::temp_dir();
+    loop {
+        let random = RandomState::new().hash_one(process::id());
+        let file = dir.join(format!("explorer-bulk-rename-{:016x}.txt", random));
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&file)
+        {
+            Ok(handle) => return Ok((file, handle)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).at(&dir),
+        }
+    }
+}
+
+/// Run `$VISUAL` or `$EDITOR` (falling back to vi) on `file` and wait for it to exit
+fn run_editor(file: &Path) -> Result<()> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    // allow things like `code --wait`
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().unwrap_or("vi");
+    let status = Command::new(program)
+        .args(parts)
+        .arg(file)
+        .status()
+        .map_err(|e| Error::Other(format!("Couldn't run {}: {}", editor, e)))?;
+    if !status.success() {
+        return Err(Error::Other(format!("{} exited with {}", editor, status)));
+    }
+    Ok(())
+}
+
+/// Check the edited names and work out which paths change, or return every problem found
+pub fn plan(
+    paths: &[PathBuf],
+    names: &[String],
+) -> std::result::Result<Vec<(PathBuf, PathBuf)>, Vec<String>> {
+    if names.len() != paths.len() {
+        return Err(vec![format!(
+            "Expected {} lines but got {}, nothing was renamed",
+            paths.len(),
+            names.len()
+        )]);
+    }
+
+    let mut problems = Vec::new();
+    let mut targets = HashSet::new();
+    let mut changes = Vec::new();
+    for (path, text) in paths.iter().zip(names) {
+        let name = parse_name(text);
+        if name.is_empty() || name == "." || name == ".." || name.as_encoded_bytes().contains(&b'/')
+        {
+            problems.push(format!("{:?}: {:?} is not a valid name", path, text));
+            continue;
+        }
+        let target = path.with_file_name(name);
+        if !targets.insert(target.clone()) {
+            problems.push(format!("{:?}: more than one file renamed to it", target));
+            continue;
+        }
+        if target != *path {
+            changes.push((path.clone(), target));
+        }
+    }
+    // something already there is only fine if it's being renamed away too
+    for (_, target) in &changes {
+        if fs::symlink_metadata(target).is_ok() && !paths.contains(target) {
+            problems.push(format!("{:?}: already exists", target));
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(changes)
+    } else {
+        Err(problems)
+    }
+}
+
+/// What became of one file in a bulk rename
+#[derive(Debug)]
+pub struct Renamed {
+    pub from: PathBuf,
+    /// Where it is now: the new name, back where it was if that failed, or the temporary name
+    /// if it couldn't be put back
+    pub at: PathBuf,
+    pub error: Option<Error>,
+}
+
+/// Perform the renames. Everything goes through a temporary name first so swaps and chains
+/// (`a -> b`, `b -> a`) work. Returns what became of each file, in the order given.
+pub fn apply(changes: &[(PathBuf, PathBuf)]) -> Vec<Renamed> {
+    let mut results = Vec::new();
+    let mut staged = Vec::new();
+    for (i, (from, _)) in changes.iter().enumerate() {
+        let temp = temp_name(from, i);
+        match fs::rename(from, &temp).at(from) {
+            Ok(_) => staged.push((i, temp)),
+            Err(e) => results.push((i, from.clone(), Some(e))),
+        }
+    }
+    for (i, temp) in staged {
+        let (from, to) = &changes[i];
+        // something may have shown up there since the names were checked
+        let result = match fs::symlink_metadata(to) {
+            Ok(_) => Err(Error::AlreadyExists(to.clone())),
+            Err(_) => fs::rename(&temp, to).at(to),
+        };
+        match result {
+            Ok(_) => results.push((i, to.clone(), None)),
+            Err(e) => {
+                // put it back where it was if nothing took its place, otherwise leave it under
+                // the temporary name
+                let restored =
+                    fs::symlink_metadata(from).is_err() && fs::rename(&temp, from).is_ok();
+                let at = if restored { from.clone() } else { temp };
+                results.push((i, at, Some(e)));
+            }
+        }
+    }
+    results.sort_by_key(|(i, ..)| *i);
+    results
+        .into_iter()
+        .map(|(i, at, error)| Renamed {
+            from: changes[i].0.clone(),
+            at,
+            error,
+        })
+        .collect()
+}
+
+/// Name next to `path` to park it under while names are shuffled, which nothing has yet
+fn temp_name(path: &Path, i: usize) -> PathBuf {
+    let mut n = 0;
+    loop {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".bulk-rename-{}-{}", process::id(), i));
+        if n > 0 {
+            name.push(format!("-{}", n));
+        }
+        let temp = path.with_file_name(name);
+        if fs::symlink_metadata(&temp).is_err() {
+            return temp;
+        }
+        n += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Files named `names` in `dir`, each holding its own name
+    fn files(dir: &Path, names: &[&str]) -> Vec<PathBuf> {
+        names
+            .iter()
+            .map(|name| {
+                fs::write(dir.join(name), name).unwrap();
+                dir.join(name)
+            })
+            .collect()
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn plans_only_changed_names() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let paths = files(dir, &["a", "b", "c"]);
+        let changes = plan(&paths, &names(&["a", "x", "c"])).unwrap();
+        assert_eq!(changes, vec![(dir.join("b"), dir.join("x"))]);
+    }
+
+    #[test]
+    fn plan_refuses_bad_edits() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let paths = files(dir, &["a", "b"]);
+        fs::write(dir.join("taken"), "").unwrap();
+
+        assert_eq!(plan(&paths, &names(&["a"])).unwrap_err().len(), 1);
+        let problems = plan(&paths, &names(&["", "c/d"])).unwrap_err();
+        assert_eq!(problems.len(), 2);
+        let problems = plan(&paths, &names(&["same", "same"])).unwrap_err();
+        assert_eq!(problems.len(), 1);
+        let problems = plan(&paths, &names(&["taken", "b"])).unwrap_err();
+        assert!(problems[0].contains("already exists"));
+    }
+
+    #[test]
+    fn plan_allows_swaps_and_chains() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let paths = files(dir, &["a", "b", "c"]);
+        assert_eq!(plan(&paths, &names(&["b", "a", "c"])).unwrap().len(), 2);
+        assert_eq!(plan(&paths, &names(&["b", "c", "d"])).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn applies_swaps_and_chains() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let paths = files(dir, &["a", "b", "c"]);
+        let changes = plan(&paths, &names(&["b", "a", "d"])).unwrap();
+
+        let results = apply(&changes);
+        assert_eq!(results.len(), 3);
+        for (renamed, (from, to)) in results.iter().zip(&changes) {
+            assert_eq!(&renamed.from, from);
+            assert_eq!(&renamed.at, to);
+            assert!(renamed.error.is_none());
+        }
+        assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "b");
+        assert_eq!(fs::read_to_string(dir.join("b")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(dir.join("d")).unwrap(), "c");
+        assert!(!dir.join("c").exists());
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn puts_back_files_whose_new_name_was_taken() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let paths = files(dir, &["a", "b"]);
+        let changes = plan(&paths, &names(&["x", "y"])).unwrap();
+        // shows up after the names were checked
+        fs::write(dir.join("y"), "other").unwrap();
+
+        let results = apply(&changes);
+        assert_eq!(results[0].at, dir.join("x"));
+        assert!(results[0].error.is_none());
+        assert_eq!(results[1].at, dir.join("b"));
+        assert!(matches!(results[1].error, Some(Error::AlreadyExists(_))));
+        assert_eq!(fs::read_to_string(dir.join("b")).unwrap(), "b");
+        assert_eq!(fs::read_to_string(dir.join("y")).unwrap(), "other");
+    }
+
+    #[test]
+    fn temp_name_skips_existing_files() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let first = temp_name(&dir.join("a"), 0);
+        fs::write(&first, "").unwrap();
+        let second = temp_name(&dir.join("a"), 0);
+        assert_ne!(first, second);
+        assert!(!second.exists());
+    }
+}
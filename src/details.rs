@@ -0,0 +1,214 @@
+use std::{
+    collections::HashMap,
+    fs::Metadata,
+    os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt},
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Local};
+use sysinfo::{Groups, Users};
+
+use crate::dir_size::DirSize;
+
+/// Files modified longer ago than this show the year instead of the time, like `ls -l`
+const RECENT: Duration = Duration::from_secs(60 * 60 * 24 * 182);
+
+/// A column of the detail view
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    /// Size in human readable units
+    Size,
+    /// Last modified time
+    Modified,
+    /// Type and permission bits, e.g. `drwxr-xr-x`
+    Permissions,
+    /// Owning user and group
+    Owner,
+}
+
+impl Column {
+    /// Every column, in the order they are drawn left to right. Columns further right are
+    /// dropped first when there isn't room.
+    pub const ALL: [Column; 4] = [
+        Column::Size,
+        Column::Modified,
+        Column::Permissions,
+        Column::Owner,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Column::Size => "size",
+            Column::Modified => "modified time",
+            Column::Permissions => "permissions",
+            Column::Owner => "owner",
+        }
+    }
+
+    /// Key that toggles this in the columns menu
+    pub fn shortcut(&self) -> char {
+        match self {
+            Column::Size => 's',
+            Column::Modified => 'm',
+            Column::Permissions => 'p',
+            Column::Owner => 'o',
+        }
+    }
+}
+
+/// `ls -l`-style columns shown next to the names in the tree
+#[derive(Debug)]
+pub struct DetailView {
+    pub shown: bool,
+    /// Columns turned on, kept in the order of `Column::ALL`
+    pub columns: Vec<Column>,
+    /// Names of users and groups by id, read the first time the view is shown
+    owners: Option<(HashMap<u32, String>, HashMap<u32, String>)>,
+}
+
+impl Default for DetailView {
+    fn default() -> Self {
+        Self {
+            shown: false,
+            columns: Column::ALL.to_vec(),
+            owners: None,
+        }
+    }
+}
+
+impl DetailView {
+    /// Show or hide the columns
+    pub fn toggle(&mut self) {
+        self.shown = !self.shown;
+        if self.shown && self.owners.is_none() {
+            let users = Users::new_with_refreshed_list()
+                .iter()
+                .map(|user| (**user.id(), user.name().to_string()))
+                .collect();
+            let groups = Groups::new_with_refreshed_list()
+                .iter()
+                .map(|group| (**group.id(), group.name().to_string()))
+                .collect();
+            self.owners = Some((users, groups));
+        }
+    }
+
+    /// Turn `column` on or off
+    pub fn toggle_column(&mut self, column: Column) {
+        if self.columns.contains(&column) {
+            self.columns.retain(|shown| *shown != column);
+        } else {
+            self.columns.push(column);
+            self.columns
+                .sort_by_key(|column| Column::ALL.iter().position(|c| c == column));
+        }
+    }
+
+    /// Text of `column` for an entry, empty when its metadata couldn't be read. Directories
+    /// show their measured size if there is one, with a trailing `…` while still measuring.
+    pub fn cell(
+        &self,
+        column: Column,
+        metadata: Option<&Metadata>,
+        dir_size: Option<&DirSize>,
+    ) -> String {
+        let Some(metadata) = metadata else {
+            return String::new();
+        };
+        match column {
+            Column::Size if metadata.is_dir() => match dir_size {
+                Some(DirSize { bytes, done: true }) => short_size(*bytes),
+                Some(DirSize { bytes, done: false }) => format!("{}…", short_size(*bytes)),
+                None => "-".to_string(),
+            },
+            Column::Size => short_size(metadata.len()),
+            Column::Modified => metadata.modified().map(ls_time).unwrap_or_default(),
+            Column::Permissions => permission_string(metadata),
+            Column::Owner => {
+                let (users, groups) = match &self.owners {
+                    Some((users, groups)) => {
+                        (users.get(&metadata.uid()), groups.get(&metadata.gid()))
+                    }
+                    None => (None, None),
+                };
+                format!(
+                    "{} {}",
+                    users.cloned().unwrap_or_else(|| metadata.uid().to_string()),
+                    groups
+                        .cloned()
+                        .unwrap_or_else(|| metadata.gid().to_string())
+                )
+            }
+        }
+    }
+}
+
+/// Size in at most 5 characters, like `ls -lh`: `999`, `1.5K`, `12M`
+fn short_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["", "K", "M", "G", "T", "P"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        bytes.to_string()
+    } else if size < 10.0 {
+        format!("{:.1}{}", size, UNITS[unit])
+    } else {
+        format!("{:.0}{}", size, UNITS[unit])
+    }
+}
+
+/// `Oct 18 14:03` for recent times, `Oct 18  2025` for older ones
+fn ls_time(time: SystemTime) -> String {
+    let recent = SystemTime::now()
+        .duration_since(time)
+        .map_or(true, |age| age < RECENT);
+    let time: DateTime<Local> = time.into();
+    if recent {
+        time.format("%b %e %H:%M").to_string()
+    } else {
+        time.format("%b %e  %Y").to_string()
+    }
+}
+
+/// Type character followed by the permission bits, e.g. `drwxr-xr-x` or `-rwsr-x--T`
+fn permission_string(metadata: &Metadata) -> String {
+    let file_type = metadata.file_type();
+    let kind = if file_type.is_symlink() {
+        'l'
+    } else if file_type.is_dir() {
+        'd'
+    } else if file_type.is_block_device() {
+        'b'
+    } else if file_type.is_char_device() {
+        'c'
+    } else if file_type.is_fifo() {
+        'p'
+    } else if file_type.is_socket() {
+        's'
+    } else {
+        '-'
+    };
+    let mode = metadata.permissions().mode();
+    let mut text = String::from(kind);
+    // (read, write, execute bit, special bit, special char when executable / not)
+    let triples = [
+        (0o400, 0o200, 0o100, 0o4000, ('s', 'S')),
+        (0o040, 0o020, 0o010, 0o2000, ('s', 'S')),
+        (0o004, 0o002, 0o001, 0o1000, ('t', 'T')),
+    ];
+    for (read, write, exec, special, (on, off)) in triples {
+        text.push(if mode & read != 0 { 'r' } else { '-' });
+        text.push(if mode & write != 0 { 'w' } else { '-' });
+        text.push(match (mode & exec != 0, mode & special != 0) {
+            (true, true) => on,
+            (false, true) => off,
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    text
+}
@@ -0,0 +1,288 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use ignore::{WalkBuilder, WalkState};
+
+/// How often a directory being measured reports its running total
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Total size of the files under a directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirSize {
+    pub bytes: u64,
+    /// The walk finished, otherwise this is a running total
+    pub done: bool,
+}
+
+/// Measures directories one at a time on background threads, each with a parallel walk
+#[derive(Debug)]
+pub struct DirSizer {
+    /// Sizes measured so far, or being measured, by directory
+    pub sizes: HashMap<PathBuf, DirSize>,
+    /// Measure every directory in the tree as it shows up
+    pub auto: bool,
+    queue: VecDeque<PathBuf>,
+    /// Directory being measured, with the id its results come with and its cancel flag
+    current: Option<(usize, PathBuf, Arc<AtomicBool>)>,
+    next_id: usize,
+    /// Count of changes made to `sizes`, which only goes up
+    changes: u64,
+    tx: Sender<(usize, DirSize)>,
+    rx: Receiver<(usize, DirSize)>,
+}
+
+impl Default for DirSizer {
+    fn default() -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            sizes: HashMap::new(),
+            auto: false,
+            queue: VecDeque::new(),
+            current: None,
+            next_id: 0,
+            changes: 0,
+            tx,
+            rx,
+        }
+    }
+}
+
+impl DirSizer {
+    /// Queue `dir` to be measured, again if it was before
+    pub fn measure(&mut self, dir: PathBuf) {
+        let queued = self.queue.contains(&dir)
+            || self
+                .current
+                .as_ref()
+                .is_some_and(|(_, current, _)| *current == dir);
+        if !queued {
+            self.queue.push_back(dir);
+        }
+        self.start_next();
+    }
+
+    /// Queue `dir` to be measured unless it already has been
+    pub fn measure_once(&mut self, dir: PathBuf) {
+        if !self.sizes.contains_key(&dir) && !self.queue.contains(&dir) {
+            self.measure(dir);
+        }
+    }
+
+    /// Take in results that came in. Returns whether any sizes changed.
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        while let Ok((id, size)) = self.rx.try_recv() {
+            let Some((current, dir, _)) = &self.current else {
+                continue;
+            };
+            if *current != id {
+                // cancelled
+                continue;
+            }
+            self.sizes.insert(dir.clone(), size);
+            if size.done {
+                self.current = None;
+            }
+            changed = true;
+            self.changes += 1;
+        }
+        self.start_next();
+        changed
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Number of changes made to the sizes so far, so a different number means they changed
+    pub fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    /// Stop measuring and forget every size, as they may be out of date
+    pub fn clear(&mut self) {
+        if let Some((_, _, cancel)) = self.current.take() {
+            cancel.store(true, Ordering::Relaxed);
+        }
+        self.queue.clear();
+        self.sizes.clear();
+        self.changes += 1;
+    }
+
+    fn start_next(&mut self) {
+        if self.current.is_some() {
+            return;
+        }
+        let Some(dir) = self.queue.pop_front() else {
+            return;
+        };
+        self.next_id += 1;
+        let (id, cancel) = (self.next_id, Arc::new(AtomicBool::new(false)));
+        let (worker_dir, worker_cancel, tx) = (dir.clone(), cancel.clone(), self.tx.clone());
+        thread::spawn(move || {
+            let bytes = walk(id, &worker_dir, &worker_cancel, &tx);
+            let _ = tx.send((id, DirSize { bytes, done: true }));
+        });
+        self.current = Some((id, dir, cancel));
+    }
+}
+
+/// Add up the sizes of the files under `dir`, sending running totals along the way
+fn walk(id: usize, dir: &Path, cancel: &AtomicBool, tx: &Sender<(usize, DirSize)>) -> u64 {
+    let total = AtomicU64::new(0);
+    let last_sent = Mutex::new(Instant::now());
+    walk_sizes(dir, cancel, |found| {
+        // unreadable entries are left out
+        let Some(found) = found.filter(|found| !found.is_dir) else {
+            return;
+        };
+        let bytes = total.fetch_add(found.bytes, Ordering::Relaxed) + found.bytes;
+        if let Ok(mut last_sent) = last_sent.try_lock() {
+            if last_sent.elapsed() >= PROGRESS_INTERVAL {
+                *last_sent = Instant::now();
+                let _ = tx.send((id, DirSize { bytes, done: false }));
+            }
+        }
+    });
+    total.into_inner()
+}
+
+/// An entry `walk_sizes` came across
+#[derive(Debug)]
+pub struct FoundEntry {
+    /// How far below the walked directory it is, which is at depth 0
+    pub depth: usize,
+    pub path: PathBuf,
+    /// Size of a file, or 0 for a directory or a file already counted through another hard link
+    pub bytes: u64,
+    pub is_dir: bool,
+}
+
+/// Walk everything under `dir` in parallel, staying on its filesystem, and hand each entry to
+/// `found`, or `None` for one that couldn't be read. Stops early once `cancel` is set.
+pub fn walk_sizes(dir: &Path, cancel: &AtomicBool, found: impl Fn(Option<FoundEntry>) + Sync) {
+    let linked = Mutex::new(HashSet::new());
+    WalkBuilder::new(dir)
+        .standard_filters(false)
+        .same_file_system(true)
+        .build_parallel()
+        .run(|| {
+            Box::new(|entry| {
+                if cancel.load(Ordering::Relaxed) {
+                    return WalkState::Quit;
+                }
+                let Ok(entry) = entry else {
+                    found(None);
+                    return WalkState::Continue;
+                };
+                let Ok(metadata) = entry.metadata() else {
+                    found(None);
+                    return WalkState::Continue;
+                };
+                let is_dir = metadata.is_dir();
+                // each hard-linked file is counted once
+                let counted = is_dir
+                    || metadata.nlink() <= 1
+                    || linked
+                        .lock()
+                        .unwrap()
+                        .insert((metadata.dev(), metadata.ino()));
+                let bytes = if is_dir || !counted {
+                    0
+                } else {
+                    metadata.len()
+                };
+                found(Some(FoundEntry {
+                    depth: entry.depth(),
+                    path: entry.into_path(),
+                    bytes,
+                    is_dir,
+                }));
+                WalkState::Continue
+            })
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Measure `dir` to the end, returning its size
+    fn measure(sizer: &mut DirSizer, dir: &Path) -> DirSize {
+        sizer.measure(dir.to_path_buf());
+        let start = Instant::now();
+        while sizer.is_busy() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            sizer.poll();
+            thread::sleep(Duration::from_millis(5));
+        }
+        sizer.sizes[dir]
+    }
+
+    #[test]
+    fn adds_up_nested_files() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("a/b")).unwrap();
+        fs::write(dir.join("top"), [0; 10]).unwrap();
+        fs::write(dir.join("a/mid"), [0; 100]).unwrap();
+        fs::write(dir.join("a/b/deep"), [0; 1000]).unwrap();
+
+        let mut sizer = DirSizer::default();
+        let size = measure(&mut sizer, dir);
+        assert_eq!(
+            size,
+            DirSize {
+                bytes: 1110,
+                done: true
+            }
+        );
+        assert_eq!(measure(&mut sizer, &dir.join("a")).bytes, 1100);
+    }
+
+    #[test]
+    fn counts_hard_links_once() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        fs::write(dir.join("file"), [0; 100]).unwrap();
+        fs::hard_link(dir.join("file"), dir.join("link")).unwrap();
+
+        let found = Mutex::new(Vec::new());
+        walk_sizes(dir, &AtomicBool::new(false), |entry| {
+            found.lock().unwrap().push(entry.unwrap());
+        });
+        let found = found.into_inner().unwrap();
+        assert_eq!(found.len(), 3);
+        assert_eq!(found.iter().map(|entry| entry.bytes).sum::<u64>(), 100);
+        assert!(found.iter().any(|entry| entry.depth == 0 && entry.is_dir));
+    }
+
+    #[test]
+    fn cleared_measurement_is_dropped() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        fs::write(dir.join("file"), [0; 10]).unwrap();
+
+        let mut sizer = DirSizer::default();
+        sizer.measure(dir.to_path_buf());
+        sizer.clear();
+        assert!(!sizer.is_busy());
+        thread::sleep(Duration::from_millis(50));
+        sizer.poll();
+        assert!(sizer.sizes.is_empty());
+    }
+}
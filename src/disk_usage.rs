@@ -0,0 +1,400 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use ratatui::widgets::ListState;
+
+use crate::dir_size::walk_sizes;
+
+/// A file or directory with the total size of everything under it
+#[derive(Debug)]
+pub struct UsageEntry {
+    pub name: OsString,
+    pub bytes: u64,
+    pub is_dir: bool,
+    /// Largest first
+    pub children: Vec<UsageEntry>,
+}
+
+/// Running totals of a scan, shared with the thread doing it
+#[derive(Debug, Default)]
+pub struct ScanProgress {
+    pub files: AtomicU64,
+    pub bytes: AtomicU64,
+    /// Entries that couldn't be read and are left out
+    pub skipped: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Scan {
+    dir: PathBuf,
+    cancel: Arc<AtomicBool>,
+    progress: Arc<ScanProgress>,
+    handle: JoinHandle<UsageEntry>,
+}
+
+/// ncdu-style view of what takes up the space under a directory, one directory at a time
+#[derive(Debug, Default)]
+pub struct DiskUsage {
+    /// Directory that was scanned and everything under it, once the scan is done
+    root: Option<(PathBuf, UsageEntry)>,
+    /// Child indices leading from the root to the directory being shown
+    trail: Vec<usize>,
+    pub list_state: ListState,
+    /// Entries left out of the last scan because they couldn't be read
+    pub skipped: u64,
+    scan: Option<Scan>,
+}
+
+impl DiskUsage {
+    /// Start scanning `dir` in the background, dropping any earlier results
+    pub fn scan(&mut self, dir: PathBuf) {
+        if let Some(scan) = self.scan.take() {
+            scan.cancel.store(true, Ordering::Relaxed);
+        }
+        self.root = None;
+        self.trail.clear();
+        self.list_state = ListState::default();
+        let (cancel, progress) = (Arc::new(AtomicBool::new(false)), Arc::default());
+        let (worker_dir, worker_cancel, worker_progress) =
+            (dir.clone(), cancel.clone(), Arc::clone(&progress));
+        let handle = thread::spawn(move || walk(&worker_dir, &worker_cancel, &worker_progress));
+        self.scan = Some(Scan {
+            dir,
+            cancel,
+            progress,
+            handle,
+        });
+    }
+
+    /// Take in the finished scan. Returns whether it finished.
+    pub fn poll(&mut self) -> bool {
+        if !self
+            .scan
+            .as_ref()
+            .is_some_and(|scan| scan.handle.is_finished())
+        {
+            return false;
+        }
+        let Some(scan) = self.scan.take() else {
+            return false;
+        };
+        self.skipped = scan.progress.skipped.load(Ordering::Relaxed);
+        // a panicked scan just leaves nothing to show
+        if let Ok(entry) = scan.handle.join() {
+            self.list_state
+                .select((!entry.children.is_empty()).then_some(0));
+            self.root = Some((scan.dir, entry));
+        }
+        true
+    }
+
+    pub fn is_scanning(&self) -> bool {
+        self.scan.is_some()
+    }
+
+    /// Directory being scanned and the totals so far
+    pub fn progress(&self) -> Option<(&Path, &ScanProgress)> {
+        self.scan
+            .as_ref()
+            .map(|scan| (scan.dir.as_path(), scan.progress.as_ref()))
+    }
+
+    /// Directory scanned or being scanned
+    pub fn scanned_dir(&self) -> Option<&Path> {
+        match (&self.scan, &self.root) {
+            (Some(scan), _) => Some(&scan.dir),
+            (None, Some((dir, _))) => Some(dir),
+            (None, None) => None,
+        }
+    }
+
+    /// Directory being shown, with its path
+    pub fn dir(&self) -> Option<(PathBuf, &UsageEntry)> {
+        let (root, mut entry) = match &self.root {
+            Some((root, entry)) => (root, entry),
+            None => return None,
+        };
+        let mut path = root.clone();
+        for &idx in &self.trail {
+            entry = &entry.children[idx];
+            path.push(&entry.name);
+        }
+        Some((path, entry))
+    }
+
+    /// Entry under the cursor, with its path
+    pub fn selected(&self) -> Option<(PathBuf, &UsageEntry)> {
+        let (path, dir) = self.dir()?;
+        let entry = dir.children.get(self.list_state.selected()?)?;
+        Some((path.join(&entry.name), entry))
+    }
+
+    pub fn move_cursor(&mut self, down: bool) {
+        let len = self.dir().map_or(0, |(_, dir)| dir.children.len());
+        let Some(idx) = self.list_state.selected() else {
+            return;
+        };
+        let idx = if down {
+            (idx + 1).min(len.saturating_sub(1))
+        } else {
+            idx.saturating_sub(1)
+        };
+        self.list_state.select(Some(idx));
+    }
+
+    /// Show the selected directory
+    pub fn enter(&mut self) {
+        let Some(idx) = self.list_state.selected() else {
+            return;
+        };
+        let Some((_, entry)) = self.selected() else {
+            return;
+        };
+        if !entry.is_dir {
+            return;
+        }
+        let empty = entry.children.is_empty();
+        self.trail.push(idx);
+        self.list_state = ListState::default().with_selected((!empty).then_some(0));
+    }
+
+    /// Show the parent of the current directory, with the cursor on the one left
+    pub fn leave(&mut self) {
+        if let Some(idx) = self.trail.pop() {
+            self.list_state = ListState::default().with_selected(Some(idx));
+        }
+    }
+
+    /// Drop the entry at `path` after it was removed from disk, taking its size off the
+    /// directories above it. The view goes up out of it if it was inside.
+    pub fn remove(&mut self, path: &Path) {
+        let Some((root, root_entry)) = &mut self.root else {
+            return;
+        };
+        let Ok(relative) = path.strip_prefix(&*root) else {
+            return;
+        };
+        // child indices leading to the entry
+        let mut trail = Vec::new();
+        let mut entry = &*root_entry;
+        for name in relative.iter() {
+            let Some(idx) = entry.children.iter().position(|child| child.name == name) else {
+                return;
+            };
+            trail.push(idx);
+            entry = &entry.children[idx];
+        }
+        let (Some((&idx, parents)), bytes) = (trail.split_last(), entry.bytes) else {
+            return;
+        };
+        let mut dir = root_entry;
+        dir.bytes -= bytes;
+        for &parent in parents {
+            dir = &mut dir.children[parent];
+            dir.bytes -= bytes;
+        }
+        dir.children.remove(idx);
+        let len = dir.children.len();
+
+        let depth = parents.len();
+        if !self.trail.starts_with(parents) {
+            return;
+        }
+        if self.trail.len() == depth {
+            // it was in the directory shown
+            let selected = self.list_state.selected().map(|selected| {
+                let selected = if selected > idx {
+                    selected - 1
+                } else {
+                    selected
+                };
+                selected.min(len.saturating_sub(1))
+            });
+            self.list_state.select(selected.filter(|_| len > 0));
+        } else if self.trail[depth] == idx {
+            // the directory shown was inside it
+            self.trail.truncate(depth);
+            self.list_state =
+                ListState::default().with_selected((len > 0).then(|| idx.min(len - 1)));
+        } else if self.trail[depth] > idx {
+            self.trail[depth] -= 1;
+        }
+    }
+}
+
+/// Find the size of everything under `dir`, staying on its filesystem and counting each
+/// hard-linked file once
+fn walk(dir: &Path, cancel: &AtomicBool, progress: &ScanProgress) -> UsageEntry {
+    let found = Mutex::new(Vec::new());
+    walk_sizes(dir, cancel, |entry| {
+        let Some(entry) = entry else {
+            progress.skipped.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+        // the scanned directory itself is put together at the end
+        if entry.depth == 0 {
+            return;
+        }
+        if !entry.is_dir {
+            progress.files.fetch_add(1, Ordering::Relaxed);
+            progress.bytes.fetch_add(entry.bytes, Ordering::Relaxed);
+        }
+        found
+            .lock()
+            .unwrap()
+            .push((entry.depth, entry.path, entry.bytes, entry.is_dir));
+    });
+    assemble(dir, found.into_inner().unwrap())
+}
+
+/// Put the flat list of `(depth, path, bytes, is_dir)` found under `dir` back together as a
+/// tree, adding up the directory totals deepest first
+fn assemble(dir: &Path, mut found: Vec<(usize, PathBuf, u64, bool)>) -> UsageEntry {
+    found.sort_by_key(|(depth, ..)| Reverse(*depth));
+    let mut children: HashMap<PathBuf, Vec<UsageEntry>> = HashMap::new();
+    let finish = |mut entries: Vec<UsageEntry>| -> (u64, Vec<UsageEntry>) {
+        entries.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.name.cmp(&b.name)));
+        (entries.iter().map(|entry| entry.bytes).sum(), entries)
+    };
+    for (_, path, bytes, is_dir) in found {
+        let (bytes, entries) = match children.remove(&path) {
+            Some(entries) => finish(entries),
+            None => (bytes, Vec::new()),
+        };
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            continue;
+        };
+        children
+            .entry(parent.to_path_buf())
+            .or_default()
+            .push(UsageEntry {
+                name: name.to_os_string(),
+                bytes,
+                is_dir,
+                children: entries,
+            });
+    }
+    let (bytes, entries) = finish(children.remove(dir).unwrap_or_default());
+    UsageEntry {
+        name: dir.file_name().unwrap_or_default().to_os_string(),
+        bytes,
+        is_dir: true,
+        children: entries,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        time::{Duration, Instant},
+    };
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Usage of `/d` made up of `a` (dir holding `x` 30 and `y` 10), `b` 50 and `c` 5
+    fn usage() -> DiskUsage {
+        let dir = Path::new("/d");
+        let found = vec![
+            (1, dir.join("b"), 50, false),
+            (2, dir.join("a/x"), 30, false),
+            (1, dir.join("a"), 0, true),
+            (1, dir.join("c"), 5, false),
+            (2, dir.join("a/y"), 10, false),
+        ];
+        DiskUsage {
+            root: Some((dir.to_path_buf(), assemble(dir, found))),
+            list_state: ListState::default().with_selected(Some(0)),
+            ..DiskUsage::default()
+        }
+    }
+
+    fn names(entry: &UsageEntry) -> Vec<&str> {
+        entry
+            .children
+            .iter()
+            .map(|child| child.name.to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn assembles_largest_first_with_totals() {
+        let usage = usage();
+        let (path, root) = usage.dir().unwrap();
+        assert_eq!(path, Path::new("/d"));
+        assert_eq!(root.bytes, 95);
+        assert_eq!(names(root), ["b", "a", "c"]);
+        let a = &root.children[1];
+        assert!(a.is_dir);
+        assert_eq!(a.bytes, 40);
+        assert_eq!(names(a), ["x", "y"]);
+    }
+
+    #[test]
+    fn remove_takes_size_off_parents() {
+        let mut usage = usage();
+        usage.remove(Path::new("/d/a/x"));
+        let (_, root) = usage.dir().unwrap();
+        assert_eq!(root.bytes, 65);
+        assert_eq!(root.children[1].bytes, 10);
+        assert_eq!(names(&root.children[1]), ["y"]);
+        // not under the scanned directory
+        usage.remove(Path::new("/elsewhere/b"));
+        assert_eq!(usage.dir().unwrap().1.bytes, 65);
+    }
+
+    #[test]
+    fn remove_keeps_cursor_on_the_same_entry() {
+        let mut usage = usage();
+        usage.list_state.select(Some(2));
+        usage.remove(Path::new("/d/b"));
+        assert_eq!(usage.selected().unwrap().0, Path::new("/d/c"));
+        usage.remove(Path::new("/d/c"));
+        assert_eq!(usage.selected().unwrap().0, Path::new("/d/a"));
+    }
+
+    #[test]
+    fn remove_leaves_a_removed_directory_being_shown() {
+        let mut usage = usage();
+        usage.list_state.select(Some(1));
+        usage.enter();
+        assert_eq!(usage.dir().unwrap().0, Path::new("/d/a"));
+        usage.remove(Path::new("/d/a"));
+        let (path, root) = usage.dir().unwrap();
+        assert_eq!(path, Path::new("/d"));
+        assert_eq!(names(root), ["b", "c"]);
+        assert_eq!(usage.selected().unwrap().0, Path::new("/d/c"));
+    }
+
+    #[test]
+    fn scans_a_directory() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        fs::create_dir(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub/file"), [0; 100]).unwrap();
+        fs::write(dir.join("file"), [0; 10]).unwrap();
+
+        let mut usage = DiskUsage::default();
+        usage.scan(dir.to_path_buf());
+        let start = Instant::now();
+        while !usage.poll() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
+        }
+        let (_, root) = usage.dir().unwrap();
+        assert_eq!(root.bytes, 110);
+        assert_eq!(names(root), ["sub", "file"]);
+        assert_eq!(usage.skipped, 0);
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+/// Errors from tree and file operations, with the path involved where there is one
+#[derive(Debug)]
+pub enum Error {
+    /// An I/O call failed, on `path` if known
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// Something is already at the path and won't be overwritten
+    AlreadyExists(PathBuf),
+    /// The path was modified since an operation on it was recorded
+    Changed(PathBuf),
+    /// A name or path typed in that can't be used
+    InvalidName(String),
+    /// The tree is empty so there's nothing to act on
+    NothingSelected,
+    /// A symlink to a directory it is itself inside of
+    SymlinkCycle(PathBuf),
+    Other(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io {
+                path: Some(path),
+                source,
+            } => write!(f, "{:?}: {}", path, source),
+            Error::Io { path: None, source } => write!(f, "{}", source),
+            Error::AlreadyExists(path) => write!(f, "{:?} already exists", path),
+            Error::Changed(path) => {
+                write!(f, "{:?} has changed since, refusing to touch it", path)
+            }
+            Error::InvalidName(name) => write!(f, "{:?} is not a valid name", name),
+            Error::NothingSelected => write!(f, "Nothing is selected"),
+            Error::SymlinkCycle(path) => {
+                write!(
+                    f,
+                    "{:?} links to a directory it is inside of, not opening it",
+                    path
+                )
+            }
+            Error::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(source: io::Error) -> Self {
+        Error::Io { path: None, source }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Io { path: None, source } => source,
+            error => io::Error::other(error),
+        }
+    }
+}
+
+/// Attach the path an I/O call was acting on to its error
+pub trait PathContext<T> {
+    fn at(self, path: &Path) -> Result<T>;
+}
+
+impl<T> PathContext<T> for io::Result<T> {
+    fn at(self, path: &Path) -> Result<T> {
+        self.map_err(|source| Error::Io {
+            path: Some(path.to_path_buf()),
+            source,
+        })
+    }
+}
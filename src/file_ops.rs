@@ -0,0 +1,900 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File, FileTimes, Metadata},
+    io::{self, Read, Write},
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+use crate::error::{Error, PathContext, Result};
+
+/// Amount of file data copied per call to `Transfer::step`
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Kind of file transfer being performed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferKind {
+    Copy,
+    Move,
+    /// Remove permanently, `dest` being the same as `src`
+    Delete,
+}
+
+/// Running totals for a transfer, used to display progress
+#[derive(Debug, Default, Clone)]
+pub struct Progress {
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+/// How to handle a destination that already exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// Replace the existing entry, merging when both are directories
+    Overwrite,
+    Skip,
+    /// Use a free name like `name (1).ext` instead
+    RenameWithSuffix,
+    /// Overwrite only when the source was modified more recently
+    OverwriteIfNewer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StepKind {
+    /// Move with a rename, expanded into smaller steps when that's not possible
+    Rename,
+    CreateDir,
+    /// Copy a regular file of the given size
+    CopyFile(u64),
+    CopySymlink,
+    /// Apply the source dir's permissions and times once its contents are written, and remove
+    /// it when moving
+    FinishDir,
+    /// Delete a file, symlink or a directory that has been emptied
+    Remove,
+}
+
+/// A single unit of work in a transfer plan
+#[derive(Debug)]
+struct Step {
+    kind: StepKind,
+    src: PathBuf,
+    dest: PathBuf,
+}
+
+/// A file copy that is partially done
+#[derive(Debug)]
+struct OpenCopy {
+    reader: File,
+    writer: File,
+    src: PathBuf,
+    dest: PathBuf,
+}
+
+/// One of the top level entries being transferred
+#[derive(Debug, Clone)]
+pub struct TransferItem {
+    pub src: PathBuf,
+    pub dest: PathBuf,
+    /// Whether anything existing was overwritten or merged into, which can't be undone
+    pub clobbered: bool,
+    /// Whether the whole item was skipped because of a conflict
+    pub skipped: bool,
+}
+
+/// A recursive copy or move of one or more entries, performed a chunk at a time so progress can
+/// be drawn in between
+#[derive(Debug)]
+pub struct Transfer {
+    pub kind: TransferKind,
+    pub items: Vec<TransferItem>,
+    pub progress: Progress,
+    /// Destination that already exists, the transfer is paused until `resolve` is called
+    pub conflict: Option<PathBuf>,
+    plan: VecDeque<Step>,
+    current: Option<OpenCopy>,
+    /// Space for the chunk being copied, allocated once the first file is opened
+    buffer: Vec<u8>,
+    /// Resolution for every conflict ("apply to all")
+    policy: Option<Resolution>,
+    /// Resolution for just the conflict currently paused on
+    next_resolution: Option<Resolution>,
+}
+
+impl Transfer {
+    /// Set up a transfer of `src` to `dest`
+    pub fn new(kind: TransferKind, src: PathBuf, dest: PathBuf) -> Result<Self> {
+        Self::batch(kind, vec![(src, dest)])
+    }
+
+    /// Set up a transfer of several `(src, dest)` pairs. Copies are planned out up front so
+    /// progress has a total, moves start off as a single rename each.
+    pub fn batch(kind: TransferKind, pairs: Vec<(PathBuf, PathBuf)>) -> Result<Self> {
+        let mut transfer = Self {
+            kind,
+            items: Vec::new(),
+            progress: Progress::default(),
+            conflict: None,
+            plan: VecDeque::new(),
+            current: None,
+            buffer: Vec::new(),
+            policy: None,
+            next_resolution: None,
+        };
+
+        for (src, dest) in pairs {
+            if src == dest && kind != TransferKind::Delete {
+                return Err(Error::Other(format!(
+                    "{:?} would be transferred onto itself",
+                    src
+                )));
+            }
+            match kind {
+                TransferKind::Copy => {
+                    let mut plan = Vec::new();
+                    transfer.plan_copy(&src, &dest, &mut plan)?;
+                    transfer.plan.extend(plan);
+                }
+                TransferKind::Move => {
+                    transfer.progress.files_total += 1;
+                    transfer.plan.push_back(Step {
+                        kind: StepKind::Rename,
+                        src: src.clone(),
+                        dest: dest.clone(),
+                    });
+                }
+                TransferKind::Delete => {
+                    let mut plan = Vec::new();
+                    transfer.plan_delete(&src, &mut plan)?;
+                    transfer.plan.extend(plan);
+                }
+            }
+            transfer.items.push(TransferItem {
+                src,
+                dest,
+                clobbered: false,
+                skipped: false,
+            });
+        }
+        Ok(transfer)
+    }
+
+    /// Set up the permanent deletion of `paths` and everything under them
+    pub fn delete(paths: Vec<PathBuf>) -> Result<Self> {
+        let pairs = paths.into_iter().map(|path| (path.clone(), path)).collect();
+        Self::batch(TransferKind::Delete, pairs)
+    }
+
+    /// Short description of what's being transferred for messages
+    pub fn describe(&self) -> String {
+        match self.items.as_slice() {
+            [item] => format!("{:?}", item.src),
+            items => format!("{} items", items.len()),
+        }
+    }
+
+    /// Whether all the work for this transfer is done
+    pub fn is_done(&self) -> bool {
+        self.plan.is_empty() && self.current.is_none()
+    }
+
+    /// Continue past the current conflict, optionally using the same resolution for the rest
+    pub fn resolve(&mut self, resolution: Resolution, apply_to_all: bool) {
+        if apply_to_all {
+            self.policy = Some(resolution);
+        }
+        self.next_resolution = Some(resolution);
+        self.conflict = None;
+    }
+
+    /// Run the whole transfer right away, failing on any name conflict
+    pub fn finish(&mut self) -> Result<()> {
+        while !self.is_done() {
+            self.step()?;
+            if let Some(dest) = &self.conflict {
+                return Err(Error::AlreadyExists(dest.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop the transfer, removing the file that was partway through being copied. Anything
+    /// already transferred is left in place.
+    pub fn abort(&mut self) {
+        self.plan.clear();
+        self.conflict = None;
+        if let Some(copy) = self.current.take() {
+            drop(copy.writer);
+            let _ = fs::remove_file(&copy.dest);
+        }
+    }
+
+    /// Perform the next chunk of work. Returns true once the transfer has finished.
+    pub fn step(&mut self) -> Result<bool> {
+        if self.conflict.is_some() {
+            return Ok(false);
+        }
+
+        if let Some(copy) = &mut self.current {
+            self.buffer.resize(CHUNK_SIZE, 0);
+            let read = copy.reader.read(&mut self.buffer).at(&copy.src)?;
+            if read > 0 {
+                copy.writer.write_all(&self.buffer[..read]).at(&copy.dest)?;
+                self.progress.bytes_done += read as u64;
+                return Ok(false);
+            }
+            // finished this file
+            let copy = self.current.take().unwrap();
+            let meta = fs::metadata(&copy.src).at(&copy.src)?;
+            copy.writer
+                .set_permissions(meta.permissions())
+                .at(&copy.dest)?;
+            copy.writer
+                .set_times(file_times(&meta).at(&copy.src)?)
+                .at(&copy.dest)?;
+            if self.kind == TransferKind::Move {
+                fs::remove_file(&copy.src).at(&copy.src)?;
+            }
+            self.progress.files_done += 1;
+            return Ok(self.is_done());
+        }
+
+        let mut step = match self.plan.pop_front() {
+            Some(step) => step,
+            None => return Ok(true),
+        };
+        if self.has_conflict(&step) {
+            let resolution = match self.next_resolution.take().or(self.policy) {
+                Some(resolution) => resolution,
+                None => {
+                    // wait for the user to decide
+                    self.conflict = Some(step.dest.clone());
+                    self.plan.push_front(step);
+                    return Ok(false);
+                }
+            };
+            step = match self.apply_resolution(step, resolution)? {
+                Some(step) => step,
+                None => return Ok(self.is_done()),
+            };
+        }
+        self.run_step(step)?;
+        Ok(self.is_done())
+    }
+
+    /// Whether a step would clash with something at its destination. Directories are merged
+    /// into existing ones silently, except for the top level item being transferred.
+    fn has_conflict(&self, step: &Step) -> bool {
+        if matches!(step.kind, StepKind::FinishDir | StepKind::Remove) {
+            return false;
+        }
+        let dest_meta = match fs::symlink_metadata(&step.dest) {
+            Ok(meta) => meta,
+            Err(_) => return false,
+        };
+        !is_merge(step, &dest_meta) || self.is_root(&step.dest)
+    }
+
+    /// Returns the step to carry on with, or None if it is skipped
+    fn apply_resolution(&mut self, mut step: Step, resolution: Resolution) -> Result<Option<Step>> {
+        match resolution {
+            Resolution::Overwrite => Ok(Some(step)),
+            Resolution::Skip => {
+                self.skip(&step);
+                Ok(None)
+            }
+            Resolution::OverwriteIfNewer => {
+                let src_time = modified(&step.src)?;
+                let dest_time = modified(&step.dest)?;
+                if src_time > dest_time {
+                    Ok(Some(step))
+                } else {
+                    self.skip(&step);
+                    Ok(None)
+                }
+            }
+            Resolution::RenameWithSuffix => {
+                let is_dir = fs::symlink_metadata(&step.src).at(&step.src)?.is_dir();
+                let new_dest = free_name(&step.dest, is_dir);
+                // anything planned inside this entry goes along with the rename
+                for planned in self.plan.iter_mut() {
+                    if let Ok(rest) = planned.dest.strip_prefix(&step.dest) {
+                        planned.dest = new_dest.join(rest);
+                    }
+                }
+                if let Some(item) = self.items.iter_mut().find(|item| item.dest == step.dest) {
+                    item.dest = new_dest.clone();
+                }
+                step.dest = new_dest;
+                Ok(Some(step))
+            }
+        }
+    }
+
+    /// Whether `dest` is where one of the top level items goes
+    fn is_root(&self, dest: &Path) -> bool {
+        self.items.iter().any(|item| item.dest == dest)
+    }
+
+    /// Drop a step and everything planned inside it
+    fn skip(&mut self, step: &Step) {
+        if let Some(item) = self.items.iter_mut().find(|item| item.dest == step.dest) {
+            item.skipped = true;
+        }
+        let mut skipped = vec![step];
+        let (inside, rest): (VecDeque<Step>, VecDeque<Step>) = std::mem::take(&mut self.plan)
+            .into_iter()
+            .partition(|planned| planned.dest.starts_with(&step.dest));
+        self.plan = rest;
+        skipped.extend(inside.iter());
+        for skipped_step in skipped {
+            match skipped_step.kind {
+                StepKind::CopyFile(len) => {
+                    self.progress.files_total = self.progress.files_total.saturating_sub(1);
+                    self.progress.bytes_total = self.progress.bytes_total.saturating_sub(len);
+                }
+                StepKind::Rename | StepKind::CopySymlink => {
+                    self.progress.files_total = self.progress.files_total.saturating_sub(1)
+                }
+                // deletions have no conflicts to skip
+                StepKind::CreateDir | StepKind::FinishDir | StepKind::Remove => {}
+            }
+        }
+    }
+
+    fn run_step(&mut self, step: Step) -> Result<()> {
+        // clear the way for anything that was chosen to be overwritten
+        if !matches!(step.kind, StepKind::FinishDir | StepKind::Remove) {
+            if let Ok(dest_meta) = fs::symlink_metadata(&step.dest) {
+                if let Some(item) = self
+                    .items
+                    .iter_mut()
+                    .find(|item| step.dest.starts_with(&item.dest))
+                {
+                    item.clobbered = true;
+                }
+                if !is_merge(&step, &dest_meta) {
+                    delete_path(&step.dest)?;
+                }
+            }
+        }
+
+        match step.kind {
+            StepKind::Rename => match fs::rename(&step.src, &step.dest) {
+                Ok(_) => self.progress.files_done += 1,
+                Err(e) => {
+                    let mut plan = Vec::new();
+                    if e.kind() == io::ErrorKind::CrossesDevices {
+                        // different filesystems, fall back to copy + delete
+                        self.plan_copy(&step.src, &step.dest, &mut plan)?;
+                    } else if step.dest.is_dir() && step.src.is_dir() {
+                        // merging into an existing directory, move the contents one by one
+                        for entry in fs::read_dir(&step.src).at(&step.src)? {
+                            let entry = entry.at(&step.src)?;
+                            self.progress.files_total += 1;
+                            plan.push(Step {
+                                kind: StepKind::Rename,
+                                src: entry.path(),
+                                dest: step.dest.join(entry.file_name()),
+                            });
+                        }
+                        plan.push(Step {
+                            kind: StepKind::FinishDir,
+                            src: step.src.clone(),
+                            dest: step.dest.clone(),
+                        });
+                    } else {
+                        return Err(e).at(&step.src);
+                    }
+                    // the rename itself is replaced by the expanded steps
+                    self.progress.files_total -= 1;
+                    for planned in plan.into_iter().rev() {
+                        self.plan.push_front(planned);
+                    }
+                }
+            },
+            StepKind::CreateDir => match fs::create_dir(&step.dest) {
+                Ok(_) => {}
+                // merging into an existing directory
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists && step.dest.is_dir() => {}
+                Err(e) => return Err(e).at(&step.dest),
+            },
+            StepKind::CopyFile(_) => {
+                self.current = Some(OpenCopy {
+                    reader: File::open(&step.src).at(&step.src)?,
+                    writer: File::create(&step.dest).at(&step.dest)?,
+                    src: step.src,
+                    dest: step.dest,
+                });
+            }
+            StepKind::CopySymlink => {
+                let target = fs::read_link(&step.src).at(&step.src)?;
+                symlink(target, &step.dest).at(&step.dest)?;
+                if self.kind == TransferKind::Move {
+                    fs::remove_file(&step.src).at(&step.src)?;
+                }
+                self.progress.files_done += 1;
+            }
+            StepKind::FinishDir => {
+                let meta = fs::metadata(&step.src).at(&step.src)?;
+                fs::set_permissions(&step.dest, meta.permissions()).at(&step.dest)?;
+                File::open(&step.dest)
+                    .and_then(|dir| dir.set_times(file_times(&meta)?))
+                    .at(&step.dest)?;
+                if self.kind == TransferKind::Move {
+                    // anything skipped is left behind in the source
+                    if let Err(e) = fs::remove_dir(&step.src) {
+                        if e.kind() != io::ErrorKind::DirectoryNotEmpty {
+                            return Err(e).at(&step.src);
+                        }
+                    }
+                }
+            }
+            StepKind::Remove => {
+                if fs::symlink_metadata(&step.src).at(&step.src)?.is_dir() {
+                    fs::remove_dir(&step.src).at(&step.src)?;
+                } else {
+                    fs::remove_file(&step.src).at(&step.src)?;
+                    self.progress.files_done += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Walk the source tree (without following symlinks) and list the steps to copy it
+    fn plan_copy(&mut self, src: &Path, dest: &Path, plan: &mut Vec<Step>) -> Result<()> {
+        let meta = fs::symlink_metadata(src).at(src)?;
+        let file_type = meta.file_type();
+        let step = |kind| Step {
+            kind,
+            src: src.to_path_buf(),
+            dest: dest.to_path_buf(),
+        };
+        if file_type.is_symlink() {
+            self.progress.files_total += 1;
+            plan.push(step(StepKind::CopySymlink));
+        } else if file_type.is_dir() {
+            if dest.starts_with(src) {
+                return Err(Error::Other(format!(
+                    "{:?} can't be copied into itself",
+                    src
+                )));
+            }
+            plan.push(step(StepKind::CreateDir));
+            for entry in fs::read_dir(src).at(src)? {
+                let entry = entry.at(src)?;
+                self.plan_copy(&entry.path(), &dest.join(entry.file_name()), plan)?;
+            }
+            plan.push(step(StepKind::FinishDir));
+        } else if file_type.is_file() {
+            self.progress.files_total += 1;
+            self.progress.bytes_total += meta.len();
+            plan.push(step(StepKind::CopyFile(meta.len())));
+        } else {
+            // reading a FIFO or device would block or never end
+            return Err(Error::Other(format!(
+                "{:?} is a special file, which can't be copied",
+                src
+            )));
+        }
+        Ok(())
+    }
+
+    /// Walk the tree at `path` (without following symlinks) and list the steps to delete it,
+    /// contents before the directory they are in
+    fn plan_delete(&mut self, path: &Path, plan: &mut Vec<Step>) -> Result<()> {
+        let meta = fs::symlink_metadata(path).at(path)?;
+        if meta.is_dir() {
+            for entry in fs::read_dir(path).at(path)? {
+                let entry = entry.at(path)?;
+                self.plan_delete(&entry.path(), plan)?;
+            }
+        } else {
+            self.progress.files_total += 1;
+        }
+        plan.push(Step {
+            kind: StepKind::Remove,
+            src: path.to_path_buf(),
+            dest: path.to_path_buf(),
+        });
+        Ok(())
+    }
+}
+
+/// Whether a step puts a directory where there already is one
+fn is_merge(step: &Step, dest_meta: &Metadata) -> bool {
+    dest_meta.is_dir()
+        && match step.kind {
+            StepKind::CreateDir => true,
+            StepKind::Rename => fs::symlink_metadata(&step.src).is_ok_and(|meta| meta.is_dir()),
+            _ => false,
+        }
+}
+
+/// First free variant of `path` in the form `name (1).ext`, `name (2).ext`, ...
+pub fn free_name(path: &Path, is_dir: bool) -> PathBuf {
+    let (stem, ext) = match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(ext)) if !is_dir => (stem.to_os_string(), Some(ext)),
+        _ => (path.file_name().unwrap_or_default().to_os_string(), None),
+    };
+    let mut n = 1;
+    loop {
+        let mut name = stem.clone();
+        name.push(format!(" ({})", n));
+        if let Some(ext) = ext {
+            name.push(".");
+            name.push(ext);
+        }
+        let candidate = path.with_file_name(name);
+        if fs::symlink_metadata(&candidate).is_err() {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+/// Permanently remove a file or a whole directory tree
+pub fn delete_path(path: &Path) -> Result<()> {
+    if fs::symlink_metadata(path).at(path)?.is_dir() {
+        fs::remove_dir_all(path).at(path)
+    } else {
+        fs::remove_file(path).at(path)
+    }
+}
+
+fn modified(path: &Path) -> Result<std::time::SystemTime> {
+    fs::symlink_metadata(path)
+        .and_then(|meta| meta.modified())
+        .at(path)
+}
+
+fn file_times(meta: &Metadata) -> io::Result<FileTimes> {
+    Ok(FileTimes::new()
+        .set_accessed(meta.accessed()?)
+        .set_modified(meta.modified()?))
+}
+
+/// Format a byte count in human readable units, e.g. `1.5 MiB`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        os::unix::{fs::MetadataExt, net::UnixListener},
+        time::{Duration, SystemTime},
+    };
+
+    use tempfile::{tempdir, tempdir_in};
+
+    use super::*;
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    /// A directory with files, a nested directory and a symlink
+    fn make_tree(dir: &Path) {
+        write(&dir.join("top.txt"), "top");
+        write(&dir.join("a/b/deep.txt"), "deep");
+        write(&dir.join("a/mid.txt"), "mid");
+        symlink("top.txt", dir.join("link")).unwrap();
+    }
+
+    fn check_tree(dir: &Path) {
+        assert_eq!(read(&dir.join("top.txt")), "top");
+        assert_eq!(read(&dir.join("a/b/deep.txt")), "deep");
+        assert_eq!(read(&dir.join("a/mid.txt")), "mid");
+        assert_eq!(
+            fs::read_link(dir.join("link")).unwrap(),
+            Path::new("top.txt")
+        );
+    }
+
+    /// Step `transfer` to the end, answering the conflicts it stops on with `resolutions` in
+    /// order. Returns the destinations it stopped on.
+    fn run(transfer: &mut Transfer, resolutions: &[(Resolution, bool)]) -> Vec<PathBuf> {
+        let mut conflicts = Vec::new();
+        let mut resolutions = resolutions.iter();
+        while !transfer.step().unwrap() {
+            if let Some(dest) = transfer.conflict.clone() {
+                let (resolution, apply_to_all) = resolutions
+                    .next()
+                    .unwrap_or_else(|| panic!("unexpected conflict on {:?}", dest));
+                conflicts.push(dest);
+                transfer.resolve(*resolution, *apply_to_all);
+            }
+        }
+        assert!(
+            resolutions.next().is_none(),
+            "fewer conflicts than expected"
+        );
+        conflicts
+    }
+
+    fn set_modified(path: &Path, time: SystemTime) {
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    }
+
+    #[test]
+    fn copies_nested_tree() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        make_tree(&dir.join("src"));
+        let mut transfer =
+            Transfer::new(TransferKind::Copy, dir.join("src"), dir.join("dest")).unwrap();
+        assert!(run(&mut transfer, &[]).is_empty());
+        check_tree(&dir.join("src"));
+        check_tree(&dir.join("dest"));
+        assert_eq!(transfer.progress.files_done, 4);
+        assert_eq!(transfer.progress.files_total, 4);
+        assert_eq!(transfer.progress.bytes_done, transfer.progress.bytes_total);
+        assert!(!transfer.items[0].clobbered);
+    }
+
+    #[test]
+    fn moves_nested_tree() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        make_tree(&dir.join("src"));
+        let mut transfer =
+            Transfer::new(TransferKind::Move, dir.join("src"), dir.join("dest")).unwrap();
+        assert!(run(&mut transfer, &[]).is_empty());
+        assert!(!dir.join("src").exists());
+        check_tree(&dir.join("dest"));
+    }
+
+    #[test]
+    fn deletes_nested_tree() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        make_tree(&dir.join("tree"));
+        write(&dir.join("file.txt"), "file");
+        let mut transfer = Transfer::delete(vec![dir.join("tree"), dir.join("file.txt")]).unwrap();
+        assert_eq!(transfer.progress.files_total, 5);
+        assert!(run(&mut transfer, &[]).is_empty());
+        assert_eq!(transfer.progress.files_done, 5);
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 0);
+        // links are removed, not followed
+        write(&dir.join("kept/file.txt"), "kept");
+        symlink(dir.join("kept"), dir.join("link")).unwrap();
+        Transfer::delete(vec![dir.join("link")])
+            .unwrap()
+            .finish()
+            .unwrap();
+        assert_eq!(read(&dir.join("kept/file.txt")), "kept");
+    }
+
+    #[test]
+    fn refuses_to_copy_dir_into_itself() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        make_tree(&dir.join("src"));
+        assert!(
+            Transfer::new(TransferKind::Copy, dir.join("src"), dir.join("src/a/copy")).is_err()
+        );
+    }
+
+    #[test]
+    fn refuses_to_copy_special_files() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        write(&dir.join("src/file.txt"), "file");
+        let _socket = UnixListener::bind(dir.join("src/socket")).unwrap();
+        let result = Transfer::new(TransferKind::Copy, dir.join("src"), dir.join("dest"));
+        assert!(matches!(result, Err(Error::Other(_))));
+        assert!(!dir.join("dest").exists());
+    }
+
+    #[test]
+    fn overwrite_replaces_file() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        write(&dir.join("new/f.txt"), "new");
+        write(&dir.join("old/f.txt"), "old");
+        for kind in [TransferKind::Copy, TransferKind::Move] {
+            let mut transfer =
+                Transfer::new(kind, dir.join("new/f.txt"), dir.join("old/f.txt")).unwrap();
+            let conflicts = run(&mut transfer, &[(Resolution::Overwrite, false)]);
+            assert_eq!(conflicts, [dir.join("old/f.txt")]);
+            assert_eq!(read(&dir.join("old/f.txt")), "new");
+            assert!(transfer.items[0].clobbered);
+            assert!(!transfer.items[0].skipped);
+        }
+        assert!(!dir.join("new/f.txt").exists());
+    }
+
+    #[test]
+    fn skip_leaves_both() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        write(&dir.join("new/f.txt"), "new");
+        write(&dir.join("old/f.txt"), "old");
+        for kind in [TransferKind::Copy, TransferKind::Move] {
+            let mut transfer =
+                Transfer::new(kind, dir.join("new/f.txt"), dir.join("old/f.txt")).unwrap();
+            run(&mut transfer, &[(Resolution::Skip, false)]);
+            assert_eq!(read(&dir.join("old/f.txt")), "old");
+            assert_eq!(read(&dir.join("new/f.txt")), "new");
+            assert!(transfer.items[0].skipped);
+            assert!(!transfer.items[0].clobbered);
+            assert_eq!(transfer.progress.files_total, 0);
+        }
+    }
+
+    #[test]
+    fn rename_with_suffix_picks_free_name() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        write(&dir.join("new/f.txt"), "new");
+        write(&dir.join("old/f.txt"), "old");
+        write(&dir.join("old/f (1).txt"), "taken");
+        let mut transfer = Transfer::new(
+            TransferKind::Copy,
+            dir.join("new/f.txt"),
+            dir.join("old/f.txt"),
+        )
+        .unwrap();
+        run(&mut transfer, &[(Resolution::RenameWithSuffix, false)]);
+        assert_eq!(read(&dir.join("old/f.txt")), "old");
+        assert_eq!(read(&dir.join("old/f (2).txt")), "new");
+        assert_eq!(transfer.items[0].dest, dir.join("old/f (2).txt"));
+        assert!(!transfer.items[0].clobbered);
+    }
+
+    #[test]
+    fn rename_with_suffix_takes_dir_contents_along() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        make_tree(&dir.join("new/d.x"));
+        write(&dir.join("old/d.x/top.txt"), "old");
+        let mut transfer =
+            Transfer::new(TransferKind::Copy, dir.join("new/d.x"), dir.join("old/d.x")).unwrap();
+        run(&mut transfer, &[(Resolution::RenameWithSuffix, false)]);
+        // directories keep their whole name, extension included
+        check_tree(&dir.join("old/d.x (1)"));
+        assert_eq!(read(&dir.join("old/d.x/top.txt")), "old");
+        assert!(!dir.join("old/d.x/a").exists());
+    }
+
+    #[test]
+    fn overwrite_if_newer_compares_modified_times() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let (src, dest) = (dir.join("new/f.txt"), dir.join("old/f.txt"));
+        let now = SystemTime::now();
+        write(&src, "src");
+        write(&dest, "dest");
+
+        set_modified(&src, now - Duration::from_secs(60));
+        set_modified(&dest, now);
+        let mut transfer = Transfer::new(TransferKind::Copy, src.clone(), dest.clone()).unwrap();
+        run(&mut transfer, &[(Resolution::OverwriteIfNewer, false)]);
+        assert_eq!(read(&dest), "dest");
+        assert!(transfer.items[0].skipped);
+
+        set_modified(&src, now + Duration::from_secs(60));
+        let mut transfer = Transfer::new(TransferKind::Copy, src.clone(), dest.clone()).unwrap();
+        run(&mut transfer, &[(Resolution::OverwriteIfNewer, false)]);
+        assert_eq!(read(&dest), "src");
+        assert!(transfer.items[0].clobbered);
+    }
+
+    #[test]
+    fn apply_to_all_answers_later_conflicts() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let names = ["a.txt", "b.txt", "c.txt"];
+        for name in names {
+            write(&dir.join("new").join(name), "new");
+            write(&dir.join("old").join(name), "old");
+        }
+        let pairs = || {
+            names
+                .iter()
+                .map(|name| (dir.join("new").join(name), dir.join("old").join(name)))
+                .collect::<Vec<_>>()
+        };
+
+        // just this one, the next conflict is asked about again
+        let mut transfer = Transfer::batch(TransferKind::Copy, pairs()).unwrap();
+        let conflicts = run(
+            &mut transfer,
+            &[
+                (Resolution::Skip, false),
+                (Resolution::Overwrite, false),
+                (Resolution::Skip, false),
+            ],
+        );
+        assert_eq!(conflicts.len(), 3);
+        assert_eq!(read(&dir.join("old/a.txt")), "old");
+        assert_eq!(read(&dir.join("old/b.txt")), "new");
+        assert_eq!(read(&dir.join("old/c.txt")), "old");
+
+        // every conflict after this one too
+        let mut transfer = Transfer::batch(TransferKind::Copy, pairs()).unwrap();
+        let conflicts = run(&mut transfer, &[(Resolution::Overwrite, true)]);
+        assert_eq!(conflicts, [dir.join("old/a.txt")]);
+        for name in names {
+            assert_eq!(read(&dir.join("old").join(name)), "new");
+        }
+        assert!(transfer.items.iter().all(|item| item.clobbered));
+    }
+
+    #[test]
+    fn skip_inside_merge() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        for kind in [TransferKind::Copy, TransferKind::Move] {
+            let (src, dest) = (dir.join("src"), dir.join("dest"));
+            write(&src.join("same.txt"), "new");
+            write(&src.join("sub/only_new.txt"), "new");
+            write(&dest.join("same.txt"), "old");
+            write(&dest.join("sub/only_old.txt"), "old");
+            let mut transfer = Transfer::new(kind, src.clone(), dest.clone()).unwrap();
+            // merging into the top level directory is asked about, its contents only when
+            // they clash
+            let conflicts = run(
+                &mut transfer,
+                &[(Resolution::Overwrite, false), (Resolution::Skip, false)],
+            );
+            assert_eq!(conflicts, [dest.clone(), dest.join("same.txt")]);
+            assert_eq!(read(&dest.join("same.txt")), "old");
+            assert_eq!(read(&dest.join("sub/only_new.txt")), "new");
+            assert_eq!(read(&dest.join("sub/only_old.txt")), "old");
+            assert!(transfer.items[0].clobbered);
+            assert!(!transfer.items[0].skipped);
+            // a move leaves behind what was skipped
+            assert_eq!(read(&src.join("same.txt")), "new");
+            assert_eq!(
+                src.join("sub").exists(),
+                kind == TransferKind::Copy,
+                "{:?}",
+                kind
+            );
+            fs::remove_dir_all(&src).unwrap();
+            fs::remove_dir_all(&dest).unwrap();
+        }
+    }
+
+    #[test]
+    #[ignore = "needs /dev/shm on a different filesystem than the temp directory"]
+    fn move_across_filesystems_copies_and_deletes() {
+        let local = tempdir().unwrap();
+        let remote = tempdir_in("/dev/shm").unwrap();
+        let dev = |path: &Path| fs::metadata(path).unwrap().dev();
+        assert_ne!(dev(local.path()), dev(remote.path()), "same filesystem");
+        let (src, dest) = (local.path().join("src"), remote.path().join("dest"));
+        make_tree(&src);
+        let mut transfer = Transfer::new(TransferKind::Move, src.clone(), dest.clone()).unwrap();
+        assert!(run(&mut transfer, &[]).is_empty());
+        assert!(!src.exists());
+        check_tree(&dest);
+        assert_eq!(transfer.progress.files_done, transfer.progress.files_total);
+    }
+}
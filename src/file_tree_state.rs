@@ -1,104 +1,203 @@
 use ratatui::widgets::ListState;
-use std::cmp::Ordering;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
 
-use crate::tree::{DirectoryStatus, FileObj, FileObjType, TreeAction};
+use crate::{
+    arena::{NodeId, TreeArena},
+    file_tree_widget::ColumnWidths,
+    tree::rebase_paths,
+};
 
 #[derive(Default, Debug)]
 pub struct FileTreeState {
     /// Internally managed underlying Ratatui List state
     pub list_state: ListState,
-    /// Depth into sub dir
-    pub parent_indices: Vec<usize>,
     /// Prev file index
     pub prev_idx: usize,
+    /// Paths marked for batch operations
+    pub selection: HashSet<PathBuf>,
+    /// Entry visual mode was started on, while it is active
+    pub visual_anchor: Option<(PathBuf, VisualKind)>,
+    /// Widths of the detail columns as last worked out, redone once the rows change
+    pub column_widths: Option<ColumnWidths>,
+}
+
+/// Which rows a visual mode range covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisualKind {
+    /// Only entries in the same directory as the anchor (`v`)
+    Entries,
+    /// Every row between the anchor and cursor, including expanded contents (`V`)
+    Lines,
+}
+
+/// Rows a visual mode range covers, worked out once for a pass over the rows
+#[derive(Debug, Clone, Copy)]
+pub struct VisualRange {
+    start: usize,
+    end: usize,
+    kind: VisualKind,
+    /// Directory of the anchor, which `v` keeps to
+    parent: Option<NodeId>,
+}
+
+impl VisualRange {
+    /// Whether the row at `idx` is inside the range
+    fn contains(&self, nodes: &TreeArena, idx: usize) -> bool {
+        let Some(id) = nodes.at(idx) else {
+            return false;
+        };
+        if idx < self.start || idx > self.end {
+            return false;
+        }
+        match self.kind {
+            VisualKind::Lines => true,
+            VisualKind::Entries => nodes.get(id).parent == self.parent,
+        }
+    }
 }
 
 impl FileTreeState {
     pub fn default() -> Self {
         let mut fts = Self {
             list_state: ListState::default(),
-            parent_indices: Vec::new(),
             prev_idx: 0,
+            selection: HashSet::new(),
+            visual_anchor: None,
+            column_widths: None,
         };
         fts.list_state.select_first();
         fts
     }
 
-    pub fn move_down(&mut self, list: &[FileObj]) -> TreeAction {
-        let idx = self.list_state.selected().unwrap();
-
-        let mut count = idx + 1;
-        while count < list.len() {
-            match list[idx].depth.cmp(&list[count].depth) {
-                Ordering::Equal => {
-                    // next file is in the same dir
-                    self.list_state.select(Some(count));
-                    break;
+    /// Start visual mode at the cursor, or switch to `kind` if already in visual mode of another
+    /// kind. Leaving visual mode toggles the covered entries in the selection.
+    pub fn toggle_visual(&mut self, nodes: &TreeArena, kind: VisualKind) {
+        match &self.visual_anchor {
+            Some((anchor, current)) if *current != kind => {
+                self.visual_anchor = Some((anchor.clone(), kind));
+            }
+            Some(_) => {
+                let paths = self.visual_paths(nodes);
+                let all_selected = paths.iter().all(|path| self.selection.contains(path));
+                for path in paths {
+                    if all_selected {
+                        self.selection.remove(&path);
+                    } else {
+                        self.selection.insert(path);
+                    }
                 }
-                // bottom of this sub list
-                Ordering::Greater => break,
-
-                // skipping over a subdir's entries
-                Ordering::Less => count += 1,
+                self.visual_anchor = None;
             }
-        }
-        TreeAction::None
-    }
-
-    pub fn move_up(&mut self, list: &[FileObj]) -> TreeAction {
-        let idx = self.list_state.selected().unwrap();
-        if idx == 0 {
-            return TreeAction::None;
-        }
-        let mut count = idx;
-        while count > 0 {
-            count -= 1;
-            match list[idx].depth.cmp(&list[count].depth) {
-                Ordering::Equal => {
-                    self.list_state.select(Some(count));
-                    return TreeAction::None;
+            None => {
+                let idx = self.list_state.selected().unwrap_or(0);
+                if let Some(id) = nodes.at(idx) {
+                    self.visual_anchor = Some((nodes.obj(id).path.clone(), kind));
                 }
-                // top of this subdir
-                // NOTE: make this select the parent? (by popping)
-                Ordering::Greater => break,
-                Ordering::Less => {}
             }
         }
-        TreeAction::None
     }
 
-    pub fn move_sub_dir(&mut self, list: &[FileObj]) -> TreeAction {
-        let idx = self.list_state.selected().unwrap();
+    /// Whether anything is marked or a visual range is active
+    pub fn has_selection(&self) -> bool {
+        !self.selection.is_empty() || self.visual_anchor.is_some()
+    }
+
+    /// Leave visual mode and unmark everything
+    pub fn clear_selection(&mut self) {
+        self.visual_anchor = None;
+        self.selection.clear();
+    }
 
-        match list[idx].object_type {
-            FileObjType::File => TreeAction::None,
+    /// Whether the row at `idx` is marked or inside `visual`, the range from `visual_range`
+    pub fn is_selected(&self, nodes: &TreeArena, visual: Option<&VisualRange>, idx: usize) -> bool {
+        nodes
+            .at(idx)
+            .is_some_and(|id| self.selection.contains(&nodes.obj(id).path))
+            || visual.is_some_and(|visual| visual.contains(nodes, idx))
+    }
 
-            FileObjType::Directory(DirectoryStatus::Collapsed) => TreeAction::GenerateChild(idx),
-            FileObjType::Directory(DirectoryStatus::Open) => {
-                if list[idx].sub_items_size > 0 {
-                    self.list_state.select_next();
-                    self.parent_indices.push(idx);
-                }
-                TreeAction::None
+    /// Rows between the visual mode anchor and the cursor, if visual mode is active
+    pub fn visual_range(&self, nodes: &TreeArena) -> Option<VisualRange> {
+        let (anchor, kind) = self.visual_anchor.as_ref()?;
+        let anchor = nodes.find(anchor)?;
+        let anchor_idx = nodes.row_of(anchor)?;
+        let cursor = self.list_state.selected().unwrap_or(0);
+        Some(VisualRange {
+            start: anchor_idx.min(cursor),
+            end: anchor_idx.max(cursor),
+            kind: *kind,
+            parent: nodes.get(anchor).parent,
+        })
+    }
+
+    fn visual_paths(&self, nodes: &TreeArena) -> Vec<PathBuf> {
+        let Some(visual) = self.visual_range(nodes) else {
+            return Vec::new();
+        };
+        (visual.start..=visual.end)
+            .filter(|&idx| visual.contains(nodes, idx))
+            .filter_map(|idx| nodes.at(idx))
+            .map(|id| nodes.obj(id).path.clone())
+            .collect()
+    }
+
+    /// Take the marked entries and visual range for a batch operation, or just the entry under
+    /// the cursor if nothing is marked. Entries inside another marked directory are dropped, as
+    /// they go along with it.
+    pub fn take_selection(&mut self, nodes: &TreeArena) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self.selection.drain().collect();
+        paths.extend(self.visual_paths(nodes));
+        self.visual_anchor = None;
+        if paths.is_empty() {
+            if let Some(id) = self.list_state.selected().and_then(|idx| nodes.at(idx)) {
+                paths.push(nodes.obj(id).path.clone());
             }
         }
+        paths.sort();
+        paths.dedup();
+        paths
+            .iter()
+            .filter(|path| {
+                !paths
+                    .iter()
+                    .any(|other| other != *path && path.starts_with(other))
+            })
+            .cloned()
+            .collect()
     }
 
-    pub fn move_parent_dir(&mut self, _list: &[FileObj]) -> TreeAction {
-        // let idx = self.list_state.selected().unwrap();
-        let parent_idx = self.parent_indices.pop();
+    /// Forget about a path that no longer exists
+    pub fn unselect(&mut self, path: &Path) {
+        self.selection
+            .retain(|selected| !selected.starts_with(path));
+        if matches!(&self.visual_anchor, Some((anchor, _)) if anchor.starts_with(path)) {
+            self.visual_anchor = None;
+        }
+    }
 
-        match parent_idx {
-            Some(idx) => self.list_state.select(Some(idx)),
-            None => return TreeAction::GenerateParent,
+    /// Keep marked paths pointing at the right place after `renames` were done together
+    pub fn paths_renamed(&mut self, renames: &[(PathBuf, PathBuf)]) {
+        self.selection = self
+            .selection
+            .drain()
+            .map(|path| rebase_paths(&path, renames).unwrap_or(path))
+            .collect();
+        if let Some((anchor, _)) = &mut self.visual_anchor {
+            if let Some(new_anchor) = rebase_paths(anchor, renames) {
+                *anchor = new_anchor;
+            }
         }
-        TreeAction::None
+        // preview needs reloading from the new path
+        self.prev_idx = usize::MAX;
     }
 
     /// Returns whether the file tree selected item has changed since the last call to this
     /// function
     pub fn index_changed(&mut self) -> bool {
-        let idx = self.list_state.selected().expect("File not selected");
+        let idx = self.list_state.selected().unwrap_or(usize::MAX);
 
         if idx != self.prev_idx {
             self.prev_idx = idx;
@@ -1,46 +1,83 @@
 use ratatui::{
     buffer::Buffer,
     layout::Rect,
-    style::{Color, Style, Styled, Stylize},
-    widgets::{Block, List, ListItem, StatefulWidget},
+    style::{Color, Style},
+    widgets::{Block, List, ListItem, ListState, StatefulWidget},
 };
 
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
 use crate::{
+    arena::{NodeId, Row, TreeArena},
+    details::{Column, DetailView},
+    dir_size::{DirSize, DirSizer},
     file_tree_state::FileTreeState,
-    tree::{DirectoryStatus, FileObj, FileObjType},
+    tree::{display_name, DirectoryStatus, FileObjType},
 };
 
+/// Narrowest the names get in the detail view before columns are dropped to make room
+const MIN_NAME_WIDTH: usize = 20;
+/// Rows kept between the cursor and the top or bottom edge when scrolling
+const SCROLL_PADDING: usize = 3;
+
+/// Widest cell of every detail column over all the rows, along with the changes to the tree and
+/// to the directory sizes it was worked out at
+#[derive(Debug, Clone)]
+pub struct ColumnWidths {
+    changes: (u64, u64),
+    widths: Vec<(Column, usize)>,
+}
+
 #[derive(Debug, Clone)]
 pub struct FileTreeWidget<'a> {
-    /// filetree object with sublists
-    file_list: Vec<FileObj>,
+    /// Entries of the tree, drawn one per row
+    nodes: &'a TreeArena,
     /// Ratatui list component for rendering the output list
     // list: List<'a>,
     style: Style,
-    highlight_style: Style,
     block: Block<'a>,
+    /// Columns drawn right of the names, if the detail view is on
+    details: Option<&'a DetailView>,
+    /// Measured directory sizes, for the size column, and the changes made to them so far
+    dir_sizes: Option<(&'a HashMap<PathBuf, DirSize>, u64)>,
 }
 
 impl<'a> FileTreeWidget<'a> {
-    pub fn new(obj_list: Vec<FileObj>) -> Self {
+    pub fn new(nodes: &'a TreeArena) -> Self {
         Self {
-            file_list: obj_list,
+            nodes,
             // list: List::default(),
             style: Style::default(),
-            highlight_style: Style::default(),
             block: Block::default(),
+            details: None,
+            dir_sizes: None,
         }
     }
 
-    /// Mimic ratatui component styling
-    pub fn style(mut self, style: Style) -> Self {
-        self.style = style;
+    /// Draw the columns of `details` next to the names, if it is shown
+    pub fn details(mut self, details: &'a DetailView) -> Self {
+        self.details = details.shown.then_some(details);
+        self
+    }
+
+    pub fn dir_sizes(mut self, dir_sizer: &'a DirSizer) -> Self {
+        self.dir_sizes = Some((&dir_sizer.sizes, dir_sizer.changes()));
         self
     }
 
-    /// Mimic ratatui component styling for highlight
-    pub fn highlight_style(mut self, highlight_style: Style) -> Self {
-        self.highlight_style = highlight_style;
+    /// Measured size of the directory at `path`, if there is one
+    fn dir_size(&self, path: &Path) -> Option<&'a DirSize> {
+        self.dir_sizes.and_then(|(sizes, _)| sizes.get(path))
+    }
+
+    /// Mimic ratatui component styling
+    pub fn style(mut self, style: Style) -> Self {
+        self.style = style;
         self
     }
 
@@ -50,36 +87,218 @@ impl<'a> FileTreeWidget<'a> {
         self
     }
 
-    /// Helper method to generate the List (of ListItems) for Tree
-    fn generate_list_items(&self, selected_idx: Option<usize>) -> Vec<ListItem> {
+    /// Column the item's name starts at within its row, after the indentation and cursor marker
+    pub fn name_offset(depth: usize) -> u16 {
+        (depth * 3 + 3) as u16
+    }
+
+    /// Helper method to generate the List (of ListItems) for the rows from `offset` on that
+    /// fit in `height`
+    fn generate_list_items(
+        &self,
+        state: &FileTreeState,
+        columns: Option<&[(Column, usize)]>,
+        name_width: usize,
+        offset: usize,
+        height: usize,
+    ) -> Vec<ListItem<'static>> {
         let mut item_list: Vec<ListItem> = Vec::new();
+        let selected_idx = state.list_state.selected();
+        let visual = state.visual_range(self.nodes);
+        // depths of the directories seen, so each row only looks at its parent
+        let mut depths: HashMap<NodeId, usize> = HashMap::new();
+        let mut depth_of = |id: NodeId| -> usize {
+            let parent = self.nodes.get(id).parent;
+            let depth = match parent.and_then(|parent| depths.get(&parent)) {
+                Some(depth) => depth + 1,
+                None => self.nodes.depth(id),
+            };
+            depths.insert(id, depth);
+            depth
+        };
 
         // map each FileObj to a ListItem
-        for (pos, item) in self.file_list.iter().enumerate() {
+        let rows = self
+            .nodes
+            .rows()
+            .iter()
+            .enumerate()
+            .skip(offset)
+            .take(height);
+        for (pos, row) in rows {
+            let id = match *row {
+                Row::Entry(id) => id,
+                Row::Loading(dir) => {
+                    let indent = " ".repeat((depth_of(dir) + 1) * 3);
+                    let placeholder = format!("{}   loading…", indent);
+                    item_list.push(
+                        ListItem::new(placeholder).style(Style::default().fg(Color::DarkGray)),
+                    );
+                    continue;
+                }
+            };
+            let item = self.nodes.obj(id);
+            let depth = depth_of(id);
             // TODO: fix the way the disp_str is calculated
             let disp_str = match selected_idx {
                 Some(idx) => {
                     if idx == pos {
-                        format!("{}> ", " ".repeat(item.depth * 3))
+                        format!("{}> ", " ".repeat(depth * 3))
                     } else {
-                        format!("{}  ", " ".repeat(item.depth * 3))
+                        format!("{}  ", " ".repeat(depth * 3))
                     }
                 }
-                None => format!("{}  ", " ".repeat(item.depth * 3)),
+                None => format!("{}  ", " ".repeat(depth * 3)),
             };
-            let disp_str = match item.object_type {
-                FileObjType::File => format!("{} {}", disp_str, item.name.clone()),
+            let name = item.display_name();
+            let disp_str = match &item.object_type {
+                FileObjType::File => format!("{} {}", disp_str, name),
                 FileObjType::Directory(DirectoryStatus::Collapsed) => {
-                    format!("{} {}/", disp_str, item.name.clone())
+                    format!("{} {}/", disp_str, name)
                 }
                 FileObjType::Directory(DirectoryStatus::Open) => {
-                    format!("{} {}/", disp_str, item.name.clone())
+                    format!("{} {}/", disp_str, name)
+                }
+                FileObjType::DirSymlink { target, .. } => {
+                    let target = display_name(target.as_os_str());
+                    format!("{} {} -> {}/", disp_str, name, target)
+                }
+                FileObjType::FileSymlink { target } | FileObjType::BrokenSymlink { target } => {
+                    let target = display_name(target.as_os_str());
+                    format!("{} {} -> {}", disp_str, name, target)
+                }
+            };
+            let disp_str = match columns {
+                Some(columns) => {
+                    let mut line = fit_width(&disp_str, name_width);
+                    for (column, column_width) in columns {
+                        let cell = self.details.map_or_else(String::new, |details| {
+                            details.cell(*column, item.metadata.as_ref(), self.dir_size(&item.path))
+                        });
+                        line.push_str(&format!("  {:>1$}", cell, column_width));
+                    }
+                    line
                 }
+                None => disp_str,
             };
-            item_list.push(ListItem::new(disp_str).style(Style::default().fg(Color::White)));
+            let style = if state.is_selected(self.nodes, visual.as_ref(), pos) {
+                Style::default().fg(Color::Yellow).bg(Color::DarkGray)
+            } else if matches!(item.object_type, FileObjType::BrokenSymlink { .. }) {
+                Style::default().fg(Color::Red)
+            } else {
+                Style::default().fg(Color::White)
+            };
+            item_list.push(ListItem::new(disp_str).style(style));
         }
         item_list
     }
+
+    /// Widest cell of every column over all the rows, worked out again only if the rows or the
+    /// directory sizes changed since `cached` was
+    fn column_widths<'c>(
+        &self,
+        details: &DetailView,
+        cached: &'c mut Option<ColumnWidths>,
+    ) -> &'c [(Column, usize)] {
+        let changes = (
+            self.nodes.changes(),
+            self.dir_sizes.map_or(0, |(_, changes)| changes),
+        );
+        if cached
+            .as_ref()
+            .is_some_and(|cached| cached.changes == changes)
+        {
+            return &cached.as_ref().unwrap().widths;
+        }
+        let widths = Column::ALL
+            .iter()
+            .map(|column| {
+                let widest = self
+                    .nodes
+                    .rows()
+                    .iter()
+                    .filter_map(|row| match row {
+                        Row::Entry(id) => Some(*id),
+                        Row::Loading(_) => None,
+                    })
+                    .map(|id| {
+                        let item = self.nodes.obj(id);
+                        let metadata = item.metadata.as_ref();
+                        details
+                            .cell(*column, metadata, self.dir_size(&item.path))
+                            .width()
+                    })
+                    .max()
+                    .unwrap_or(0);
+                (*column, widest)
+            })
+            .collect();
+        &cached.insert(ColumnWidths { changes, widths }).widths
+    }
+
+    /// Detail columns that fit in `width` with their widths, dropping the rightmost ones until
+    /// the names have room, and the width left for the names
+    fn fit_columns(
+        &self,
+        width: usize,
+        cached: &mut Option<ColumnWidths>,
+    ) -> (Option<Vec<(Column, usize)>>, usize) {
+        let Some(details) = self.details else {
+            return (None, width);
+        };
+        let widths = self.column_widths(details, cached);
+        let mut columns: Vec<(Column, usize)> = widths
+            .iter()
+            .filter(|(column, _)| details.columns.contains(column))
+            .copied()
+            .collect();
+        // each column is set off by two spaces
+        let used = |columns: &[(Column, usize)]| -> usize {
+            columns.iter().map(|(_, widest)| widest + 2).sum()
+        };
+        while !columns.is_empty() && width < used(&columns) + MIN_NAME_WIDTH {
+            columns.pop();
+        }
+        let name_width = width.saturating_sub(used(&columns));
+        (Some(columns), name_width)
+    }
+}
+
+/// First of `len` rows to draw in `height`, moved as little as possible from `offset` to keep
+/// the `selected` row `SCROLL_PADDING` rows away from the edges, as far as there are rows
+fn window_offset(offset: usize, selected: Option<usize>, height: usize, len: usize) -> usize {
+    let last_offset = len.saturating_sub(height);
+    let Some(selected) = selected else {
+        return offset.min(last_offset);
+    };
+    let padding = SCROLL_PADDING.min(height.saturating_sub(1) / 2);
+    let offset = if selected + padding >= offset + height {
+        selected + padding + 1 - height
+    } else {
+        offset.min(selected.saturating_sub(padding))
+    };
+    offset.min(last_offset)
+}
+
+/// `text` cut or padded with spaces to exactly `width` columns, ending in `…` if it was cut
+fn fit_width(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return format!("{}{}", text, " ".repeat(width - text.width()));
+    }
+    let mut fitted = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let char_width = c.width().unwrap_or(0);
+        if used + char_width + 1 > width {
+            break;
+        }
+        fitted.push(c);
+        used += char_width;
+    }
+    fitted.push('…');
+    used += 1;
+    fitted.push_str(&" ".repeat(width.saturating_sub(used)));
+    fitted
 }
 
 // TODO: is this & or &mut or as is??
@@ -87,17 +306,29 @@ impl StatefulWidget for FileTreeWidget<'_> {
     type State = FileTreeState;
 
     fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
-        // generate ListItems
-        // TODO: fix this clone nonsense, i think generate_list_items needs changing
-        let clon = self.clone();
-        let list_items_formatted = clon.generate_list_items(state.list_state.selected());
+        let inner = self.block.inner(area);
+        let (width, height) = (inner.width as usize, inner.height as usize);
+        let len = self.nodes.rows().len();
+        // keep the cursor on a row, like the list would
+        let selected = state
+            .list_state
+            .selected()
+            .and_then(|idx| len.checked_sub(1).map(|last| idx.min(last)));
+        state.list_state.select(selected);
+        let offset = window_offset(state.list_state.offset(), selected, height, len);
+        *state.list_state.offset_mut() = offset;
+
+        // only generate ListItems for the rows in view
+        let (columns, name_width) = self.fit_columns(width, &mut state.column_widths);
+        let list_items_formatted =
+            self.generate_list_items(state, columns.as_deref(), name_width, offset, height);
+        let mut window_state =
+            ListState::default().with_selected(selected.and_then(|idx| idx.checked_sub(offset)));
 
         let list = List::new(list_items_formatted)
             .style(self.style)
-            .highlight_style(self.highlight_style)
-            .block(self.block)
-            .scroll_padding(3);
+            .block(self.block);
 
-        StatefulWidget::render(list, area, buf, &mut state.list_state);
+        StatefulWidget::render(list, area, buf, &mut window_state);
     }
 }
@@ -0,0 +1,233 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+/// Which entries are left out of directory listings
+#[derive(Debug, Default)]
+pub struct EntryFilter {
+    /// Hide names starting with `.`
+    pub hide_dotfiles: bool,
+    /// Hide entries matched by `.gitignore`, `.ignore` and `.git/info/exclude` rules
+    pub hide_ignored: bool,
+    /// Ignore files found in each directory, highest precedence first, by whether the
+    /// directory was looked at as part of a repository
+    dir_rules: HashMap<(PathBuf, bool), Vec<Gitignore>>,
+    /// Repository root each directory belongs to, if any
+    repo_roots: HashMap<PathBuf, Option<PathBuf>>,
+    /// `.git/info/exclude` of each repository
+    excludes: HashMap<PathBuf, Gitignore>,
+}
+
+impl EntryFilter {
+    /// Whether the entry at `path` should be shown
+    pub fn allows(&mut self, path: &Path, is_dir: bool) -> bool {
+        if self.hide_dotfiles
+            && path
+                .file_name()
+                .is_some_and(|name| name.as_encoded_bytes().starts_with(b"."))
+        {
+            return false;
+        }
+        !(self.hide_ignored && self.is_ignored(path, is_dir))
+    }
+
+    /// Short description of what is hidden, for the status bar
+    pub fn describe(&self) -> String {
+        match (self.hide_dotfiles, self.hide_ignored) {
+            (false, false) => "showing all".to_string(),
+            (true, false) => "dotfiles hidden".to_string(),
+            (false, true) => "ignored hidden".to_string(),
+            (true, true) => "dotfiles and ignored hidden".to_string(),
+        }
+    }
+
+    /// Forget cached ignore rules, so edits to ignore files are picked up
+    pub fn clear_cache(&mut self) {
+        self.dir_rules.clear();
+        self.repo_roots.clear();
+        self.excludes.clear();
+    }
+
+    /// Check the rules from the entry's own directory upwards. The nearest file with a rule
+    /// matching the entry decides, so nested ignore files and `!` negations override outer ones.
+    fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        let Some(dir) = path.parent() else {
+            return false;
+        };
+        let repo = self.repo_root(dir);
+        for ancestor in dir.ancestors() {
+            // `.ignore` files still apply above a repository, `.gitignore` files don't
+            let in_repo = repo
+                .as_deref()
+                .is_some_and(|repo| ancestor.starts_with(repo));
+            for rules in self.rules_in(ancestor, in_repo) {
+                let matched = rules.matched_path_or_any_parents(path, is_dir);
+                if !matched.is_none() {
+                    return matched.is_ignore();
+                }
+            }
+        }
+        match repo {
+            Some(repo) => self
+                .exclude(&repo)
+                .matched_path_or_any_parents(path, is_dir)
+                .is_ignore(),
+            None => false,
+        }
+    }
+
+    /// Ignore files in `dir`, `.ignore` before `.gitignore` as it takes precedence
+    fn rules_in(&mut self, dir: &Path, in_repo: bool) -> &[Gitignore] {
+        self.dir_rules
+            .entry((dir.to_path_buf(), in_repo))
+            .or_insert_with(|| {
+                let mut names = vec![".ignore"];
+                if in_repo {
+                    names.push(".gitignore");
+                }
+                names
+                    .into_iter()
+                    .map(|name| dir.join(name))
+                    .filter(|file| file.is_file())
+                    // unparsable lines are skipped, the rest still applies
+                    .map(|file| Gitignore::new(file).0)
+                    .collect()
+            })
+    }
+
+    /// Nearest directory at or above `dir` with a `.git` in it
+    fn repo_root(&mut self, dir: &Path) -> Option<PathBuf> {
+        if let Some(root) = self.repo_roots.get(dir) {
+            return root.clone();
+        }
+        let root = dir
+            .ancestors()
+            .find(|ancestor| ancestor.join(".git").exists())
+            .map(Path::to_path_buf);
+        self.repo_roots.insert(dir.to_path_buf(), root.clone());
+        root
+    }
+
+    fn exclude(&mut self, repo: &Path) -> &Gitignore {
+        self.excludes.entry(repo.to_path_buf()).or_insert_with(|| {
+            let mut builder = GitignoreBuilder::new(repo);
+            builder.add(repo.join(".git/info/exclude"));
+            builder.build().unwrap_or_else(|_| Gitignore::empty())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Filter hiding ignored entries only
+    fn ignoring() -> EntryFilter {
+        EntryFilter {
+            hide_ignored: true,
+            ..EntryFilter::default()
+        }
+    }
+
+    /// Write `contents` to `path`, making the directories on the way
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn nested_gitignore_overrides_outer_one() {
+        let temp = tempdir().unwrap();
+        let repo = temp.path();
+        fs::create_dir(repo.join(".git")).unwrap();
+        write(&repo.join(".gitignore"), "*.log\nbuild/\n");
+        write(&repo.join("sub/.gitignore"), "!keep.log\n");
+        let mut filter = ignoring();
+
+        assert!(!filter.allows(&repo.join("a.log"), false));
+        assert!(filter.allows(&repo.join("a.txt"), false));
+        assert!(filter.allows(&repo.join("sub/keep.log"), false));
+        assert!(!filter.allows(&repo.join("sub/other.log"), false));
+        // only directories match `build/`, and so does what's inside them
+        assert!(!filter.allows(&repo.join("build"), true));
+        assert!(filter.allows(&repo.join("sub/build"), false));
+        assert!(!filter.allows(&repo.join("build/out.txt"), false));
+    }
+
+    #[test]
+    fn dot_ignore_takes_precedence_over_gitignore() {
+        let temp = tempdir().unwrap();
+        let repo = temp.path();
+        fs::create_dir(repo.join(".git")).unwrap();
+        write(&repo.join(".gitignore"), "generated\nlocal\n");
+        write(&repo.join(".ignore"), "!generated\n");
+        let mut filter = ignoring();
+
+        assert!(filter.allows(&repo.join("generated"), false));
+        assert!(!filter.allows(&repo.join("local"), false));
+    }
+
+    #[test]
+    fn gitignore_only_applies_inside_a_repository() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        write(&dir.join(".gitignore"), "by-git\n");
+        write(&dir.join(".ignore"), "by-ignore\n");
+        let mut filter = ignoring();
+
+        assert!(filter.allows(&dir.join("by-git"), false));
+        assert!(!filter.allows(&dir.join("by-ignore"), false));
+        // `.ignore` above a repository still applies inside it
+        fs::create_dir_all(dir.join("repo/.git")).unwrap();
+        assert!(!filter.allows(&dir.join("repo/by-ignore"), false));
+        assert!(filter.allows(&dir.join("repo/by-git"), false));
+    }
+
+    #[test]
+    fn git_info_exclude_applies_under_ignore_files() {
+        let temp = tempdir().unwrap();
+        let repo = temp.path();
+        write(&repo.join(".git/info/exclude"), "secret\nnotes\n");
+        write(&repo.join("sub/.gitignore"), "!notes\n");
+        let mut filter = ignoring();
+
+        assert!(!filter.allows(&repo.join("secret"), false));
+        assert!(!filter.allows(&repo.join("sub/secret"), false));
+        assert!(!filter.allows(&repo.join("notes"), false));
+        assert!(filter.allows(&repo.join("sub/notes"), false));
+    }
+
+    #[test]
+    fn picks_up_edits_once_cache_is_cleared() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        write(&dir.join(".ignore"), "a\n");
+        let mut filter = ignoring();
+        assert!(!filter.allows(&dir.join("a"), false));
+
+        write(&dir.join(".ignore"), "b\n");
+        assert!(!filter.allows(&dir.join("a"), false));
+        filter.clear_cache();
+        assert!(filter.allows(&dir.join("a"), false));
+        assert!(!filter.allows(&dir.join("b"), false));
+    }
+
+    #[test]
+    fn hides_dotfiles_separately() {
+        let mut filter = EntryFilter {
+            hide_dotfiles: true,
+            ..EntryFilter::default()
+        };
+        assert!(!filter.allows(Path::new("/x/.hidden"), false));
+        assert!(filter.allows(Path::new("/x/shown"), false));
+        filter.hide_dotfiles = false;
+        assert!(filter.allows(Path::new("/x/.hidden"), false));
+    }
+}
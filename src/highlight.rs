@@ -0,0 +1,146 @@
+use std::path::Path;
+
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+};
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{self, FontStyle, Theme, ThemeSet},
+    parsing::{SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+
+/// Theme used until another one is picked
+const DEFAULT_THEME: &str = "base16-ocean.dark";
+/// Lines past this are shown without highlighting, as working out the colors of a long file
+/// takes a while
+const MAX_HIGHLIGHTED_LINES: usize = 500;
+
+/// Colors source code by its language, picked from the file extension or a `#!` line
+pub struct Highlighter {
+    syntaxes: SyntaxSet,
+    /// Themes to pick from by index, sorted by name
+    themes: Vec<(String, Theme)>,
+}
+
+impl std::fmt::Debug for Highlighter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Highlighter")
+            .field("themes", &self.themes.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Highlighter {
+    fn default() -> Self {
+        Self {
+            // the bundled set only has a handful of languages, this one adds TOML and more
+            syntaxes: two_face::syntax::extra_newlines(),
+            themes: ThemeSet::load_defaults().themes.into_iter().collect(),
+        }
+    }
+}
+
+impl Highlighter {
+    /// Theme used until another one is picked
+    pub fn default_theme(&self) -> usize {
+        self.themes
+            .iter()
+            .position(|(name, _)| name == DEFAULT_THEME)
+            .unwrap_or(0)
+    }
+
+    /// Theme after `theme`, going back to the first after the last
+    pub fn next_theme(&self, theme: usize) -> usize {
+        (theme + 1) % self.themes.len()
+    }
+
+    pub fn theme_name(&self, theme: usize) -> &str {
+        &self.themes[theme].0
+    }
+
+    /// Background of `theme`, to draw highlighted text on
+    pub fn background(&self, theme: usize) -> Option<Color> {
+        self.themes[theme].1.settings.background.map(rgb)
+    }
+
+    /// Language of the file at `path`, if it's one that can be highlighted
+    fn syntax(&self, path: &Path, text: &str) -> Option<&SyntaxReference> {
+        let by_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| self.syntaxes.find_syntax_by_extension(name));
+        let by_extension = || {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .and_then(|ext| self.syntaxes.find_syntax_by_extension(ext))
+        };
+        let by_shebang = || {
+            let first = text.lines().next()?;
+            first
+                .starts_with("#!")
+                .then(|| self.syntaxes.find_syntax_by_first_line(first))
+                .flatten()
+        };
+        by_name.or_else(by_extension).or_else(by_shebang)
+    }
+
+    /// `text` split into lines, colored with `theme` by the language of the file at `path`.
+    /// Lines in a language that isn't known are left plain.
+    pub fn highlight(&self, path: &Path, text: &str, theme: usize) -> Vec<Line<'static>> {
+        let Some(syntax) = self.syntax(path, text) else {
+            return text
+                .lines()
+                .map(|line| Line::from(line.to_string()))
+                .collect();
+        };
+        let mut highlighter = HighlightLines::new(syntax, &self.themes[theme].1);
+        let mut lines = Vec::new();
+        for (number, line) in LinesWithEndings::from(text).enumerate() {
+            let ranges = match highlighter.highlight_line(line, &self.syntaxes) {
+                Ok(ranges) if number < MAX_HIGHLIGHTED_LINES => ranges,
+                // carry on plain from here
+                _ => {
+                    lines.extend(
+                        text.lines()
+                            .skip(number)
+                            .map(|line| Line::from(line.to_string())),
+                    );
+                    break;
+                }
+            };
+            let spans: Vec<Span> = ranges
+                .into_iter()
+                .map(|(style, piece)| {
+                    let piece = piece.trim_end_matches(['\n', '\r']).to_string();
+                    Span::styled(piece, span_style(style))
+                })
+                .collect();
+            lines.push(Line::from(spans));
+        }
+        lines
+    }
+}
+
+fn rgb(color: highlighting::Color) -> Color {
+    Color::Rgb(color.r, color.g, color.b)
+}
+
+/// Foreground and font of a syntect style. The background is left to the pane, so the
+/// theme's background shows as one block rather than behind each piece of text.
+fn span_style(style: highlighting::Style) -> Style {
+    let mut modifier = Modifier::empty();
+    if style.font_style.contains(FontStyle::BOLD) {
+        modifier |= Modifier::BOLD;
+    }
+    if style.font_style.contains(FontStyle::ITALIC) {
+        modifier |= Modifier::ITALIC;
+    }
+    if style.font_style.contains(FontStyle::UNDERLINE) {
+        modifier |= Modifier::UNDERLINED;
+    }
+    Style::default()
+        .fg(rgb(style.foreground))
+        .add_modifier(modifier)
+}
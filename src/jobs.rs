@@ -0,0 +1,467 @@
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::Result,
+    file_ops::{Progress, Resolution, Transfer, TransferKind},
+};
+
+/// Number of transfers that can run at the same time
+const WORKERS: usize = 2;
+
+pub type JobId = usize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobState {
+    Queued,
+    Running,
+    Paused,
+    /// Stopped on a destination that already exists, see `Job::conflict`
+    WaitingOnConflict,
+    Failed(String),
+    Cancelled,
+    Done,
+}
+
+/// Snapshot of a transfer job shared between the UI and the worker running it
+#[derive(Debug)]
+pub struct Job {
+    pub id: JobId,
+    pub kind: TransferKind,
+    pub description: String,
+    pub state: JobState,
+    pub progress: Progress,
+    pub conflict: Option<PathBuf>,
+    /// Time spent actually running, so pauses don't drag the throughput down
+    active_time: Duration,
+    running_since: Option<Instant>,
+    pause_requested: bool,
+    cancel_requested: bool,
+    resolution: Option<(Resolution, bool)>,
+}
+
+impl Job {
+    /// Whether the job still has work left to do
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self.state,
+            JobState::Queued | JobState::Running | JobState::Paused | JobState::WaitingOnConflict
+        )
+    }
+
+    /// Bytes copied per second while running
+    pub fn throughput(&self) -> f64 {
+        let elapsed = self.active_time
+            + self
+                .running_since
+                .map_or(Duration::ZERO, |since| since.elapsed());
+        if elapsed.is_zero() {
+            return 0.0;
+        }
+        self.progress.bytes_done as f64 / elapsed.as_secs_f64()
+    }
+
+    /// Estimated time left at the current throughput
+    pub fn eta(&self) -> Option<Duration> {
+        let throughput = self.throughput();
+        if throughput <= 0.0 {
+            return None;
+        }
+        let remaining = self
+            .progress
+            .bytes_total
+            .saturating_sub(self.progress.bytes_done);
+        Some(Duration::from_secs_f64(remaining as f64 / throughput))
+    }
+
+    fn set_running(&mut self, running: bool) {
+        match (running, self.running_since) {
+            (true, None) => self.running_since = Some(Instant::now()),
+            (false, Some(since)) => {
+                self.active_time += since.elapsed();
+                self.running_since = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A job and the condvar its worker sleeps on while paused or waiting on a conflict
+type JobHandle = Arc<(Mutex<Job>, Condvar)>;
+
+/// A transfer that is no longer running, handed back to the UI
+#[derive(Debug)]
+pub struct FinishedJob {
+    pub id: JobId,
+    pub transfer: Transfer,
+    pub result: Result<()>,
+    /// Stopped early by the user, anything done so far stays in place
+    pub cancelled: bool,
+}
+
+/// Shared list of jobs waiting for a worker
+type Queue = Arc<(Mutex<VecDeque<(JobHandle, Transfer)>>, Condvar)>;
+
+/// File transfers running on worker threads so the UI doesn't block
+#[derive(Debug, Default)]
+pub struct JobQueue {
+    jobs: Vec<JobHandle>,
+    queue: Queue,
+    finished: Option<(Sender<FinishedJob>, Receiver<FinishedJob>)>,
+    /// Jobs whose results haven't been collected yet
+    outstanding: usize,
+    next_id: JobId,
+}
+
+impl JobQueue {
+    /// Queue up a transfer to run in the background
+    pub fn submit(&mut self, transfer: Transfer) -> JobId {
+        self.start_workers();
+        self.next_id += 1;
+        self.outstanding += 1;
+        let job = Arc::new((
+            Mutex::new(Job {
+                id: self.next_id,
+                kind: transfer.kind,
+                description: transfer.describe(),
+                state: JobState::Queued,
+                progress: transfer.progress.clone(),
+                conflict: None,
+                active_time: Duration::ZERO,
+                running_since: None,
+                pause_requested: false,
+                cancel_requested: false,
+                resolution: None,
+            }),
+            Condvar::new(),
+        ));
+        self.jobs.push(job.clone());
+        let (queue, available) = &*self.queue;
+        queue.lock().unwrap().push_back((job, transfer));
+        available.notify_one();
+        self.next_id
+    }
+
+    /// Spawn the worker threads the first time they're needed
+    fn start_workers(&mut self) {
+        if self.finished.is_some() {
+            return;
+        }
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..WORKERS {
+            let queue = self.queue.clone();
+            let tx = tx.clone();
+            thread::spawn(move || worker(queue, tx));
+        }
+        self.finished = Some((tx, rx));
+    }
+
+    /// Collect jobs that finished, failed or were cancelled since the last call
+    pub fn poll_finished(&mut self) -> Vec<FinishedJob> {
+        let finished: Vec<FinishedJob> = match &self.finished {
+            Some((_, rx)) => rx.try_iter().collect(),
+            None => Vec::new(),
+        };
+        self.outstanding -= finished.len();
+        // successful jobs have nothing more to show, failures stay until cleared
+        self.jobs.retain(|job| {
+            let job = lock(job);
+            !finished.iter().any(|done| done.id == job.id) || job.state != JobState::Done
+        });
+        finished
+    }
+
+    /// Lock every job for reading, in the order they were submitted
+    pub fn jobs(&self) -> Vec<MutexGuard<'_, Job>> {
+        self.jobs.iter().map(lock).collect()
+    }
+
+    /// Whether any job is still running or waiting to be collected, so the UI should keep
+    /// refreshing
+    pub fn is_busy(&self) -> bool {
+        self.outstanding > 0
+    }
+
+    /// First job waiting on the user to resolve a conflict
+    pub fn next_conflict(&self) -> Option<(JobId, PathBuf)> {
+        self.jobs.iter().find_map(|job| {
+            let job = lock(job);
+            match (&job.state, &job.conflict) {
+                (JobState::WaitingOnConflict, Some(path)) if job.resolution.is_none() => {
+                    Some((job.id, path.clone()))
+                }
+                _ => None,
+            }
+        })
+    }
+
+    pub fn resolve(&self, id: JobId, resolution: Resolution, apply_to_all: bool) {
+        self.with_job(id, |job| job.resolution = Some((resolution, apply_to_all)));
+    }
+
+    /// Pause a queued or running job, or resume it if it is paused
+    pub fn toggle_pause(&self, id: JobId) {
+        self.with_job(id, |job| {
+            job.pause_requested = !job.pause_requested;
+            if job.state == JobState::Queued && job.pause_requested {
+                job.state = JobState::Paused;
+            } else if job.state == JobState::Paused && !job.pause_requested {
+                // a worker sets the real state once it picks the job up or wakes
+                job.state = JobState::Queued;
+            }
+        });
+        // a resumed job may be the only one in the queue a worker can take
+        self.queue.1.notify_all();
+    }
+
+    pub fn cancel(&mut self, id: JobId) {
+        self.with_job(id, |job| job.cancel_requested = true);
+        // jobs still in the queue never reach a worker, so they are handed back from here
+        let (queue, _) = &*self.queue;
+        let mut queue = queue.lock().unwrap();
+        if let Some(pos) = queue.iter().position(|(job, _)| lock(job).id == id) {
+            let (job, transfer) = queue.remove(pos).unwrap();
+            lock(&job).state = JobState::Cancelled;
+            if let Some((tx, _)) = &self.finished {
+                let _ = tx.send(FinishedJob {
+                    id,
+                    transfer,
+                    result: Ok(()),
+                    cancelled: true,
+                });
+            }
+        }
+    }
+
+    /// Forget about jobs that failed or were cancelled
+    pub fn clear_inactive(&mut self) {
+        self.jobs.retain(|job| lock(job).is_active());
+    }
+
+    fn with_job(&self, id: JobId, f: impl FnOnce(&mut Job)) {
+        if let Some(handle) = self.jobs.iter().find(|job| lock(job).id == id) {
+            f(&mut lock(handle));
+            handle.1.notify_all();
+        }
+    }
+}
+
+fn lock(job: &JobHandle) -> MutexGuard<'_, Job> {
+    job.0.lock().unwrap()
+}
+
+/// Take jobs off the queue and run them until the app exits
+fn worker(queue: Queue, finished: Sender<FinishedJob>) {
+    loop {
+        let (handle, mut transfer) = {
+            let (queue, available) = &*queue;
+            let mut queue = queue.lock().unwrap();
+            loop {
+                // paused jobs wait in the queue rather than holding up a worker
+                match queue.iter().position(|(job, _)| !lock(job).pause_requested) {
+                    Some(pos) => break queue.remove(pos).unwrap(),
+                    None => queue = available.wait(queue).unwrap(),
+                }
+            }
+        };
+        let id = lock(&handle).id;
+        let result = run_job(&handle, &mut transfer);
+        let cancelled = {
+            let mut job = lock(&handle);
+            job.set_running(false);
+            job.state = match &result {
+                Ok(_) if job.cancel_requested => JobState::Cancelled,
+                Ok(_) => JobState::Done,
+                Err(e) => JobState::Failed(e.to_string()),
+            };
+            job.state == JobState::Cancelled
+        };
+        if finished
+            .send(FinishedJob {
+                id,
+                transfer,
+                result,
+                cancelled,
+            })
+            .is_err()
+        {
+            // UI is gone
+            return;
+        }
+    }
+}
+
+/// Step a transfer to the end, honouring pause, cancel and conflict resolution from the UI
+fn run_job(handle: &JobHandle, transfer: &mut Transfer) -> Result<()> {
+    let (job, wake) = &**handle;
+    loop {
+        {
+            let mut job = job.lock().unwrap();
+            // sleep while paused or waiting on the user
+            loop {
+                if job.cancel_requested {
+                    transfer.abort();
+                    return Ok(());
+                }
+                if let Some((resolution, apply_to_all)) = job.resolution.take() {
+                    transfer.resolve(resolution, apply_to_all);
+                    job.conflict = None;
+                }
+                if job.pause_requested {
+                    job.state = JobState::Paused;
+                } else if transfer.conflict.is_some() {
+                    job.state = JobState::WaitingOnConflict;
+                    job.conflict = transfer.conflict.clone();
+                } else {
+                    break;
+                }
+                job.set_running(false);
+                job = wake.wait(job).unwrap();
+            }
+            job.state = JobState::Running;
+            job.set_running(true);
+        }
+
+        // don't leave a half written file behind looking like a finished one
+        let done = transfer.step().inspect_err(|_| transfer.abort())?;
+        let mut job = job.lock().unwrap();
+        job.progress = transfer.progress.clone();
+        job.description = transfer.describe();
+        if done {
+            return Ok(());
+        }
+    }
+}
+
+/// Format a duration as `m:ss`, or `h:mm:ss` once it's over an hour
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Wait for the job `id` to be handed back
+    fn wait_for(jobs: &mut JobQueue, id: JobId) -> FinishedJob {
+        wait_for_all(jobs, &[id]).pop().unwrap()
+    }
+
+    /// Wait for every job in `ids` to be handed back, returning them in the same order. Several
+    /// can come back in one poll, so they're waited on together.
+    fn wait_for_all(jobs: &mut JobQueue, ids: &[JobId]) -> Vec<FinishedJob> {
+        let start = Instant::now();
+        let mut finished = Vec::new();
+        while start.elapsed() < Duration::from_secs(5) {
+            finished.extend(jobs.poll_finished());
+            if ids
+                .iter()
+                .all(|id| finished.iter().any(|job| job.id == *id))
+            {
+                return ids
+                    .iter()
+                    .map(|id| {
+                        let pos = finished.iter().position(|job| job.id == *id).unwrap();
+                        finished.remove(pos)
+                    })
+                    .collect();
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        panic!("jobs {:?} didn't finish", ids);
+    }
+
+    /// Copy of `dir/name` onto a file that's already there
+    fn conflicting_copy(dir: &Path, name: &str) -> Transfer {
+        let dest = dir.join(format!("{}.copy", name));
+        fs::write(dir.join(name), name).unwrap();
+        fs::write(&dest, "taken").unwrap();
+        Transfer::new(TransferKind::Copy, dir.join(name), dest).unwrap()
+    }
+
+    /// Tie up every worker with a job waiting on a conflict, so later jobs stay queued
+    fn occupy_workers(jobs: &mut JobQueue, dir: &Path) -> Vec<JobId> {
+        let ids = (0..WORKERS)
+            .map(|i| jobs.submit(conflicting_copy(dir, &format!("busy{}", i))))
+            .collect();
+        let start = Instant::now();
+        let waiting = |jobs: &JobQueue| {
+            jobs.jobs()
+                .iter()
+                .filter(|job| job.state == JobState::WaitingOnConflict)
+                .count()
+        };
+        while waiting(jobs) < WORKERS {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
+        }
+        ids
+    }
+
+    #[test]
+    fn cancelling_queued_job_hands_it_back() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let mut jobs = JobQueue::default();
+        let busy = occupy_workers(&mut jobs, dir);
+        fs::write(dir.join("a"), "a").unwrap();
+        let queued =
+            jobs.submit(Transfer::new(TransferKind::Move, dir.join("a"), dir.join("b")).unwrap());
+
+        jobs.cancel(queued);
+        let finished = wait_for(&mut jobs, queued);
+        assert!(finished.cancelled && finished.result.is_ok());
+        assert_eq!(finished.transfer.items[0].src, dir.join("a"));
+        assert!(dir.join("a").exists() && !dir.join("b").exists());
+        for id in busy {
+            jobs.cancel(id);
+            assert!(wait_for(&mut jobs, id).cancelled);
+        }
+        assert!(!jobs.is_busy());
+    }
+
+    #[test]
+    fn paused_job_in_queue_lets_later_ones_run() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let mut jobs = JobQueue::default();
+        let busy = occupy_workers(&mut jobs, dir);
+        let paused = jobs.submit(conflicting_copy(dir, "c"));
+        jobs.toggle_pause(paused);
+        let (src, dest) = (dir.join("d"), dir.join("d.new"));
+        fs::write(&src, "d").unwrap();
+        let last = jobs.submit(Transfer::new(TransferKind::Copy, src, dest.clone()).unwrap());
+
+        jobs.resolve(busy[0], Resolution::Skip, false);
+        for job in wait_for_all(&mut jobs, &[busy[0], last]) {
+            assert!(job.result.is_ok());
+        }
+        assert!(dest.exists());
+        let state = |id| {
+            jobs.jobs()
+                .iter()
+                .find(|job| job.id == id)
+                .unwrap()
+                .state
+                .clone()
+        };
+        assert_eq!(state(paused), JobState::Paused);
+    }
+}
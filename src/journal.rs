@@ -0,0 +1,947 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::SystemTime,
+};
+
+use crate::{
+    bulk_rename,
+    error::{Error, PathContext, Result},
+    file_ops::{Transfer, TransferKind},
+    trash::{claim_slot, remove_info},
+};
+
+/// Most batches of operations kept to undo or redo, older ones are forgotten
+const MAX_HISTORY: usize = 100;
+
+/// A file operation that was performed, with what's needed to invert it
+#[derive(Debug, Clone)]
+pub enum Operation {
+    Copy {
+        src: PathBuf,
+        dest: PathBuf,
+    },
+    Move {
+        src: PathBuf,
+        dest: PathBuf,
+    },
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    Create {
+        path: PathBuf,
+        is_dir: bool,
+    },
+    Trash {
+        path: PathBuf,
+        trashed: PathBuf,
+    },
+    /// Renames done together as `(from, to)` pairs, which may swap or pass along names, so
+    /// they are undone and redone together too
+    BulkRename {
+        renames: Vec<(PathBuf, PathBuf)>,
+    },
+}
+
+impl Operation {
+    /// Short description of the operation for messages
+    pub fn describe(&self) -> String {
+        match self {
+            Operation::Copy { src, dest } => format!("copy of {:?} to {:?}", src, dest),
+            Operation::Move { src, dest } => format!("move of {:?} to {:?}", src, dest),
+            Operation::Rename { from, to } => format!("rename of {:?} to {:?}", from, to),
+            Operation::Create { path, .. } => format!("creation of {:?}", path),
+            Operation::Trash { path, .. } => format!("trashing of {:?}", path),
+            Operation::BulkRename { renames } => format!("bulk rename of {} files", renames.len()),
+        }
+    }
+
+    /// Paths the operation left behind, which undoing it will act on
+    fn result_paths(&self) -> Vec<&Path> {
+        match self {
+            Operation::Copy { dest, .. } | Operation::Move { dest, .. } => vec![dest],
+            Operation::Rename { to, .. } => vec![to],
+            Operation::Create { path, .. } => vec![path],
+            Operation::Trash { trashed, .. } => vec![trashed],
+            Operation::BulkRename { renames } => renames.iter().map(|(_, to)| &**to).collect(),
+        }
+    }
+
+    /// Paths that redoing the operation will act on
+    fn source_paths(&self) -> Vec<&Path> {
+        match self {
+            Operation::Copy { src, .. } | Operation::Move { src, .. } => vec![src],
+            Operation::Rename { from, .. } => vec![from],
+            Operation::Create { path, .. } => vec![path],
+            Operation::Trash { path, .. } => vec![path],
+            Operation::BulkRename { renames } => renames.iter().map(|(from, _)| &**from).collect(),
+        }
+    }
+}
+
+/// Identity and last modification of one entry, relative to the path that was stamped
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct EntryStamp {
+    path: PathBuf,
+    inode: u64,
+    len: u64,
+    modified: SystemTime,
+}
+
+/// Identity and last modification of a path and everything under it, used to tell if any of
+/// it changed behind our back
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Stamp(Vec<EntryStamp>);
+
+impl Stamp {
+    fn of(path: &Path) -> Option<Self> {
+        let mut entries = Vec::new();
+        stamp_entry(path, PathBuf::new(), &mut entries)?;
+        Some(Self(entries))
+    }
+}
+
+/// Stamp `path` into `entries` as `relative`, and what's inside it if it's a directory.
+/// Symlinks are stamped themselves rather than followed.
+fn stamp_entry(path: &Path, relative: PathBuf, entries: &mut Vec<EntryStamp>) -> Option<()> {
+    let meta = fs::symlink_metadata(path).ok()?;
+    entries.push(EntryStamp {
+        path: relative.clone(),
+        inode: meta.ino(),
+        len: meta.len(),
+        modified: meta.modified().ok()?,
+    });
+    if meta.is_dir() {
+        let Ok(read_dir) = fs::read_dir(path) else {
+            // the directory's own stamp is all there is to go on
+            return Some(());
+        };
+        let mut names: Vec<_> = read_dir
+            .filter_map(|entry| entry.ok().map(|entry| entry.file_name()))
+            .collect();
+        names.sort();
+        for name in names {
+            // an entry that disappears partway through just goes unstamped
+            let _ = stamp_entry(&path.join(&name), relative.join(&name), entries);
+        }
+    }
+    Some(())
+}
+
+/// Operations done together, like a paste of several files, which are undone as one
+#[derive(Debug)]
+struct JournalEntry {
+    /// What the stamps of the paths the next undo/redo will act on are kept under
+    id: usize,
+    ops: Vec<Operation>,
+}
+
+#[derive(Debug)]
+enum StampRequest {
+    /// Stamp `paths` for the entry `id`
+    Stamp { id: usize, paths: Vec<PathBuf> },
+    /// Check `paths` are still as they were stamped for the entry `id`
+    Check { id: usize, paths: Vec<PathBuf> },
+    /// Drop the stamps of the entry `id`
+    Forget(usize),
+}
+
+/// Stamps and checks paths on a background thread, as a large directory takes a while to go
+/// through. Requests are handled in the order they're made, so an entry is always stamped
+/// before it's checked.
+#[derive(Debug)]
+struct Stamper {
+    tx: Sender<StampRequest>,
+    rx: Receiver<(usize, Result<()>)>,
+}
+
+impl Default for Stamper {
+    fn default() -> Self {
+        let (tx, requests) = mpsc::channel();
+        let (results, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut stamps: HashMap<usize, Vec<Option<Stamp>>> = HashMap::new();
+            while let Ok(request) = requests.recv() {
+                match request {
+                    StampRequest::Stamp { id, paths } => {
+                        let stamped = paths.iter().map(|path| Stamp::of(path)).collect();
+                        stamps.insert(id, stamped);
+                    }
+                    StampRequest::Check { id, paths } => {
+                        let result = match stamps.get(&id) {
+                            Some(stamped) => check_unchanged(&paths, stamped),
+                            None => Ok(()),
+                        };
+                        if results.send((id, result)).is_err() {
+                            break;
+                        }
+                    }
+                    StampRequest::Forget(id) => {
+                        stamps.remove(&id);
+                    }
+                }
+            }
+        });
+        Self { tx, rx }
+    }
+}
+
+impl Stamper {
+    fn send(&self, request: StampRequest) {
+        // the thread only stops once the journal is dropped
+        let _ = self.tx.send(request);
+    }
+}
+
+/// Undoing or redoing a batch of operations. Renames and creations are carried out straight
+/// away, moves, copies and deletions are left to transfers run as jobs.
+#[derive(Debug)]
+pub struct Replay {
+    /// Whether this is an undo rather than a redo
+    pub undo: bool,
+    /// Operations carried out already
+    pub done: Vec<Operation>,
+    /// Error that stopped the batch partway, what wasn't carried out stays in the journal
+    pub result: Result<()>,
+    /// Transfers still to run, each with the operation every one of its items carries out.
+    /// How they went is handed back with `finish_undo` or `finish_redo`.
+    pub transfers: Vec<(Transfer, Vec<Operation>)>,
+}
+
+impl Replay {
+    fn failed(error: Error, undo: bool) -> Self {
+        Self {
+            undo,
+            done: Vec::new(),
+            result: Err(error),
+            transfers: Vec::new(),
+        }
+    }
+}
+
+/// How far carrying out an operation right away got
+struct Ran {
+    /// What was carried out, as it's recorded afterwards
+    done: Option<Operation>,
+    /// What's left to carry out, if it stopped partway
+    left: Option<Operation>,
+    result: Result<()>,
+}
+
+impl Ran {
+    /// An operation carried out either completely or not at all
+    fn whole(op: &Operation, result: Result<()>) -> Self {
+        match result {
+            Ok(_) => Self {
+                done: Some(op.clone()),
+                left: None,
+                result,
+            },
+            Err(_) => Self {
+                done: None,
+                left: Some(op.clone()),
+                result,
+            },
+        }
+    }
+}
+
+/// Files an operation moves or copies when it's undone or redone, with the operation as it's
+/// recorded afterwards
+type Planned = (TransferKind, PathBuf, PathBuf, Operation);
+
+/// History of file operations that can be undone and redone. Before an undo or redo goes ahead,
+/// the paths it acts on are checked to be as they were left, which happens in the background.
+#[derive(Debug, Default)]
+pub struct Journal {
+    undo_stack: Vec<JournalEntry>,
+    redo_stack: Vec<JournalEntry>,
+    stamper: Stamper,
+    next_id: usize,
+    /// Entry being checked before it's undone (`true`) or redone
+    checking: Option<(usize, bool)>,
+}
+
+impl Journal {
+    /// Record freshly completed operations. Anything that was undone can no longer be redone.
+    pub fn record(&mut self, ops: Vec<Operation>) {
+        if ops.is_empty() {
+            return;
+        }
+        for entry in self.redo_stack.drain(..) {
+            self.stamper.send(StampRequest::Forget(entry.id));
+        }
+        self.push_undo(ops);
+    }
+
+    fn push_undo(&mut self, ops: Vec<Operation>) {
+        if let Some(entry) = self.new_entry(ops, Operation::result_paths) {
+            push_capped(&mut self.undo_stack, entry, &self.stamper);
+        }
+    }
+
+    fn push_redo(&mut self, ops: Vec<Operation>) {
+        if let Some(entry) = self.new_entry(ops, Operation::source_paths) {
+            push_capped(&mut self.redo_stack, entry, &self.stamper);
+        }
+    }
+
+    /// Entry for `ops`, if there are any, with the `paths` of each stamped for it
+    fn new_entry(
+        &mut self,
+        ops: Vec<Operation>,
+        paths: fn(&Operation) -> Vec<&Path>,
+    ) -> Option<JournalEntry> {
+        if ops.is_empty() {
+            return None;
+        }
+        self.next_id += 1;
+        let paths = ops.iter().flat_map(paths).map(Path::to_path_buf).collect();
+        self.stamper.send(StampRequest::Stamp {
+            id: self.next_id,
+            paths,
+        });
+        Some(JournalEntry {
+            id: self.next_id,
+            ops,
+        })
+    }
+
+    /// Start checking the most recent batch of operations can be inverted. The undo itself is
+    /// handed out by `poll` once that's done.
+    pub fn undo(&mut self) -> Result<()> {
+        let entry = self
+            .undo_stack
+            .last()
+            .ok_or_else(|| Error::Other("Nothing to undo".to_string()))?;
+        let paths = entry.ops.iter().flat_map(Operation::result_paths);
+        self.check(entry.id, paths.map(Path::to_path_buf).collect(), true);
+        Ok(())
+    }
+
+    /// Start checking the most recently undone batch can be performed again. The redo itself
+    /// is handed out by `poll` once that's done.
+    pub fn redo(&mut self) -> Result<()> {
+        let entry = self
+            .redo_stack
+            .last()
+            .ok_or_else(|| Error::Other("Nothing to redo".to_string()))?;
+        let paths = entry.ops.iter().flat_map(Operation::source_paths);
+        self.check(entry.id, paths.map(Path::to_path_buf).collect(), false);
+        Ok(())
+    }
+
+    fn check(&mut self, id: usize, paths: Vec<PathBuf>, undo: bool) {
+        self.checking = Some((id, undo));
+        self.stamper.send(StampRequest::Check { id, paths });
+    }
+
+    /// Whether an undo or redo is waiting on its check
+    pub fn is_checking(&self) -> bool {
+        self.checking.is_some()
+    }
+
+    /// The undo or redo asked for last, once its check is done. Everything is checked up front
+    /// so a batch isn't left half done because of a change.
+    pub fn poll(&mut self) -> Option<Replay> {
+        let mut ready = None;
+        while let Ok((id, result)) = self.stamper.rx.try_recv() {
+            let Some((checking, undo)) = self.checking else {
+                continue;
+            };
+            if checking != id {
+                // asked again since
+                continue;
+            }
+            self.checking = None;
+            let stack = if undo {
+                &mut self.undo_stack
+            } else {
+                &mut self.redo_stack
+            };
+            if stack.last().map(|entry| entry.id) != Some(id) {
+                // the history moved on while it was checked
+                continue;
+            }
+            if let Err(e) = result {
+                ready = Some(Replay::failed(e, undo));
+                continue;
+            }
+            let entry = stack.pop().unwrap();
+            self.stamper.send(StampRequest::Forget(entry.id));
+            ready = Some(if undo {
+                // last done is first undone
+                let ops = entry.ops.into_iter().rev();
+                let (replay, remaining) = replay(ops, undo_op, undo_transfer, |_| {}, true);
+                self.finish_undo(replay.done.clone(), remaining);
+                replay
+            } else {
+                let ops = entry.ops.into_iter();
+                let (replay, remaining) = replay(ops, redo_op, redo_transfer, release_slot, false);
+                self.finish_redo(replay.done.clone(), remaining);
+                replay
+            });
+        }
+        ready
+    }
+
+    /// Record how undoing went: `undone` can be redone, `remaining` are left to undo again.
+    /// Both are in the order they were undone in.
+    pub fn finish_undo(&mut self, undone: Vec<Operation>, remaining: Vec<Operation>) {
+        self.push_undo(remaining.into_iter().rev().collect());
+        self.push_redo(undone.into_iter().rev().collect());
+    }
+
+    /// Record how redoing went: `redone` can be undone, `remaining` are left to redo again
+    pub fn finish_redo(&mut self, redone: Vec<Operation>, remaining: Vec<Operation>) {
+        self.push_redo(remaining);
+        self.push_undo(redone);
+    }
+}
+
+/// Carry out `ops` in order with `run` until one fails, setting aside the ones `plan` says move
+/// or copy files for a transfer. Those are only started if nothing failed, otherwise `release`
+/// undoes their planning. Returns the replay and the operations not carried out.
+fn replay(
+    ops: impl Iterator<Item = Operation>,
+    run: fn(&Operation) -> Ran,
+    plan: fn(&Operation) -> Option<Result<Planned>>,
+    release: fn(&Operation),
+    undo: bool,
+) -> (Replay, Vec<Operation>) {
+    let mut replay = Replay {
+        undo,
+        done: Vec::new(),
+        result: Ok(()),
+        transfers: Vec::new(),
+    };
+    let mut remaining = Vec::new();
+    let mut planned: Vec<Planned> = Vec::new();
+    for op in ops {
+        if replay.result.is_err() {
+            remaining.push(op);
+            continue;
+        }
+        let result = match plan(&op) {
+            Some(Ok(step)) => {
+                planned.push(step);
+                Ok(())
+            }
+            Some(Err(e)) => {
+                remaining.push(op);
+                Err(e)
+            }
+            None => {
+                let ran = run(&op);
+                replay.done.extend(ran.done);
+                remaining.extend(ran.left);
+                ran.result
+            }
+        };
+        if let Err(e) = result {
+            replay.result = Err(e);
+        }
+    }
+    if replay.result.is_err() {
+        // the planned ones came before the one that failed
+        let mut left: Vec<Operation> = planned.into_iter().map(|(.., op)| op).collect();
+        left.iter().for_each(release);
+        left.extend(remaining);
+        return (replay, left);
+    }
+    for kind in [TransferKind::Copy, TransferKind::Move, TransferKind::Delete] {
+        let (pairs, ops): (Vec<_>, Vec<_>) = planned
+            .iter()
+            .filter(|(step_kind, ..)| *step_kind == kind)
+            .map(|(_, src, dest, op)| ((src.clone(), dest.clone()), op.clone()))
+            .unzip();
+        if pairs.is_empty() {
+            continue;
+        }
+        match Transfer::batch(kind, pairs) {
+            Ok(transfer) => replay.transfers.push((transfer, ops)),
+            Err(e) => {
+                ops.iter().for_each(release);
+                remaining.extend(ops);
+                replay.result = Err(e);
+            }
+        }
+    }
+    (replay, remaining)
+}
+
+/// Give back the spot in the trash claimed for redoing `op`, if it's a trashing
+fn release_slot(op: &Operation) {
+    if let Operation::Trash { trashed, .. } = op {
+        let _ = remove_info(trashed);
+    }
+}
+
+/// Push `entry` onto `stack`, forgetting the oldest entry if there are too many
+fn push_capped(stack: &mut Vec<JournalEntry>, entry: JournalEntry, stamper: &Stamper) {
+    stack.push(entry);
+    if stack.len() > MAX_HISTORY {
+        let oldest = stack.remove(0);
+        stamper.send(StampRequest::Forget(oldest.id));
+    }
+}
+
+fn check_unchanged(paths: &[PathBuf], stamps: &[Option<Stamp>]) -> Result<()> {
+    for (path, stamp) in paths.iter().zip(stamps) {
+        if Stamp::of(path) != *stamp {
+            return Err(Error::Changed(path.to_path_buf()));
+        }
+    }
+    Ok(())
+}
+
+/// Make sure an operation won't clobber anything at `path`
+fn check_free(path: &Path) -> Result<()> {
+    if fs::symlink_metadata(path).is_ok() {
+        return Err(Error::AlreadyExists(path.to_path_buf()));
+    }
+    Ok(())
+}
+
+/// Undo `op` right away, unless it moves files back or deletes them
+fn undo_op(op: &Operation) -> Ran {
+    let result = match op {
+        Operation::Rename { from, to } => {
+            check_free(from).and_then(|_| fs::rename(to, from).at(to))
+        }
+        Operation::Create { path, is_dir } => {
+            if *is_dir {
+                // only empty, so nothing made inside it since gets lost
+                fs::remove_dir(path).at(path)
+            } else {
+                fs::remove_file(path).at(path)
+            }
+        }
+        Operation::BulkRename { renames } => {
+            let ran = rename_all(&reversed(renames));
+            // recorded the way round they are redone
+            let flip = |op: Option<Operation>| match op {
+                Some(Operation::BulkRename { renames }) => bulk_rename_op(reversed(&renames)),
+                _ => None,
+            };
+            return Ran {
+                done: flip(ran.done),
+                left: flip(ran.left),
+                result: ran.result,
+            };
+        }
+        Operation::Copy { .. } | Operation::Move { .. } | Operation::Trash { .. } => {
+            unreachable!("undone by a transfer")
+        }
+    };
+    Ran::whole(op, result)
+}
+
+/// Files to move back or delete to undo `op`, if it's undone that way. A trashed item's
+/// `.trashinfo` is left for the caller to drop once it's back.
+fn undo_transfer(op: &Operation) -> Option<Result<Planned>> {
+    let (from, to) = match op {
+        Operation::Copy { dest, .. } => {
+            let planned = (TransferKind::Delete, dest.clone(), dest.clone(), op.clone());
+            return Some(Ok(planned));
+        }
+        Operation::Move { src, dest } => (dest, src),
+        Operation::Trash { path, trashed } => (trashed, path),
+        _ => return None,
+    };
+    let planned = (TransferKind::Move, from.clone(), to.clone(), op.clone());
+    Some(check_free(to).map(|_| planned))
+}
+
+/// Redo `op` right away, unless it moves or copies files
+fn redo_op(op: &Operation) -> Ran {
+    let result = match op {
+        Operation::Rename { from, to } => {
+            check_free(to).and_then(|_| fs::rename(from, to).at(from))
+        }
+        Operation::Create { path, is_dir } => {
+            if *is_dir {
+                fs::create_dir(path).at(path)
+            } else {
+                File::create_new(path).map(|_| ()).at(path)
+            }
+        }
+        Operation::BulkRename { renames } => return rename_all(renames),
+        Operation::Copy { .. } | Operation::Move { .. } | Operation::Trash { .. } => {
+            unreachable!("redone by a transfer")
+        }
+    };
+    Ran::whole(op, result)
+}
+
+/// Rename files to new names together, as a bulk rename does, recording where each file that
+/// moved ended up. Nothing is renamed if one of the new names is taken by something that isn't
+/// being renamed away.
+fn rename_all(renames: &[(PathBuf, PathBuf)]) -> Ran {
+    for (_, to) in renames {
+        if !renames.iter().any(|(from, _)| from == to) {
+            if let Err(e) = check_free(to) {
+                return Ran {
+                    done: None,
+                    left: bulk_rename_op(renames.to_vec()),
+                    result: Err(e),
+                };
+            }
+        }
+    }
+    let mut done = Vec::new();
+    let mut left = Vec::new();
+    let mut result = Ok(());
+    for (renamed, (from, to)) in bulk_rename::apply(renames).into_iter().zip(renames) {
+        if renamed.at != *from {
+            done.push((from.clone(), renamed.at));
+        } else {
+            left.push((from.clone(), to.clone()));
+        }
+        if let (Some(e), Ok(_)) = (renamed.error, &result) {
+            result = Err(e);
+        }
+    }
+    Ran {
+        done: bulk_rename_op(done),
+        left: bulk_rename_op(left),
+        result,
+    }
+}
+
+/// `renames` the other way round
+fn reversed(renames: &[(PathBuf, PathBuf)]) -> Vec<(PathBuf, PathBuf)> {
+    renames
+        .iter()
+        .map(|(from, to)| (to.clone(), from.clone()))
+        .collect()
+}
+
+/// Bulk rename made of `renames`, if there are any
+fn bulk_rename_op(renames: Vec<(PathBuf, PathBuf)>) -> Option<Operation> {
+    (!renames.is_empty()).then_some(Operation::BulkRename { renames })
+}
+
+/// Files to move or copy to redo `op`, if it's redone that way. Trashing again claims a new
+/// spot in the trash, which the caller gives back if the item doesn't make it there.
+fn redo_transfer(op: &Operation) -> Option<Result<Planned>> {
+    match op {
+        Operation::Copy { src, dest } => Some(
+            check_free(dest).map(|_| (TransferKind::Copy, src.clone(), dest.clone(), op.clone())),
+        ),
+        Operation::Move { src, dest } => Some(
+            check_free(dest).map(|_| (TransferKind::Move, src.clone(), dest.clone(), op.clone())),
+        ),
+        Operation::Trash { path, .. } => Some(claim_slot(path).map(|trashed| {
+            let op = Operation::Trash {
+                path: path.clone(),
+                trashed: trashed.clone(),
+            };
+            (TransferKind::Move, path.clone(), trashed, op)
+        })),
+        Operation::Rename { .. } | Operation::Create { .. } | Operation::BulkRename { .. } => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Run the transfers of a replay to the end, returning everything it carried out
+    fn run(mut replay: Replay) -> Vec<Operation> {
+        for (mut transfer, ops) in replay.transfers.drain(..) {
+            transfer.finish().unwrap();
+            replay.done.extend(ops);
+        }
+        replay.result.unwrap();
+        replay.done
+    }
+
+    /// Undo the last batch once the journal has checked it
+    fn undo(journal: &mut Journal) -> Replay {
+        match journal.undo() {
+            Ok(_) => checked(journal),
+            Err(e) => Replay::failed(e, true),
+        }
+    }
+
+    /// Redo the last undone batch once the journal has checked it
+    fn redo(journal: &mut Journal) -> Replay {
+        match journal.redo() {
+            Ok(_) => checked(journal),
+            Err(e) => Replay::failed(e, false),
+        }
+    }
+
+    /// Wait for the stamps of everything recorded so far to be taken, so changes made after
+    /// this count as changes
+    fn stamped(journal: &Journal) {
+        journal.stamper.send(StampRequest::Check {
+            id: usize::MAX,
+            paths: Vec::new(),
+        });
+        loop {
+            let (id, _) = journal
+                .stamper
+                .rx
+                .recv_timeout(Duration::from_secs(5))
+                .unwrap();
+            if id == usize::MAX {
+                return;
+            }
+        }
+    }
+
+    fn checked(journal: &mut Journal) -> Replay {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if let Some(replay) = journal.poll() {
+                return replay;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        panic!("check didn't finish");
+    }
+
+    /// Journal with a move of `dir/a` to `dir/b` recorded
+    fn moved(dir: &Path) -> Journal {
+        fs::write(dir.join("a"), "a").unwrap();
+        Transfer::new(TransferKind::Move, dir.join("a"), dir.join("b"))
+            .unwrap()
+            .finish()
+            .unwrap();
+        let mut journal = Journal::default();
+        journal.record(vec![Operation::Move {
+            src: dir.join("a"),
+            dest: dir.join("b"),
+        }]);
+        stamped(&journal);
+        journal
+    }
+
+    #[test]
+    fn undoes_and_redoes_move() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let mut journal = moved(dir);
+
+        let undone = run(undo(&mut journal));
+        journal.finish_undo(undone, Vec::new());
+        assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "a");
+        assert!(!dir.join("b").exists());
+
+        let redone = run(redo(&mut journal));
+        journal.finish_redo(redone, Vec::new());
+        assert_eq!(fs::read_to_string(dir.join("b")).unwrap(), "a");
+        assert!(!dir.join("a").exists());
+    }
+
+    #[test]
+    fn undo_refused_when_target_changed() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let mut journal = moved(dir);
+        fs::write(dir.join("b"), "changed").unwrap();
+
+        let replay = undo(&mut journal);
+        assert!(matches!(replay.result, Err(Error::Changed(_))));
+        assert!(replay.done.is_empty() && replay.transfers.is_empty());
+        assert!(!dir.join("a").exists());
+        // still there to undo
+        assert!(undo(&mut journal).result.is_err());
+    }
+
+    #[test]
+    fn undo_refused_when_original_place_taken() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let mut journal = moved(dir);
+        fs::write(dir.join("a"), "new").unwrap();
+
+        let replay = undo(&mut journal);
+        assert!(matches!(replay.result, Err(Error::AlreadyExists(_))));
+        assert!(replay.transfers.is_empty());
+        assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "new");
+
+        fs::remove_file(dir.join("a")).unwrap();
+        let undone = run(undo(&mut journal));
+        journal.finish_undo(undone, Vec::new());
+        assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "a");
+    }
+
+    #[test]
+    fn redo_refused_when_source_changed() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let mut journal = moved(dir);
+        let undone = run(undo(&mut journal));
+        journal.finish_undo(undone, Vec::new());
+        stamped(&journal);
+        fs::write(dir.join("a"), "changed").unwrap();
+
+        let replay = redo(&mut journal);
+        assert!(matches!(replay.result, Err(Error::Changed(_))));
+        assert!(replay.transfers.is_empty());
+        assert!(!dir.join("b").exists());
+    }
+
+    #[test]
+    fn undoes_rename_unless_changed() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        fs::write(dir.join("to"), "x").unwrap();
+        let mut journal = Journal::default();
+        journal.record(vec![Operation::Rename {
+            from: dir.join("from"),
+            to: dir.join("to"),
+        }]);
+        stamped(&journal);
+        fs::write(dir.join("to"), "changed").unwrap();
+        assert!(matches!(undo(&mut journal).result, Err(Error::Changed(_))));
+        assert!(dir.join("to").exists());
+
+        let mut journal = Journal::default();
+        journal.record(vec![Operation::Rename {
+            from: dir.join("from"),
+            to: dir.join("to"),
+        }]);
+        let replay = undo(&mut journal);
+        assert!(replay.transfers.is_empty());
+        assert_eq!(run(replay).len(), 1);
+        assert_eq!(fs::read_to_string(dir.join("from")).unwrap(), "changed");
+    }
+
+    #[test]
+    fn undoes_and_redoes_bulk_rename_swap() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        fs::write(dir.join("a"), "a").unwrap();
+        fs::write(dir.join("b"), "b").unwrap();
+        let renames = vec![
+            (dir.join("a"), dir.join("b")),
+            (dir.join("b"), dir.join("a")),
+            (dir.join("c"), dir.join("d")),
+        ];
+        // a chain into c, as well as the swap
+        fs::write(dir.join("c"), "c").unwrap();
+        for renamed in bulk_rename::apply(&renames) {
+            assert!(renamed.error.is_none());
+        }
+        let mut journal = Journal::default();
+        journal.record(vec![Operation::BulkRename {
+            renames: renames.clone(),
+        }]);
+
+        let undone = run(undo(&mut journal));
+        assert_eq!(undone.len(), 1);
+        journal.finish_undo(undone, Vec::new());
+        assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(dir.join("b")).unwrap(), "b");
+        assert_eq!(fs::read_to_string(dir.join("c")).unwrap(), "c");
+        assert!(!dir.join("d").exists());
+
+        let redone = run(redo(&mut journal));
+        journal.finish_redo(redone, Vec::new());
+        assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "b");
+        assert_eq!(fs::read_to_string(dir.join("b")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(dir.join("d")).unwrap(), "c");
+        assert!(!dir.join("c").exists());
+    }
+
+    #[test]
+    fn bulk_rename_undo_refused_when_original_name_taken() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        fs::write(dir.join("b"), "a").unwrap();
+        fs::write(dir.join("a"), "new").unwrap();
+        let mut journal = Journal::default();
+        journal.record(vec![Operation::BulkRename {
+            renames: vec![(dir.join("a"), dir.join("b"))],
+        }]);
+
+        let replay = undo(&mut journal);
+        assert!(matches!(replay.result, Err(Error::AlreadyExists(_))));
+        assert!(replay.done.is_empty());
+        assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "new");
+        assert_eq!(fs::read_to_string(dir.join("b")).unwrap(), "a");
+    }
+
+    #[test]
+    fn undoes_copy_by_deleting_in_a_transfer() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("a/inner")).unwrap();
+        fs::write(dir.join("a/inner/file"), "x").unwrap();
+        Transfer::new(TransferKind::Copy, dir.join("a"), dir.join("b"))
+            .unwrap()
+            .finish()
+            .unwrap();
+        let mut journal = Journal::default();
+        journal.record(vec![Operation::Copy {
+            src: dir.join("a"),
+            dest: dir.join("b"),
+        }]);
+
+        let replay = undo(&mut journal);
+        assert!(replay.done.is_empty());
+        assert_eq!(replay.transfers[0].0.kind, TransferKind::Delete);
+        // nothing is deleted until the transfer runs
+        assert!(dir.join("b/inner/file").exists());
+        let undone = run(replay);
+        journal.finish_undo(undone, Vec::new());
+        assert!(!dir.join("b").exists());
+        assert!(dir.join("a/inner/file").exists());
+    }
+
+    #[test]
+    fn forgets_oldest_batches_past_the_limit() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let mut journal = Journal::default();
+        for i in 0..MAX_HISTORY + 5 {
+            let path = dir.join(i.to_string());
+            fs::create_dir(&path).unwrap();
+            journal.record(vec![Operation::Create { path, is_dir: true }]);
+        }
+        assert_eq!(journal.undo_stack.len(), MAX_HISTORY);
+
+        for _ in 0..MAX_HISTORY {
+            let undone = run(undo(&mut journal));
+            assert_eq!(undone.len(), 1);
+        }
+        assert!(matches!(undo(&mut journal).result, Err(Error::Other(_))));
+        // the oldest ones are still there, only no longer undoable
+        assert!(dir.join("4").exists() && !dir.join("5").exists());
+    }
+
+    #[test]
+    fn check_ignores_history_that_moved_on() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let mut journal = moved(dir);
+        journal.undo().unwrap();
+        fs::create_dir(dir.join("new")).unwrap();
+        journal.record(vec![Operation::Create {
+            path: dir.join("new"),
+            is_dir: true,
+        }]);
+
+        let start = Instant::now();
+        while journal.is_checking() {
+            assert!(journal.poll().is_none());
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(journal.undo_stack.len(), 2);
+        assert!(dir.join("b").exists());
+    }
+}
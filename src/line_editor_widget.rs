@@ -0,0 +1,202 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Style, Stylize},
+    text::{Line, Span},
+    widgets::Widget,
+};
+
+/// What the editor wants done after a key press
+#[derive(Debug, PartialEq, Eq)]
+pub enum EditorEvent {
+    None,
+    Submit(String),
+    Cancel,
+}
+
+/// Single line text input with readline style keybinds and history
+#[derive(Debug, Default, Clone)]
+pub struct LineEditor {
+    text: String,
+    /// Cursor position in chars
+    cursor: usize,
+    history: Vec<String>,
+    /// Entry of the history being shown, and the text typed before browsing it
+    history_pos: Option<(usize, String)>,
+    style: Style,
+}
+
+impl LineEditor {
+    pub fn new(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            cursor: text.chars().count(),
+            ..Self::default()
+        }
+    }
+
+    /// Previous entries to browse with up/down, oldest first
+    pub fn history(mut self, history: Vec<String>) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Place the cursor at the given char position
+    pub fn cursor(mut self, cursor: usize) -> Self {
+        self.cursor = cursor.min(self.len());
+        self
+    }
+
+    /// Mimic ratatui component styling
+    pub fn style(mut self, style: Style) -> Self {
+        self.style = style;
+        self
+    }
+
+    pub fn handle_key(&mut self, key_event: KeyEvent) -> EditorEvent {
+        let ctrl = key_event.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key_event.modifiers.contains(KeyModifiers::ALT);
+        match key_event.code {
+            KeyCode::Enter => return EditorEvent::Submit(self.text.clone()),
+            KeyCode::Esc => return EditorEvent::Cancel,
+            KeyCode::Char('c') if ctrl => return EditorEvent::Cancel,
+
+            // movement
+            KeyCode::Left if ctrl => self.cursor = self.word_start(),
+            KeyCode::Right if ctrl => self.cursor = self.word_end(),
+            KeyCode::Char('b') if alt => self.cursor = self.word_start(),
+            KeyCode::Char('f') if alt => self.cursor = self.word_end(),
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.len()),
+            KeyCode::Char('b') if ctrl => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Char('f') if ctrl => self.cursor = (self.cursor + 1).min(self.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::Char('a') if ctrl => self.cursor = 0,
+            KeyCode::End => self.cursor = self.len(),
+            KeyCode::Char('e') if ctrl => self.cursor = self.len(),
+
+            // deletion
+            KeyCode::Backspace if alt || ctrl => self.delete_range(self.word_start(), self.cursor),
+            KeyCode::Char('w') if ctrl => self.delete_range(self.word_start(), self.cursor),
+            KeyCode::Char('d') if alt => self.delete_range(self.cursor, self.word_end()),
+            KeyCode::Char('u') if ctrl => self.delete_range(0, self.cursor),
+            KeyCode::Char('k') if ctrl => self.delete_range(self.cursor, self.len()),
+            KeyCode::Backspace => self.delete_range(self.cursor.saturating_sub(1), self.cursor),
+            KeyCode::Delete => self.delete_range(self.cursor, (self.cursor + 1).min(self.len())),
+
+            // history
+            KeyCode::Up => self.history_step(true),
+            KeyCode::Down => self.history_step(false),
+
+            KeyCode::Char(c) if !ctrl && !alt => {
+                let at = self.byte_idx(self.cursor);
+                self.text.insert(at, c);
+                self.cursor += 1;
+            }
+            _ => {}
+        }
+        EditorEvent::None
+    }
+
+    fn len(&self) -> usize {
+        self.text.chars().count()
+    }
+
+    fn byte_idx(&self, char_idx: usize) -> usize {
+        self.text
+            .char_indices()
+            .nth(char_idx)
+            .map_or(self.text.len(), |(i, _)| i)
+    }
+
+    fn delete_range(&mut self, start: usize, end: usize) {
+        let (start_byte, end_byte) = (self.byte_idx(start), self.byte_idx(end));
+        self.text.replace_range(start_byte..end_byte, "");
+        self.cursor = start;
+    }
+
+    /// Start of the word before the cursor. Punctuation like `.` and `_` splits words, so word
+    /// motions stop between a file's name and extension.
+    fn word_start(&self) -> usize {
+        let chars: Vec<char> = self.text.chars().collect();
+        let mut i = self.cursor;
+        while i > 0 && !chars[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        while i > 0 && chars[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        i
+    }
+
+    /// End of the word after the cursor
+    fn word_end(&self) -> usize {
+        let chars: Vec<char> = self.text.chars().collect();
+        let mut i = self.cursor;
+        while i < chars.len() && !chars[i].is_alphanumeric() {
+            i += 1;
+        }
+        while i < chars.len() && chars[i].is_alphanumeric() {
+            i += 1;
+        }
+        i
+    }
+
+    fn history_step(&mut self, older: bool) {
+        if self.history.is_empty() {
+            return;
+        }
+        let pos = match (&self.history_pos, older) {
+            (None, true) => Some(self.history.len() - 1),
+            (None, false) => return,
+            (Some((0, _)), true) => Some(0),
+            (Some((pos, _)), true) => Some(pos - 1),
+            (Some((pos, _)), false) if pos + 1 < self.history.len() => Some(pos + 1),
+            // went past the newest entry, back to what was typed
+            (Some(_), false) => None,
+        };
+        match pos {
+            Some(pos) => {
+                let typed = match self.history_pos.take() {
+                    Some((_, typed)) => typed,
+                    None => self.text.clone(),
+                };
+                self.text = self.history[pos].clone();
+                self.history_pos = Some((pos, typed));
+            }
+            None => {
+                if let Some((_, typed)) = self.history_pos.take() {
+                    self.text = typed;
+                }
+            }
+        }
+        self.cursor = self.len();
+    }
+}
+
+impl Widget for &LineEditor {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        if area.width == 0 {
+            return;
+        }
+        // scroll horizontally so the cursor stays visible
+        let width = area.width as usize;
+        let skip = (self.cursor + 1).saturating_sub(width);
+        let chars: Vec<char> = self.text.chars().collect();
+        let before: String = chars[skip..self.cursor].iter().collect();
+        let under = chars
+            .get(self.cursor)
+            .map_or(" ".to_string(), |c| c.to_string());
+        let after: String = chars.iter().skip(self.cursor + 1).collect();
+
+        buf.set_style(area, self.style);
+        Line::from(vec![
+            Span::raw(before),
+            Span::raw(under).reversed(),
+            Span::raw(after),
+        ])
+        .style(self.style)
+        .render(area, buf);
+    }
+}
@@ -0,0 +1,135 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::{Error, PathContext, Result},
+    tree::{FileObj, FileObjType},
+};
+
+/// Most entries sent in one go, so the first ones show up while the rest are still being read
+const BATCH_SIZE: usize = 1024;
+/// Longest a partly filled batch is held back
+const BATCH_INTERVAL: Duration = Duration::from_millis(50);
+
+pub type LoadId = usize;
+
+/// Progress of reading a directory, sent back to the tree
+#[derive(Debug)]
+pub enum LoadEvent {
+    /// More entries, unsorted and unfiltered
+    Entries(Vec<FileObj>),
+    /// An entry that couldn't be read
+    Skipped(Error),
+    /// Reading stopped, either at the end of the directory or on an error
+    Done(Result<()>),
+}
+
+/// Reads directories on background threads, so huge or slow ones don't freeze the UI
+#[derive(Debug)]
+pub struct DirLoader {
+    tx: Sender<(LoadId, LoadEvent)>,
+    rx: Receiver<(LoadId, LoadEvent)>,
+    next_id: LoadId,
+}
+
+impl Default for DirLoader {
+    fn default() -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self { tx, rx, next_id: 0 }
+    }
+}
+
+impl DirLoader {
+    /// Start reading `dir`. Setting the returned flag stops it early.
+    pub fn start(&mut self, dir: PathBuf) -> (LoadId, Arc<AtomicBool>) {
+        self.next_id += 1;
+        let (id, cancel, tx) = (
+            self.next_id,
+            Arc::new(AtomicBool::new(false)),
+            self.tx.clone(),
+        );
+        let worker_cancel = cancel.clone();
+        thread::spawn(move || {
+            let result = read_entries(id, &dir, &worker_cancel, &tx);
+            let _ = tx.send((id, LoadEvent::Done(result)));
+        });
+        (id, cancel)
+    }
+
+    /// Next event from any load, if one is waiting
+    pub fn try_recv(&self) -> Option<(LoadId, LoadEvent)> {
+        self.rx.try_recv().ok()
+    }
+
+    /// Next event from any load, waiting up to `timeout` for one
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<(LoadId, LoadEvent)> {
+        self.rx.recv_timeout(timeout).ok()
+    }
+}
+
+/// Send the entries of `dir` in batches until done or cancelled
+fn read_entries(
+    id: LoadId,
+    dir: &Path,
+    cancel: &AtomicBool,
+    tx: &Sender<(LoadId, LoadEvent)>,
+) -> Result<()> {
+    let mut batch = Vec::new();
+    let mut last_sent = Instant::now();
+    for entry in fs::read_dir(dir).at(dir)? {
+        if cancel.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let error = Error::Io {
+                    path: Some(dir.to_path_buf()),
+                    source: e,
+                };
+                let _ = tx.send((id, LoadEvent::Skipped(error)));
+                continue;
+            }
+        };
+        let path = entry.path();
+        let read = entry
+            .metadata()
+            .and_then(|meta| Ok((FileObjType::of(&path, &meta)?, meta)));
+        let (object_type, meta) = match read {
+            Ok(read) => read,
+            Err(e) => {
+                let error = Error::Io {
+                    path: Some(path),
+                    source: e,
+                };
+                let _ = tx.send((id, LoadEvent::Skipped(error)));
+                continue;
+            }
+        };
+        let name = entry.file_name();
+        batch.push(FileObj::new(object_type, name, path).metadata(meta));
+        if batch.len() >= BATCH_SIZE || last_sent.elapsed() >= BATCH_INTERVAL {
+            if tx
+                .send((id, LoadEvent::Entries(std::mem::take(&mut batch))))
+                .is_err()
+            {
+                // tree is gone
+                return Ok(());
+            }
+            last_sent = Instant::now();
+        }
+    }
+    if !batch.is_empty() {
+        let _ = tx.send((id, LoadEvent::Entries(batch)));
+    }
+    Ok(())
+}
@@ -1,17 +1,34 @@
 mod app;
+mod arena;
+mod bulk_rename;
+mod details;
+mod dir_size;
+mod disk_usage;
+mod error;
+mod file_ops;
 mod file_tree_state;
 mod file_tree_widget;
+mod filter;
+mod highlight;
+mod jobs;
+mod journal;
+mod line_editor_widget;
+mod loader;
+mod preview;
 mod preview_pane_widget;
+mod sort;
 mod status_bar_widget;
+mod trash;
 mod tree;
 mod tui;
+mod watcher;
 use ratatui::{backend::CrosstermBackend, Terminal};
 
 use crate::app::App;
 use std::{fs::File, io};
 
 fn main() -> io::Result<()> {
-    let target = Box::new(File::create("./log.txt").expect("Can't create file"));
+    let target = Box::new(File::create("./log.txt")?);
     env_logger::builder()
         .target(env_logger::Target::Pipe(target))
         .init();
@@ -0,0 +1,404 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread,
+    time::SystemTime,
+};
+
+use ratatui::{
+    style::{Color, Style},
+    text::{Line, Span},
+};
+
+use crate::{
+    error::{Error, PathContext, Result},
+    highlight::Highlighter,
+};
+
+/// How much of the start of a file is looked at to tell whether it's text or binary
+const SNIFF_LEN: usize = 8192;
+/// Most of a text file read in at once. Paging past it reads the next part.
+const CHUNK_BYTES: usize = 256 * 1024;
+/// Most lines of a text file read in at once
+const CHUNK_LINES: usize = 500;
+/// Previews kept for when the selection comes back to a file
+const CACHE_SIZE: usize = 32;
+/// Bytes on each row of the hex dump, like `xxd`
+pub const BYTES_PER_ROW: usize = 16;
+
+/// Which preview: the file, when it was last modified, the offset of the part shown and the
+/// theme it's highlighted with
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PreviewKey {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    start: u64,
+    theme: usize,
+}
+
+/// Part of a file as it's shown in the preview pane
+#[derive(Debug)]
+pub struct Preview {
+    /// Shown as a hex dump rather than as text
+    pub binary: bool,
+    /// Highlighted lines of text, or rows of the hex dump
+    pub lines: Vec<Line<'static>>,
+    /// Offset into the file the part ends at
+    pub end: u64,
+    /// Whole length of the file
+    pub len: u64,
+}
+
+#[derive(Debug)]
+struct Request {
+    id: usize,
+    path: PathBuf,
+    start: u64,
+    theme: usize,
+}
+
+/// Reads and highlights previews on a background thread, so moving over slow or large files
+/// doesn't hold up the UI. The thread keeps the most recent ones, checking a file's
+/// modification time to tell if one is still good.
+#[derive(Debug)]
+pub struct PreviewWorker {
+    tx: Sender<Request>,
+    rx: Receiver<(usize, Result<Arc<Preview>>)>,
+    next_id: usize,
+    /// Request whose result is wanted, results of earlier ones are dropped
+    pending: Option<usize>,
+}
+
+impl PreviewWorker {
+    pub fn new(highlighter: Arc<Highlighter>) -> Self {
+        let (tx, requests) = mpsc::channel::<Request>();
+        let (results, rx) = mpsc::channel();
+        thread::spawn(move || {
+            // least recently used first
+            let mut cache = Vec::new();
+            while let Ok(mut request) = requests.recv() {
+                // only the newest request matters when several piled up
+                while let Ok(newer) = requests.try_recv() {
+                    request = newer;
+                }
+                let preview = cached_or_generate(&request, &highlighter, &mut cache);
+                if results.send((request.id, preview)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            tx,
+            rx,
+            next_id: 0,
+            pending: None,
+        }
+    }
+
+    /// Start making the preview of the file at `path` from byte `start` on, highlighted with
+    /// `theme`. A preview still being made is no longer wanted.
+    pub fn request(&mut self, path: &Path, start: u64, theme: usize) {
+        self.next_id += 1;
+        self.pending = Some(self.next_id);
+        // the worker only stops once this is dropped
+        let _ = self.tx.send(Request {
+            id: self.next_id,
+            path: path.to_path_buf(),
+            start,
+            theme,
+        });
+    }
+
+    /// The preview last asked for, once it's ready
+    pub fn poll(&mut self) -> Option<Result<Arc<Preview>>> {
+        let mut ready = None;
+        while let Ok((id, result)) = self.rx.try_recv() {
+            if self.pending != Some(id) {
+                // the selection moved on since
+                continue;
+            }
+            self.pending = None;
+            ready = Some(result);
+        }
+        ready
+    }
+}
+
+/// The preview `request` asks for from `cache`, or made and added to it if the file changed
+/// since or it isn't there
+fn cached_or_generate(
+    request: &Request,
+    highlighter: &Highlighter,
+    cache: &mut Vec<(PreviewKey, Arc<Preview>)>,
+) -> Result<Arc<Preview>> {
+    let metadata = request.path.metadata().at(&request.path)?;
+    if metadata.is_dir() {
+        return Err(Error::Other(format!("{:?} is a directory", request.path)));
+    }
+    let key = PreviewKey {
+        path: request.path.clone(),
+        modified: metadata.modified().ok(),
+        start: request.start,
+        theme: request.theme,
+    };
+    if let Some(idx) = cache.iter().position(|(cached, _)| *cached == key) {
+        let entry = cache.remove(idx);
+        let preview = Arc::clone(&entry.1);
+        cache.push(entry);
+        return Ok(preview);
+    }
+    let preview = Arc::new(generate(&key, metadata.len(), highlighter)?);
+    cache.push((key, Arc::clone(&preview)));
+    if cache.len() > CACHE_SIZE {
+        cache.remove(0);
+    }
+    Ok(preview)
+}
+
+/// Read the part of the file `key` points to, as a hex dump if it's binary, otherwise as text
+/// highlighted with the theme of `key`
+fn generate(key: &PreviewKey, len: u64, highlighter: &Highlighter) -> Result<Preview> {
+    if is_binary(&key.path).at(&key.path)? {
+        let (lines, end) = read_hex(&key.path, key.start).at(&key.path)?;
+        return Ok(Preview {
+            binary: true,
+            lines,
+            end,
+            len,
+        });
+    }
+    let (text, end) = read_text(&key.path, key.start).at(&key.path)?;
+    Ok(Preview {
+        binary: false,
+        lines: highlighter.highlight(&key.path, &text, key.theme),
+        end,
+        len,
+    })
+}
+
+/// Whether the file at `path` looks binary, going by a NUL byte or invalid UTF-8 near its start
+fn is_binary(path: &Path) -> io::Result<bool> {
+    let mut head = Vec::new();
+    File::open(path)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)?;
+    if head.contains(&0) {
+        return Ok(true);
+    }
+    // a character cut off at the end of what was read is fine
+    Ok(std::str::from_utf8(&head).is_err_and(|e| e.error_len().is_some()))
+}
+
+/// Text of the file at `path` from byte `start` on, up to `CHUNK_LINES` lines or `CHUNK_BYTES`
+/// bytes, ending on a whole line where there is one. Returns it with the offset it ends at.
+fn read_text(path: &Path, start: u64) -> io::Result<(String, u64)> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut bytes = Vec::new();
+    file.take(CHUNK_BYTES as u64).read_to_end(&mut bytes)?;
+    let full = bytes.len() == CHUNK_BYTES;
+    if let Some((idx, _)) = bytes
+        .iter()
+        .enumerate()
+        .filter(|(_, byte)| **byte == b'\n')
+        .nth(CHUNK_LINES - 1)
+    {
+        bytes.truncate(idx + 1);
+    } else if full {
+        // a line longer than the whole chunk is cut wherever the chunk ends
+        if let Some(newline) = bytes.iter().rposition(|byte| *byte == b'\n') {
+            bytes.truncate(newline + 1);
+        }
+    }
+    let end = start + bytes.len() as u64;
+    let text = String::from_utf8_lossy(&bytes).replace('\t', "    ");
+    Ok((text, end))
+}
+
+/// Rows of a hex dump of the file at `path` from byte `start` on, which is at the start of a
+/// row, up to `CHUNK_LINES` rows. Returns them with the offset they end at.
+fn read_hex(path: &Path, start: u64) -> io::Result<(Vec<Line<'static>>, u64)> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut bytes = Vec::new();
+    file.take((CHUNK_LINES * BYTES_PER_ROW) as u64)
+        .read_to_end(&mut bytes)?;
+    let lines = bytes
+        .chunks(BYTES_PER_ROW)
+        .enumerate()
+        .map(|(row, bytes)| hex_row(start + (row * BYTES_PER_ROW) as u64, bytes))
+        .collect();
+    Ok((lines, start + bytes.len() as u64))
+}
+
+/// One `xxd`-style row: offset, bytes in hex in pairs, and the printable ones as text
+fn hex_row(offset: u64, bytes: &[u8]) -> Line<'static> {
+    let mut hex = String::new();
+    for (idx, byte) in bytes.iter().enumerate() {
+        hex.push_str(&format!("{:02x}", byte));
+        if idx % 2 == 1 {
+            hex.push(' ');
+        }
+    }
+    // pad a short last row so the text lines up
+    let full_width = BYTES_PER_ROW * 2 + BYTES_PER_ROW / 2;
+    let ascii: String = bytes
+        .iter()
+        .map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        })
+        .collect();
+    Line::from(vec![
+        Span::styled(
+            format!("{:08x}: ", offset),
+            Style::default().fg(Color::DarkGray),
+        ),
+        Span::raw(format!("{:<1$} ", hex, full_width)),
+        Span::raw(ascii),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::{tempdir, TempDir};
+
+    use super::*;
+
+    /// File holding `contents` in a new temporary directory
+    fn file_with(contents: &[u8]) -> (TempDir, PathBuf) {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("file");
+        fs::write(&path, contents).unwrap();
+        (temp, path)
+    }
+
+    fn text_of(line: &Line) -> String {
+        line.spans
+            .iter()
+            .map(|span| span.content.as_ref())
+            .collect()
+    }
+
+    #[test]
+    fn tells_binary_from_text() {
+        let binary = |contents: &[u8]| is_binary(&file_with(contents).1).unwrap();
+        assert!(!binary(b""));
+        assert!(!binary("plain text, ünïcödé\n".as_bytes()));
+        assert!(binary(b"text then \0 a NUL"));
+        assert!(binary(b"invalid \xff utf-8"));
+        // a character cut in half where sniffing stops still counts as text
+        let mut cut = vec![b'a'; SNIFF_LEN - 1];
+        cut.extend("é".as_bytes());
+        assert!(!binary(&cut));
+        // past what's sniffed isn't looked at
+        let mut late = vec![b'a'; SNIFF_LEN];
+        late.push(0);
+        assert!(!binary(&late));
+    }
+
+    #[test]
+    fn reads_text_up_to_the_line_limit() {
+        let contents: String = (0..CHUNK_LINES + 10)
+            .map(|i| format!("line {}\n", i))
+            .collect();
+        let (_temp, path) = file_with(contents.as_bytes());
+
+        let (text, end) = read_text(&path, 0).unwrap();
+        assert_eq!(text.lines().count(), CHUNK_LINES);
+        assert!(text.ends_with(&format!("line {}\n", CHUNK_LINES - 1)));
+        assert_eq!(end, text.len() as u64);
+
+        let (rest, rest_end) = read_text(&path, end).unwrap();
+        assert_eq!(
+            rest.lines().next().unwrap(),
+            format!("line {}", CHUNK_LINES)
+        );
+        assert_eq!(rest_end, contents.len() as u64);
+        assert_eq!(text + &rest, contents);
+    }
+
+    #[test]
+    fn reads_text_up_to_the_byte_limit_on_a_whole_line() {
+        let line = format!("{}\n", "x".repeat(999));
+        let contents = line.repeat(CHUNK_BYTES / line.len() + 5);
+        let (_temp, path) = file_with(contents.as_bytes());
+
+        let (text, end) = read_text(&path, 0).unwrap();
+        assert!(end <= CHUNK_BYTES as u64);
+        assert_eq!(end % line.len() as u64, 0);
+        assert_eq!(text.len() as u64, end);
+
+        // pages join up with nothing missed or repeated
+        let mut start = 0;
+        let mut read = String::new();
+        while start < contents.len() as u64 {
+            let (text, end) = read_text(&path, start).unwrap();
+            assert!(end > start);
+            read.push_str(&text);
+            start = end;
+        }
+        assert_eq!(read, contents);
+    }
+
+    #[test]
+    fn cuts_a_line_longer_than_the_chunk() {
+        let contents = "y".repeat(CHUNK_BYTES + 100);
+        let (_temp, path) = file_with(contents.as_bytes());
+        let (text, end) = read_text(&path, 0).unwrap();
+        assert_eq!(end, CHUNK_BYTES as u64);
+        assert_eq!(text.len(), CHUNK_BYTES);
+        let (rest, end) = read_text(&path, end).unwrap();
+        assert_eq!(rest.len(), 100);
+        assert_eq!(end, contents.len() as u64);
+    }
+
+    #[test]
+    fn expands_tabs() {
+        let (_temp, path) = file_with(b"a\tb\n");
+        assert_eq!(read_text(&path, 0).unwrap().0, "a    b\n");
+    }
+
+    #[test]
+    fn dumps_hex_rows_from_an_offset() {
+        let contents: Vec<u8> = (0..=255)
+            .cycle()
+            .take(CHUNK_LINES * BYTES_PER_ROW + 20)
+            .collect();
+        let (_temp, path) = file_with(&contents);
+
+        let (rows, end) = read_hex(&path, 0).unwrap();
+        assert_eq!(rows.len(), CHUNK_LINES);
+        assert_eq!(end, (CHUNK_LINES * BYTES_PER_ROW) as u64);
+        assert!(text_of(&rows[0]).starts_with("00000000: 0001 0203"));
+        assert!(text_of(&rows[1]).starts_with("00000010: 1011 1213"));
+
+        let (rows, rest_end) = read_hex(&path, end).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rest_end, contents.len() as u64);
+        assert!(text_of(&rows[0]).starts_with(&format!("{:08x}: ", end)));
+    }
+
+    #[test]
+    fn hex_rows_line_up() {
+        let full = text_of(&hex_row(0, b"Hello, world!\n\0\x7f"));
+        assert_eq!(
+            full,
+            "00000000: 4865 6c6c 6f2c 2077 6f72 6c64 210a 007f  Hello, world!..."
+        );
+        // a short row is padded so its text starts in the same column
+        let short = text_of(&hex_row(0x10, b"ab"));
+        assert_eq!(short.find("ab"), full.find("Hello"));
+        assert!(short.starts_with("00000010: 6162 "));
+    }
+}
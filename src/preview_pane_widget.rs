@@ -1,32 +1,231 @@
-use log::error;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
 use ratatui::{
     buffer::Buffer,
     layout::{Constraint, Layout, Rect},
-    style::{Style, Stylize},
-    text::{Line, Text},
+    style::{Color, Style, Stylize},
+    text::{Line, Span, Text},
     widgets::{Block, Paragraph, Widget},
 };
 
-#[derive(Debug, Default)]
+use crate::{
+    file_ops::format_size,
+    highlight::Highlighter,
+    preview::{Preview, PreviewWorker, BYTES_PER_ROW},
+};
+
+#[derive(Debug)]
 pub struct PreviewPane {
-    pub preview_contents: String,
-    pub is_available: bool,
+    /// What's shown, nothing if the file can't be previewed
+    preview: Option<Arc<Preview>>,
+    /// File shown or being loaded
+    path: PathBuf,
+    /// Offset into the file of the part of it shown
+    start: u64,
+    /// Number of the first line or hex dump row of that part in the file, counting from 0
+    first_line: usize,
+    /// Where the parts before this one started, with their first line numbers, for paging back
+    earlier: Vec<(u64, usize)>,
+    /// Lines scrolled past
+    scroll: usize,
+    /// Part of the file asked for that isn't ready yet: its offset, first line number and the
+    /// line to scroll to, counting back from its end if negative
+    loading: Option<(u64, usize, i64)>,
+    /// Rows the pane had room for when last drawn, for scrolling by a page
+    page_rows: usize,
+    highlighter: Arc<Highlighter>,
+    theme: usize,
+    worker: PreviewWorker,
 }
 
-impl PreviewPane {}
+impl Default for PreviewPane {
+    fn default() -> Self {
+        let highlighter = Arc::new(Highlighter::default());
+        Self {
+            preview: None,
+            path: PathBuf::new(),
+            start: 0,
+            first_line: 0,
+            earlier: Vec::new(),
+            scroll: 0,
+            loading: None,
+            page_rows: 0,
+            theme: highlighter.default_theme(),
+            worker: PreviewWorker::new(Arc::clone(&highlighter)),
+            highlighter,
+        }
+    }
+}
 
-impl Widget for &PreviewPane {
-    fn render(self, area: Rect, buf: &mut Buffer) {
-        let text = if self.is_available {
-            // TODO: expensive clone rip
-            Text::from(self.preview_contents.clone())
-        } else {
-            Text::from(Line::from("Preview Unavailable").style(Style::default().italic()))
+impl PreviewPane {
+    /// Show the file at `path`, as text or a hex dump depending on what's in it. Until it's
+    /// ready a placeholder is shown.
+    pub fn open(&mut self, path: &Path) {
+        self.clear();
+        self.path = path.to_path_buf();
+        self.show(0, 0, 0);
+    }
+
+    /// Show the part of the file from byte `start` on, which is line `first_line`, scrolled to
+    /// line `scroll` of it, once it's ready
+    fn show(&mut self, start: u64, first_line: usize, scroll: i64) {
+        self.loading = Some((start, first_line, scroll));
+        self.worker.request(&self.path, start, self.theme);
+    }
+
+    fn loaded(&mut self, preview: Arc<Preview>) {
+        let Some((start, first_line, scroll)) = self.loading.take() else {
+            return;
         };
+        let len = preview.lines.len() as i64;
+        let scroll = if scroll < 0 { len + scroll } else { scroll };
+        self.scroll = scroll.min(len - 1).max(0) as usize;
+        (self.start, self.first_line) = (start, first_line);
+        self.preview = Some(preview);
+    }
+
+    /// Show the preview asked for if it's ready. Returns whether it was.
+    pub fn poll(&mut self) -> bool {
+        match self.worker.poll() {
+            Some(Ok(preview)) => self.loaded(preview),
+            Some(Err(_)) => {
+                self.loading = None;
+                self.preview = None;
+            }
+            None => return false,
+        }
+        true
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.loading.is_some()
+    }
+
+    /// Move `pages` pages further into the file, or back for negative `pages`, reading in
+    /// the next or previous part of the file when scrolling off the one read in
+    pub fn scroll(&mut self, pages: i64) {
+        let Some(preview) = self.preview.clone() else {
+            return;
+        };
+        if self.loading.is_some() {
+            return;
+        }
+        let page = self.page_rows.max(1) as i64;
+        let lines = preview.lines.len();
+        let scroll = self.scroll as i64 + pages * page;
+        if scroll >= lines as i64 && preview.end < preview.len {
+            self.earlier.push((self.start, self.first_line));
+            self.show(preview.end, self.first_line + lines, scroll - lines as i64);
+        } else if scroll < 0 {
+            match self.earlier.pop() {
+                Some((start, first_line)) => self.show(start, first_line, scroll),
+                None => self.scroll = 0,
+            }
+        } else {
+            self.scroll = (scroll as usize).min(lines.saturating_sub(1));
+        }
+    }
+
+    /// Show that there is nothing to preview
+    pub fn clear(&mut self) {
+        self.preview = None;
+        self.loading = None;
+        self.path = PathBuf::new();
+        self.start = 0;
+        self.first_line = 0;
+        self.earlier.clear();
+        self.scroll = 0;
+    }
+
+    /// Switch to the next highlighting theme, returning its name
+    pub fn next_theme(&mut self) -> &str {
+        self.theme = self.highlighter.next_theme(self.theme);
+        // the old colors stay up until the new ones are ready
+        if let Some(Preview { binary: false, .. }) = self.preview.as_deref() {
+            if self.loading.is_none() {
+                self.show(self.start, self.first_line, self.scroll as i64);
+            }
+        }
+        self.highlighter.theme_name(self.theme)
+    }
+}
+
+impl Widget for &mut PreviewPane {
+    fn render(self, area: Rect, buf: &mut Buffer) {
         let chunks =
             Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)]).split(area);
+        self.page_rows = Block::bordered().inner(chunks[0]).height as usize;
+        let mut style = Style::default();
+        let mut block = Block::bordered().title("File Preview");
+        let text = match self.preview.as_deref() {
+            Some(Preview {
+                binary: true,
+                lines,
+                len,
+                ..
+            }) => {
+                let offset = self.start + (self.scroll * BYTES_PER_ROW) as u64;
+                block = Block::bordered().title(format!(
+                    "File Preview (binary, {}, at {:#x})",
+                    format_size(*len),
+                    offset
+                ));
+                // only what fits
+                Text::from(
+                    lines
+                        .iter()
+                        .skip(self.scroll)
+                        .take(chunks[0].height as usize)
+                        .cloned()
+                        .collect::<Vec<Line>>(),
+                )
+            }
+            Some(Preview {
+                binary: false,
+                lines,
+                end,
+                len,
+            }) => {
+                if let Some(background) = self.highlighter.background(self.theme) {
+                    style = style.bg(background);
+                }
+                if self.start > 0 || end < len {
+                    block = block.title_bottom(format!(
+                        "truncated, {} total, [ and ] to page",
+                        format_size(*len)
+                    ));
+                }
+                // line numbers right aligned to the widest one
+                let gutter_width = (self.first_line + lines.len()).max(1).to_string().len();
+                let gutter_style = Style::default().fg(Color::DarkGray);
+                Text::from(
+                    lines
+                        .iter()
+                        .enumerate()
+                        .skip(self.scroll)
+                        // only what fits
+                        .take(chunks[0].height as usize)
+                        .map(|(idx, line)| {
+                            let mut spans = vec![Span::styled(
+                                format!("{:>1$} ", self.first_line + idx + 1, gutter_width),
+                                gutter_style,
+                            )];
+                            spans.extend(line.spans.iter().cloned());
+                            Line::from(spans)
+                        })
+                        .collect::<Vec<Line>>(),
+                )
+            }
+            None if self.loading.is_some() => {
+                Text::from(Line::from("Loading…").style(Style::default().italic()))
+            }
+            None => Text::from(Line::from("Preview Unavailable").style(Style::default().italic())),
+        };
 
-        let para = Paragraph::new(text).block(Block::bordered().title("File Preview"));
+        let para = Paragraph::new(text).style(style).block(block);
         // error!("Paragraph text: {:?}", para);
         para.render(chunks[0], buf);
     }
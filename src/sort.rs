@@ -0,0 +1,292 @@
+use std::{cmp::Ordering, fs::Metadata, iter::Peekable, str::Chars};
+
+use crate::tree::FileObj;
+
+/// What entries within a directory are ordered by
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    /// Name, with runs of digits compared as numbers so `file9` comes before `file10`
+    #[default]
+    Name,
+    /// Like `Name` but ignoring case
+    NameCaseInsensitive,
+    /// Extension, then name
+    Extension,
+    /// Largest first, like `ls -S`
+    Size,
+    /// Most recently modified first, like `ls -t`
+    Modified,
+    /// Most recently created first
+    Created,
+}
+
+impl SortKey {
+    pub const ALL: [SortKey; 6] = [
+        SortKey::Name,
+        SortKey::NameCaseInsensitive,
+        SortKey::Extension,
+        SortKey::Size,
+        SortKey::Modified,
+        SortKey::Created,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::NameCaseInsensitive => "name, ignoring case",
+            SortKey::Extension => "extension",
+            SortKey::Size => "size",
+            SortKey::Modified => "modified time",
+            SortKey::Created => "created time",
+        }
+    }
+
+    /// Key that picks this in the sort menu
+    pub fn shortcut(&self) -> char {
+        match self {
+            SortKey::Name => 'n',
+            SortKey::NameCaseInsensitive => 'i',
+            SortKey::Extension => 'e',
+            SortKey::Size => 's',
+            SortKey::Modified => 'm',
+            SortKey::Created => 'c',
+        }
+    }
+
+    /// Whether comparing looks at the entries' metadata, so the order can change when it does
+    pub fn needs_metadata(&self) -> bool {
+        matches!(self, SortKey::Size | SortKey::Modified | SortKey::Created)
+    }
+}
+
+/// How every directory level in the tree is sorted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortOrder {
+    pub key: SortKey,
+    /// List directories before files, regardless of `reverse`
+    pub dirs_first: bool,
+    pub reverse: bool,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        Self {
+            key: SortKey::default(),
+            dirs_first: true,
+            reverse: false,
+        }
+    }
+}
+
+impl SortOrder {
+    /// Short description for titles, e.g. `size, dirs first`
+    pub fn describe(&self) -> String {
+        let mut description = self.key.label().to_string();
+        if self.dirs_first {
+            description.push_str(", dirs first");
+        }
+        if self.reverse {
+            description.push_str(", reversed");
+        }
+        description
+    }
+
+    /// Sort the entries of one directory
+    pub fn sort(&self, items: &mut [FileObj]) {
+        items.sort_by(|a, b| self.compare(a, b));
+    }
+
+    /// Compare two entries of the same directory
+    pub fn compare(&self, a: &FileObj, b: &FileObj) -> Ordering {
+        if self.dirs_first {
+            match (a.is_dir(), b.is_dir()) {
+                (true, false) => return Ordering::Less,
+                (false, true) => return Ordering::Greater,
+                _ => {}
+            }
+        }
+        let (a_name, b_name) = (a.name.to_string_lossy(), b.name.to_string_lossy());
+        // names only differing in bytes that aren't UTF-8 still get a fixed order
+        let by_name = || natural_cmp(&a_name, &b_name).then_with(|| a.name.cmp(&b.name));
+        let (a_meta, b_meta) = (a.metadata.as_ref(), b.metadata.as_ref());
+        let ordering = match self.key {
+            SortKey::Name => by_name(),
+            SortKey::NameCaseInsensitive => {
+                natural_cmp(&a_name.to_lowercase(), &b_name.to_lowercase()).then_with(by_name)
+            }
+            SortKey::Extension => extension(&a_name)
+                .cmp(&extension(&b_name))
+                .then_with(by_name),
+            // biggest and newest first, missing metadata last
+            SortKey::Size => {
+                let len = |meta: Option<&Metadata>| meta.map(|meta| meta.len());
+                len(b_meta).cmp(&len(a_meta)).then_with(by_name)
+            }
+            SortKey::Modified => {
+                let time = |meta: Option<&Metadata>| meta.and_then(|meta| meta.modified().ok());
+                time(b_meta).cmp(&time(a_meta)).then_with(by_name)
+            }
+            SortKey::Created => {
+                let time = |meta: Option<&Metadata>| meta.and_then(|meta| meta.created().ok());
+                time(b_meta).cmp(&time(a_meta)).then_with(by_name)
+            }
+        };
+        if self.reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+/// Lowercased extension of a file name, empty for none. A leading dot doesn't start one.
+fn extension(name: &str) -> String {
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => name[dot + 1..].to_lowercase(),
+        _ => String::new(),
+    }
+}
+
+/// Compare strings with runs of digits compared by value, so `file9` < `file10`
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (x, y) = (take_digits(&mut a), take_digits(&mut b));
+                let (x_value, y_value) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                let ordering = x_value
+                    .len()
+                    .cmp(&y_value.len())
+                    .then_with(|| x_value.cmp(y_value))
+                    // same value, fewer leading zeros first
+                    .then_with(|| x.len().cmp(&y.len()));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+fn take_digits(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        digits.push(c);
+    }
+    digits
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, path::PathBuf};
+
+    use super::*;
+    use crate::tree::FileObjType;
+
+    /// `names` sorted with `natural_cmp`
+    fn sorted(names: &[&str]) -> Vec<String> {
+        let mut names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        names.sort_by(|a, b| natural_cmp(a, b));
+        names
+    }
+
+    /// `names` as files sorted in `order`
+    fn sorted_files(order: SortOrder, names: &[&str]) -> Vec<String> {
+        let mut items: Vec<FileObj> = names
+            .iter()
+            .map(|name| FileObj::new(FileObjType::File, OsString::from(name), PathBuf::from(name)))
+            .collect();
+        order.sort(&mut items);
+        items
+            .iter()
+            .map(|item| item.name.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn digit_runs_compare_by_value() {
+        assert_eq!(
+            sorted(&["file10", "file9", "file1", "file100", "file2"]),
+            ["file1", "file2", "file9", "file10", "file100"]
+        );
+        assert_eq!(
+            sorted(&["v1.10", "v1.9", "v1.2"]),
+            ["v1.2", "v1.9", "v1.10"]
+        );
+        assert_eq!(natural_cmp("a2b10", "a2b9"), Ordering::Greater);
+    }
+
+    #[test]
+    fn leading_zeros() {
+        assert_eq!(natural_cmp("file007", "file8"), Ordering::Less);
+        assert_eq!(natural_cmp("file010", "file9"), Ordering::Greater);
+        assert_eq!(natural_cmp("file0", "file000"), Ordering::Less);
+        assert_eq!(
+            sorted(&["img002", "img1", "img10", "img01"]),
+            ["img1", "img01", "img002", "img10"]
+        );
+    }
+
+    #[test]
+    fn digit_runs_longer_than_u64() {
+        let max = u64::MAX.to_string();
+        let bigger = "18446744073709551616";
+        let huge = "123456789012345678901234567890";
+        assert_eq!(natural_cmp(&max, bigger), Ordering::Less);
+        assert_eq!(natural_cmp(bigger, huge), Ordering::Less);
+        assert_eq!(
+            natural_cmp(&format!("x{}y", huge), &format!("x{}z", huge)),
+            Ordering::Less
+        );
+        assert_eq!(
+            natural_cmp(&format!("0000{}", huge), huge),
+            Ordering::Greater
+        );
+        assert_eq!(
+            natural_cmp(&format!("0000{}", huge), "9"),
+            Ordering::Greater
+        );
+    }
+
+    #[test]
+    fn equal_values_tie_break() {
+        // fewer leading zeros first, before anything after the number is looked at
+        assert_eq!(natural_cmp("a1z", "a01a"), Ordering::Less);
+        assert_eq!(natural_cmp("a01", "a1"), Ordering::Greater);
+        // same digits, the rest decides
+        assert_eq!(natural_cmp("a1a", "a1b"), Ordering::Less);
+        assert_eq!(natural_cmp("a1", "a1.txt"), Ordering::Less);
+        assert_eq!(natural_cmp("a01b", "a01b"), Ordering::Equal);
+    }
+
+    #[test]
+    fn mixed_case() {
+        // case matters, uppercase first
+        assert_eq!(natural_cmp("Zeta", "alpha"), Ordering::Less);
+        assert_eq!(natural_cmp("File10", "file9"), Ordering::Less);
+        assert_eq!(
+            sorted_files(SortOrder::default(), &["b", "a10", "B", "A9"]),
+            ["A9", "B", "a10", "b"]
+        );
+        // ignoring case, with case only breaking ties
+        let order = SortOrder {
+            key: SortKey::NameCaseInsensitive,
+            ..SortOrder::default()
+        };
+        assert_eq!(
+            sorted_files(order, &["b", "a10", "B", "A9", "file2", "File10"]),
+            ["A9", "a10", "B", "b", "file2", "File10"]
+        );
+    }
+}
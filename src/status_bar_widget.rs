@@ -3,14 +3,16 @@ use std::path::Path;
 use ratatui::{
     buffer::Buffer,
     layout::Rect,
-    text::{Line, Text},
-    widgets::{Block, Paragraph, StatefulWidget, Widget},
+    text::Text,
+    widgets::{Block, Paragraph, Widget},
 };
 use sysinfo::{Disk, Disks, System};
 
 #[derive(Debug, Default)]
 pub struct StatusBar {
     disk_usage: f64,
+    /// Which entries the tree is hiding
+    filters: String,
 }
 
 impl StatusBar {
@@ -23,16 +25,23 @@ impl StatusBar {
         let total_filled = disk.total_space() - disk.available_space();
         StatusBar {
             disk_usage: (total_filled as f64 / disk.total_space() as f64) * 100.0,
+            filters: String::new(),
         }
     }
+
+    pub fn filters(mut self, filters: String) -> Self {
+        self.filters = filters;
+        self
+    }
 }
 
 impl Widget for &StatusBar {
     fn render(self, area: Rect, buf: &mut Buffer) {
         let status_text = format!(
-            "Usage: {:.2}% | Sys Name: {:?}",
+            "Usage: {:.2}% | Sys Name: {:?} | Filter: {}",
             self.disk_usage,
-            System::name().unwrap_or("Unknown".to_string())
+            System::name().unwrap_or("Unknown".to_string()),
+            self.filters
         );
         let paragraph = Paragraph::new(Text::from(status_text)).block(Block::bordered());
         paragraph.render(area, buf);
@@ -0,0 +1,146 @@
+use std::{
+    env,
+    ffi::OsString,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use chrono::Local;
+
+use crate::error::{Error, PathContext, Result};
+
+/// Home trash directory as described by the freedesktop.org trash spec
+pub fn trash_dir() -> Result<PathBuf> {
+    let data_home = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".local/share"),
+            None => {
+                return Err(Error::Other(
+                    "Neither XDG_DATA_HOME nor HOME is set".to_string(),
+                ))
+            }
+        },
+    };
+    Ok(data_home.join("Trash"))
+}
+
+/// Claim a spot in the trash for `path` by writing its `.trashinfo`, so it can be restored
+/// once moved there. Returns the location to move it to. If it doesn't get moved there, the
+/// spot is given back with `remove_info`.
+pub fn claim_slot(path: &Path) -> Result<PathBuf> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| Error::Other(format!("{:?} can't be trashed", path)))?;
+    // only resolve the parent, a symlink should be trashed itself rather than its target
+    let parent = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent.canonicalize().at(parent)?,
+        _ => env::current_dir()?,
+    };
+    let path = parent.join(name);
+
+    let trash = trash_dir()?;
+    let files_dir = trash.join("files");
+    let info_dir = trash.join("info");
+    fs::create_dir_all(&files_dir).at(&files_dir)?;
+    fs::create_dir_all(&info_dir).at(&info_dir)?;
+
+    // claim a unique name by creating the info file first, as the spec asks. A name left
+    // taken in files/ without its info file counts as taken too.
+    let mut counter = 1;
+    let (trash_name, mut info_file) = loop {
+        let mut candidate = OsString::from(name);
+        if counter > 1 {
+            candidate.push(format!(".{}", counter));
+        }
+        if fs::symlink_metadata(files_dir.join(&candidate)).is_ok() {
+            counter += 1;
+            continue;
+        }
+        let mut info_name = candidate.clone();
+        info_name.push(".trashinfo");
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(info_dir.join(&info_name))
+        {
+            Ok(file) => break (candidate, file),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => counter += 1,
+            Err(e) => return Err(e).at(&info_dir),
+        }
+    };
+    let info_path = info_dir.join({
+        let mut info_name = trash_name.clone();
+        info_name.push(".trashinfo");
+        info_name
+    });
+    write!(
+        info_file,
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        url_encode(&path),
+        Local::now().format("%Y-%m-%dT%H:%M:%S")
+    )
+    .at(&info_path)?;
+
+    Ok(files_dir.join(&trash_name))
+}
+
+/// Drop the `.trashinfo` of the item at `trashed` in the trash, once it has been restored or
+/// didn't make it in
+pub fn remove_info(trashed: &Path) -> Result<()> {
+    let mut info_name = trashed
+        .file_name()
+        .ok_or_else(|| Error::Other(format!("{:?} is not a trashed item", trashed)))?
+        .to_os_string();
+    info_name.push(".trashinfo");
+    let info_path = trash_dir()?.join("info").join(info_name);
+    fs::remove_file(&info_path).at(&info_path)
+}
+
+/// Percent-encode a path for the `Path=` key of a `.trashinfo` file
+fn url_encode(path: &Path) -> String {
+    let mut encoded = String::new();
+    for &byte in path.as_os_str().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn claim_skips_names_taken_in_either_directory() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        env::set_var("XDG_DATA_HOME", dir.join("data"));
+        let trash = dir.join("data/Trash");
+        fs::create_dir_all(trash.join("files")).unwrap();
+        // left behind without its info file
+        fs::write(trash.join("files/a.txt"), "orphan").unwrap();
+        fs::write(dir.join("a.txt"), "a").unwrap();
+
+        let first = claim_slot(&dir.join("a.txt")).unwrap();
+        assert_eq!(first, trash.join("files/a.txt.2"));
+        let second = claim_slot(&dir.join("a.txt")).unwrap();
+        assert_eq!(second, trash.join("files/a.txt.3"));
+        let info = fs::read_to_string(trash.join("info/a.txt.2.trashinfo")).unwrap();
+        assert!(info.contains(&format!(
+            "Path={}",
+            url_encode(&dir.canonicalize().unwrap().join("a.txt"))
+        )));
+
+        remove_info(&first).unwrap();
+        assert!(!trash.join("info/a.txt.2.trashinfo").exists());
+        assert_eq!(claim_slot(&dir.join("a.txt")).unwrap(), first);
+    }
+}
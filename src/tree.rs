@@ -1,21 +1,26 @@
-use log::error;
-
-use crate::file_tree_state::FileTreeState;
+use crate::arena::{NodeId, TreeArena};
+use crate::error::{Error, PathContext, Result};
+use crate::file_tree_state::{FileTreeState, VisualKind};
+use crate::filter::EntryFilter;
+use crate::loader::{DirLoader, LoadEvent, LoadId};
+use crate::sort::SortOrder;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::env;
-use std::fs;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, Metadata};
 use std::io;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long opening a directory waits for it to be read before showing it as loading, so small
+/// ones don't flicker
+const QUICK_LOAD: Duration = Duration::from_millis(25);
 
-/// An action to take after a tree operation
-#[derive(Default, Debug)]
-pub enum TreeAction {
-    #[default]
-    None,
-    GenerateParent,
-    GenerateChild(usize),
-    ShiftIntoChild,
-}
 /// File tree navigational directions
 #[derive(Default, Debug)]
 pub enum NavDirection {
@@ -32,6 +37,82 @@ pub enum FileObjType {
     #[default]
     File,
     Directory(DirectoryStatus),
+    /// Symlink to a directory, which opens like one
+    DirSymlink {
+        target: PathBuf,
+        status: DirectoryStatus,
+    },
+    /// Symlink to anything other than a directory
+    FileSymlink {
+        target: PathBuf,
+    },
+    /// Symlink to something that doesn't exist, or to itself
+    BrokenSymlink {
+        target: PathBuf,
+    },
+}
+
+impl FileObjType {
+    /// Type of the entry at `path` with metadata `meta` (not following symlinks), telling
+    /// symlinks apart from what they point to. Directories start out collapsed.
+    pub fn of(path: &Path, meta: &Metadata) -> io::Result<Self> {
+        if !meta.file_type().is_symlink() {
+            return Ok(if meta.is_dir() {
+                FileObjType::Directory(DirectoryStatus::Collapsed)
+            } else {
+                FileObjType::File
+            });
+        }
+        let target = fs::read_link(path)?;
+        Ok(match fs::metadata(path) {
+            Ok(meta) if meta.is_dir() => FileObjType::DirSymlink {
+                target,
+                status: DirectoryStatus::Collapsed,
+            },
+            Ok(_) => FileObjType::FileSymlink { target },
+            Err(_) => FileObjType::BrokenSymlink { target },
+        })
+    }
+
+    /// Whether it is open or collapsed, for anything that opens like a directory
+    pub fn dir_status(&self) -> Option<&DirectoryStatus> {
+        match self {
+            FileObjType::Directory(status) | FileObjType::DirSymlink { status, .. } => Some(status),
+            _ => None,
+        }
+    }
+
+    /// Open or collapse it, if it opens like a directory
+    pub fn set_dir_status(&mut self, new_status: DirectoryStatus) {
+        if let FileObjType::Directory(status) | FileObjType::DirSymlink { status, .. } = self {
+            *status = new_status;
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.dir_status() == Some(&DirectoryStatus::Open)
+    }
+
+    pub fn is_collapsed(&self) -> bool {
+        self.dir_status() == Some(&DirectoryStatus::Collapsed)
+    }
+
+    /// Where a symlink points, as stored in the link
+    pub fn link_target(&self) -> Option<&Path> {
+        match self {
+            FileObjType::DirSymlink { target, .. }
+            | FileObjType::FileSymlink { target }
+            | FileObjType::BrokenSymlink { target } => Some(target),
+            _ => None,
+        }
+    }
+
+    /// The same type as listed fresh from disk, with directories collapsed
+    fn listed(&self) -> Self {
+        let mut listed = self.clone();
+        listed.set_dir_status(DirectoryStatus::Collapsed);
+        listed
+    }
 }
 
 #[derive(Default, Debug, Eq, Clone, PartialEq)]
@@ -43,384 +124,923 @@ pub enum DirectoryStatus {
 
 #[derive(Default, Debug, Clone)]
 pub struct FileObj {
-    pub sub_items_size: usize,
     pub object_type: FileObjType,
-    pub name: String,
-    pub depth: usize,
+    /// Name as it is on disk, which need not be valid UTF-8
+    pub name: OsString,
     pub path: PathBuf,
+    /// Size, times, permissions and so on from when it was listed, of the link itself for a
+    /// symlink
+    pub metadata: Option<Metadata>,
 }
 
 impl FileObj {
-    pub fn new(obj_type: FileObjType, name: String, depth: usize, path: PathBuf) -> Self {
+    pub fn new(obj_type: FileObjType, name: OsString, path: PathBuf) -> Self {
         Self {
-            sub_items_size: 0,
             object_type: obj_type,
             name,
-            depth,
             path,
+            metadata: None,
         }
     }
+
+    pub fn metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    /// Name for showing on screen, see `display_name`
+    pub fn display_name(&self) -> String {
+        display_name(&self.name)
+    }
+
+    /// Whether it opens like a directory, following symlinks
+    pub fn is_dir(&self) -> bool {
+        self.object_type.dir_status().is_some()
+    }
 }
 
 /// Struct resembling a directory structure, with user state
 #[derive(Default, Debug)]
 pub struct FileTree {
     pub state: FileTreeState,
-    pub linear_list: Vec<FileObj>,
-    pub root_path: PathBuf,
+    /// Loaded entries, with the root at the top
+    pub nodes: TreeArena,
+    /// Entries that were skipped while listing a directory, for the app to report
+    pub skipped: Vec<Error>,
+    /// Order of the entries within every directory
+    pub sort: SortOrder,
+    /// Entries left out of every directory
+    pub filter: EntryFilter,
+    loader: DirLoader,
+    /// Directories being read in the background
+    loads: HashMap<NodeId, Load>,
+}
+
+/// A directory being read in the background
+#[derive(Debug)]
+struct Load {
+    id: LoadId,
+    cancel: Arc<AtomicBool>,
+    /// Move the cursor into the directory once its first entries are in
+    enter: bool,
+    /// Entry the cursor was moved to on entering
+    first: Option<PathBuf>,
 }
 
 impl FileTree {
-    pub fn new() -> Self {
+    pub fn new() -> Result<Self> {
         let mut tree = Self {
             state: FileTreeState::default(),
-            linear_list: Vec::new(),
-            root_path: PathBuf::new(),
+            nodes: TreeArena::default(),
+            skipped: Vec::new(),
+            sort: SortOrder::default(),
+            filter: EntryFilter::default(),
+            loader: DirLoader::default(),
+            loads: HashMap::new(),
         };
 
-        // keep the list of objects for the component use
-        let root_path = env::current_dir();
-        match root_path {
-            Ok(path) => {
-                let items = tree.generate_level(path.as_path(), 0);
-                tree.linear_list = items;
-                tree.root_path = path;
+        tree.set_root(env::current_dir()?)?;
+        Ok(tree)
+    }
+
+    /// Show the contents of `path` from scratch, with nothing open
+    fn set_root(&mut self, path: PathBuf) -> Result<()> {
+        let entries = self.generate_level(&path)?;
+        self.nodes = TreeArena::new(root_obj(path));
+        self.nodes.open(self.nodes.root(), entries);
+        self.drop_stale_loads();
+        self.state.clear_selection();
+        self.state.list_state.select_first();
+        Ok(())
+    }
+
+    /// Directory the tree is showing the contents of
+    pub fn root_path(&self) -> &Path {
+        &self.nodes.obj(self.nodes.root()).path
+    }
+
+    /// Node under the cursor, as long as it is on an entry
+    fn selected_id(&self) -> Result<NodeId> {
+        self.state
+            .list_state
+            .selected()
+            .and_then(|idx| self.nodes.at(idx))
+            .ok_or(Error::NothingSelected)
+    }
+
+    /// Collapse or open directory contents if type is directory
+    pub fn try_toggle_collapse(&mut self) -> Result<()> {
+        let id = self.selected_id()?;
+        match self.nodes.obj(id).object_type.dir_status() {
+            Some(DirectoryStatus::Collapsed) => self.start_load(id, false)?,
+            Some(DirectoryStatus::Open) => {
+                // collapsing a directory that is still loading stops it
+                self.nodes.close(id);
+                self.drop_stale_loads();
             }
-            Err(e) => println!("Current Dir error: {}", e),
+            None => {}
         }
-        tree
+        Ok(())
     }
 
-    /// Collapse or open directory contents if type is directory
-    pub fn try_toggle_collapse(&mut self) -> io::Result<()> {
-        let idx = self.state.list_state.selected().expect("No file selected");
-        let mut list: Vec<FileObj> = self.linear_list.clone();
-        match list[idx].object_type {
-            FileObjType::Directory(DirectoryStatus::Collapsed) => {
-                list[idx].object_type = FileObjType::Directory(DirectoryStatus::Open);
-                let subdir_path = list[idx].path.as_path();
-                let depth = list[idx].depth;
-                // error!("{:#?}", subdir_path);
-                let subdir_items = self.generate_level(subdir_path, depth + 1);
-                list[idx].sub_items_size = subdir_items.len();
-                self.linear_list = list;
-                self.insert_list(subdir_items, idx + 1);
+    /// Open the collapsed directory `id` and read its contents in the background, moving the
+    /// cursor into it once they start coming in if `enter` is set
+    fn start_load(&mut self, id: NodeId, enter: bool) -> Result<()> {
+        self.check_cycle(id)?;
+        let path = self.nodes.obj(id).path.clone();
+        self.keeping_cursor(|tree| tree.nodes.start_loading(id));
+        let (load, cancel) = self.loader.start(path);
+        let previous = self.loads.insert(
+            id,
+            Load {
+                id: load,
+                cancel,
+                enter,
+                first: None,
+            },
+        );
+        if let Some(previous) = previous {
+            previous.cancel.store(true, atomic::Ordering::Relaxed);
+        }
+
+        let deadline = Instant::now() + QUICK_LOAD;
+        while self
+            .loads
+            .get(&id)
+            .is_some_and(|current| current.id == load)
+        {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.loader.recv_timeout(left) {
+                Some((load, event)) => self.apply_load_event(load, event),
+                None => break,
             }
+        }
+        Ok(())
+    }
 
-            FileObjType::Directory(DirectoryStatus::Open) => {
-                self.linear_list[idx].object_type =
-                    FileObjType::Directory(DirectoryStatus::Collapsed);
-                let first = &self.linear_list[..idx + 1];
-                let mut stop = idx + 1;
-                while stop < self.linear_list.len()
-                    && self.linear_list[stop].depth > self.linear_list[idx].depth
-                {
-                    stop += 1;
-                }
-                // if it doesn't have a "last"
-                if stop == self.linear_list.len() {
-                    self.linear_list = first.to_vec();
-                } else {
-                    let last = &self.linear_list[stop..];
-                    self.linear_list = [first, last].concat();
-                }
-                self.linear_list[idx].sub_items_size = 0;
+    /// Refuse to open a symlink to a directory that contains it, or any directory it's shown
+    /// inside of through other links, which would nest forever
+    fn check_cycle(&self, id: NodeId) -> Result<()> {
+        let obj = self.nodes.obj(id);
+        if !matches!(obj.object_type, FileObjType::DirSymlink { .. }) {
+            return Ok(());
+        }
+        let target = fs::canonicalize(&obj.path).at(&obj.path)?;
+        for ancestor in self.nodes.ancestors(id) {
+            let path = &self.nodes.obj(ancestor).path;
+            if fs::canonicalize(path).at(path)?.starts_with(&target) {
+                return Err(Error::SymlinkCycle(obj.path.clone()));
             }
-            FileObjType::File => {}
         }
         Ok(())
     }
 
-    /// Return reference to the FileObj at the currently selected index
-    pub fn get_selected_item(&mut self) -> &FileObj {
-        let idx = self.state.list_state.selected().expect("No file selected");
-        &self.linear_list[idx]
+    /// Add whatever background reads have come up with since the last call. Returns whether
+    /// anything changed.
+    pub fn poll_loads(&mut self) -> bool {
+        let mut changed = false;
+        while let Some((load, event)) = self.loader.try_recv() {
+            self.apply_load_event(load, event);
+            changed = true;
+        }
+        changed
     }
 
-    /// Determine TreeAction to take based on NavDirection and call action handle
-    pub fn ft_move(&mut self, direction: NavDirection) {
-        match direction {
-            NavDirection::Up => {
-                let action = self.state.move_up(&self.linear_list);
-                self.handle_action(action);
+    /// Whether any directory is still being read
+    pub fn is_loading(&self) -> bool {
+        !self.loads.is_empty()
+    }
+
+    fn apply_load_event(&mut self, load: LoadId, event: LoadEvent) {
+        self.drop_stale_loads();
+        let Some(dir) = self
+            .loads
+            .iter()
+            .find_map(|(dir, current)| (current.id == load).then_some(*dir))
+        else {
+            // cancelled
+            return;
+        };
+        match event {
+            LoadEvent::Entries(entries) => self.add_loaded(dir, entries),
+            LoadEvent::Skipped(e) => self.skipped.push(e),
+            LoadEvent::Done(result) => {
+                self.loads.remove(&dir);
+                self.keeping_cursor(|tree| match result {
+                    Ok(_) => tree.nodes.finish_loading(dir),
+                    Err(e) => {
+                        tree.nodes.close(dir);
+                        tree.skipped.push(e);
+                    }
+                });
+            }
+        }
+    }
+
+    /// Sort a batch of entries read for `dir` in with the ones already there
+    fn add_loaded(&mut self, dir: NodeId, entries: Vec<FileObj>) {
+        let dir_path = self.nodes.obj(dir).path.clone();
+        let cursor = self.get_selected_item().ok().map(|item| item.path.clone());
+        let Some(load) = self.loads.get_mut(&dir) else {
+            return;
+        };
+        let mut batch = Vec::new();
+        for mut obj in entries {
+            // the directory may have been renamed since it was opened
+            obj.path = dir_path.join(&obj.name);
+            if self.nodes.find(&obj.path).is_some() || !self.filter.allows(&obj.path, obj.is_dir())
+            {
+                continue;
             }
-            NavDirection::Down => {
-                let action = self.state.move_down(&self.linear_list);
-                self.handle_action(action);
+            batch.push(obj);
+        }
+        let sort = self.sort;
+        sort.sort(&mut batch);
+        self.nodes.add_sorted(dir, batch, |a, b| sort.compare(a, b));
+
+        // entering keeps the cursor on the first entry as earlier ones come in, until it's moved
+        let first = self.nodes.get(dir).children.first().copied();
+        let on_first = match (&cursor, load.first.as_ref()) {
+            (Some(cursor), Some(first)) => cursor == first,
+            (Some(cursor), None) => *cursor == dir_path,
+            _ => false,
+        };
+        load.enter &= on_first;
+        if load.enter && first.is_some() {
+            load.first = first.map(|first| self.nodes.obj(first).path.clone());
+            self.select_node(first);
+        } else if let Some(cursor) = cursor {
+            self.select_path(&cursor);
+        }
+    }
+
+    /// Stop reading directories that were collapsed or removed
+    fn drop_stale_loads(&mut self) {
+        let nodes = &self.nodes;
+        self.loads.retain(|dir, load| {
+            let current = nodes.contains(*dir) && nodes.get(*dir).loading;
+            if !current {
+                load.cancel.store(true, atomic::Ordering::Relaxed);
             }
-            NavDirection::IntoDir => {
-                let action = self.state.move_sub_dir(&self.linear_list);
-                self.handle_action(action);
+            current
+        });
+    }
+
+    /// Open the collapsed directory `id`, loading its contents into the tree
+    fn expand(&mut self, id: NodeId) -> Result<()> {
+        let path = self.nodes.obj(id).path.clone();
+        let entries = self.generate_level(&path)?;
+        self.keeping_cursor(|tree| tree.nodes.open(id, entries));
+        Ok(())
+    }
+
+    /// Run a change to the tree, then put the cursor back on the entry it was on
+    fn keeping_cursor<T>(&mut self, change: impl FnOnce(&mut Self) -> T) -> T {
+        let cursor = self
+            .selected_id()
+            .ok()
+            .map(|id| self.nodes.obj(id).path.clone());
+        let result = change(self);
+        if let Some(cursor) = cursor {
+            self.select_path(&cursor);
+        }
+        result
+    }
+
+    /// Add a newly created `path` to the tree in its sorted place among its siblings, if its
+    /// directory is currently open. Returns the row it is at.
+    pub fn insert_entry(&mut self, path: &Path) -> Option<usize> {
+        if let Some(id) = self.nodes.find(path) {
+            return self.nodes.row_of(id);
+        }
+        let parent = self.nodes.find(path.parent()?)?;
+        if !self.nodes.obj(parent).object_type.is_open() {
+            return None;
+        }
+
+        let meta = fs::symlink_metadata(path).ok()?;
+        let object_type = FileObjType::of(path, &meta).ok()?;
+        if !self.filter.allows(path, object_type.dir_status().is_some()) {
+            return None;
+        }
+        let name = path.file_name()?.to_os_string();
+        let new_obj = FileObj::new(object_type, name, path.to_path_buf()).metadata(meta);
+        let id = self.insert_sorted(parent, new_obj);
+        self.nodes.row_of(id)
+    }
+
+    /// Insert `new_obj` among the contents of the open directory `parent`
+    fn insert_sorted(&mut self, parent: NodeId, new_obj: FileObj) -> NodeId {
+        let pos = self.sorted_position(parent, &new_obj, None);
+        self.keeping_cursor(|tree| tree.nodes.insert(parent, pos, new_obj))
+    }
+
+    /// Bring the contents of `dir` in line with what is on disk, if it is the root or an open
+    /// directory. Entries that are gone are removed and new ones inserted in their sorted place,
+    /// leaving everything else (open directories, the cursor, the selection) as it was.
+    pub fn reconcile_dir(&mut self, dir: &Path) -> Result<()> {
+        let Some(parent) = self.nodes.find(dir) else {
+            return Ok(());
+        };
+        let node = self.nodes.get(parent);
+        // anything that changes while loading is picked up by the load itself
+        if !node.obj.object_type.is_open() || node.loading {
+            return Ok(());
+        }
+
+        // unreadable entries were already reported when the directory was opened
+        let skipped = self.skipped.len();
+        let listed = match self.generate_level(dir) {
+            Ok(listed) => listed,
+            // deleted, its own parent drops it
+            Err(Error::Io { source, .. }) if source.kind() == io::ErrorKind::NotFound => {
+                return Ok(())
             }
-            NavDirection::OutOfDir => {
-                let action = self.state.move_parent_dir(&self.linear_list);
-                self.handle_action(action);
+            Err(e) => return Err(e),
+        };
+        self.skipped.truncate(skipped);
+
+        let current: HashMap<PathBuf, FileObjType> = self
+            .nodes
+            .get(parent)
+            .children
+            .iter()
+            .map(|child| {
+                let obj = self.nodes.obj(*child);
+                (obj.path.clone(), obj.object_type.listed())
+            })
+            .collect();
+        let on_disk: HashMap<&Path, &FileObjType> = listed
+            .iter()
+            .map(|item| (item.path.as_path(), &item.object_type))
+            .collect();
+
+        // an entry replaced by one of another type, or a symlink pointed elsewhere, is removed
+        // and added again
+        for (path, was) in &current {
+            if on_disk.get(path.as_path()) != Some(&was) {
+                self.remove_entry(path);
             }
-            NavDirection::ZoomIn => {
-                self.handle_action(TreeAction::ShiftIntoChild);
+        }
+        let mut updated = false;
+        for item in listed {
+            if current.get(&item.path) != Some(&item.object_type) {
+                self.insert_sorted(parent, item);
+            } else if let Some(id) = self.nodes.find(&item.path) {
+                // sizes and times shown in the detail view
+                self.nodes.set_metadata(id, item.metadata);
+                updated = true;
             }
         }
+        if updated && self.sort.key.needs_metadata() {
+            let sort = self.sort;
+            self.keeping_cursor(|tree| tree.nodes.sort_by(|a, b| sort.compare(a, b)));
+        }
+        Ok(())
     }
 
-    /// Insert a list of file objects at a specified index in the main list
-    fn insert_list(&mut self, list: Vec<FileObj>, index: usize) {
-        let mut old = self.linear_list.clone();
-        let _ = old.splice(index..index, list).collect::<Vec<FileObj>>();
-        self.linear_list = old;
+    /// The root and every open directory, which are the ones whose contents are shown
+    pub fn shown_dirs(&self) -> HashSet<PathBuf> {
+        self.nodes
+            .ids()
+            .map(|id| self.nodes.obj(id))
+            .filter(|item| item.object_type.is_open())
+            .map(|item| item.path.clone())
+            .collect()
     }
 
-    /// Handle a TreeAction by manipulating the tree & its state
-    fn handle_action(&mut self, action: TreeAction) {
-        match action {
-            TreeAction::GenerateParent => {
-                let path = self.root_path.clone();
-                let new_path = match path.parent() {
-                    Some(path) => path,
-                    None => return,
+    /// Every directory with a row in the tree, open or collapsed, leaving out symlinks to them
+    pub fn dir_rows(&self) -> Vec<PathBuf> {
+        (0..self.nodes.rows().len())
+            .filter_map(|row| self.nodes.at(row))
+            .map(|id| self.nodes.obj(id))
+            .filter(|item| matches!(item.object_type, FileObjType::Directory(_)))
+            .map(|item| item.path.clone())
+            .collect()
+    }
+
+    /// Position among the contents of `parent` before the first entry that sorts after
+    /// `new_obj`, leaving out `moving` if it is already one of them
+    fn sorted_position(&self, parent: NodeId, new_obj: &FileObj, moving: Option<NodeId>) -> usize {
+        self.nodes
+            .get(parent)
+            .children
+            .iter()
+            .filter(|child| Some(**child) != moving)
+            .take_while(|child| {
+                self.sort.compare(self.nodes.obj(**child), new_obj) != Ordering::Greater
+            })
+            .count()
+    }
+
+    /// Change the sort order and re-sort every expanded directory, keeping the cursor on the
+    /// same entry
+    pub fn set_sort(&mut self, sort: SortOrder) {
+        self.sort = sort;
+        self.keeping_cursor(|tree| tree.nodes.sort_by(|a, b| sort.compare(a, b)));
+    }
+
+    /// Show or hide dotfiles
+    pub fn toggle_dotfiles(&mut self) -> Result<()> {
+        self.filter.hide_dotfiles = !self.filter.hide_dotfiles;
+        self.refresh()
+    }
+
+    /// Show or hide entries matched by ignore files
+    pub fn toggle_ignored(&mut self) -> Result<()> {
+        self.filter.hide_ignored = !self.filter.hide_ignored;
+        self.refresh()
+    }
+
+    /// Read every shown directory again and bring the tree in line with it. Open directories
+    /// stay open, and the cursor stays on the same entry, or moves to the nearest sibling if it
+    /// is gone.
+    pub fn refresh(&mut self) -> Result<()> {
+        let root = self.root_path().to_path_buf();
+        fs::metadata(&root).at(&root)?;
+        // ignore files may have been edited too
+        self.filter.clear_cache();
+        let mut dirs: Vec<PathBuf> = self.shown_dirs().into_iter().collect();
+        // parents first, so nothing is listed inside a directory that turns out to be gone
+        dirs.sort_by_key(|dir| dir.components().count());
+        for dir in dirs {
+            // one unreadable directory shouldn't stop the rest from refreshing
+            if let Err(e) = self.reconcile_dir(&dir) {
+                self.skipped.push(e);
+            }
+        }
+        if self.selected_id().is_err() {
+            self.state.list_state.select(Some(0));
+        }
+        // contents of the entry under the cursor may have changed
+        self.state.prev_idx = usize::MAX;
+        Ok(())
+    }
+
+    /// Make `path` visible by opening every directory above it, then add it to the tree.
+    /// Returns the row it is at, if it is under the root.
+    pub fn reveal(&mut self, path: &Path) -> Result<Option<usize>> {
+        let root = self.root_path().to_path_buf();
+        let Ok(rel) = path.strip_prefix(&root) else {
+            return Ok(None);
+        };
+        let mut ancestor = root;
+        let components: Vec<_> = rel.components().collect();
+        for component in &components[..components.len().saturating_sub(1)] {
+            ancestor.push(component);
+            if self.insert_entry(&ancestor).is_none() {
+                return Ok(None);
+            }
+            let Some(id) = self.nodes.find(&ancestor) else {
+                return Ok(None);
+            };
+            if self.nodes.obj(id).object_type.is_collapsed() {
+                self.check_cycle(id)?;
+                self.expand(id)?;
+            }
+        }
+        Ok(self.insert_entry(path))
+    }
+
+    /// Move the cursor to the entry the selected symlink points to, opening the directories on
+    /// the way there. A target outside the tree becomes visible by rooting the tree at its
+    /// directory.
+    pub fn jump_to_link_target(&mut self) -> Result<()> {
+        let link = self.get_selected_item()?;
+        let Some(target) = link.object_type.link_target() else {
+            return Err(Error::Other(format!("{:?} is not a symlink", link.path)));
+        };
+        let target = match link.path.parent() {
+            Some(dir) => dir.join(target),
+            None => target.to_path_buf(),
+        };
+        // resolve the directories on the way, but not the target itself so a link to a link
+        // lands on the second link
+        let target = match (target.parent(), target.file_name()) {
+            (Some(dir), Some(name)) => fs::canonicalize(dir).at(dir)?.join(name),
+            // ends in `..`
+            _ => fs::canonicalize(&target).at(&target)?,
+        };
+
+        let root = self.root_path().to_path_buf();
+        let real_root = fs::canonicalize(&root).at(&root)?;
+        if let Ok(rel) = real_root.strip_prefix(&target) {
+            // the root or a directory above it, shown by moving the root up past it
+            for _ in 0..=rel.components().count() {
+                self.shift_root_up()?;
+            }
+            return Ok(());
+        }
+        let path = match target.strip_prefix(&real_root) {
+            Ok(rel) => root.join(rel),
+            Err(_) => {
+                let Some(dir) = target.parent() else {
+                    return self.set_root(target);
                 };
-                let parent_items: Vec<FileObj> = self.generate_level(new_path, 0);
-                // increase depth of every existing item
-                // error!("This is the new root layer: {:#?}", parent_items);
-                for item in &mut self.linear_list {
-                    item.depth += 1;
-                }
-                // find the place this list should live
-                let old = self.linear_list.clone(); // save old root
-                self.linear_list = parent_items; // put new root
-                                                 // error!("Old root to insert: {:#?}", old);
-                                                 // error!("New root: {:#?}", self.linear_list);
-                for (i, item) in self.linear_list.iter().enumerate() {
-                    // this is where the existing list is
-                    if item.path == path {
-                        // update collapse status
-                        self.linear_list.get_mut(i).unwrap().object_type =
-                            FileObjType::Directory(DirectoryStatus::Open);
-                        self.linear_list.get_mut(i).unwrap().sub_items_size = old.len();
-                        // self.linear_list.remove(i);
-                        self.insert_list(old, i + 1); // insert old root
-                                                      // update parent indices
-                        let _ = self
-                            .state
-                            .parent_indices
-                            .iter()
-                            .map(|val| val + i)
-                            .collect::<Vec<usize>>();
-                        // self.state.parent_indices.insert(0, i);
-                        // let old_selected_idx = self.state.list_state.selected().expect("Problem!");
-                        // self.state.list_state.select(Some(old_selected_idx + i));
-                        self.state.list_state.select(Some(i));
-                        self.root_path = new_path.to_path_buf();
-                        break;
-                    }
-                }
+                self.set_root(dir.to_path_buf())?;
+                target
+            }
+        };
+        if self.reveal(&path)?.is_none() {
+            return Err(Error::Io {
+                path: Some(path),
+                source: io::ErrorKind::NotFound.into(),
+            });
+        }
+        self.select_path(&path);
+        Ok(())
+    }
+
+    /// Move the cursor to the entry at `path`, if it is shown
+    pub fn select_path(&mut self, path: &Path) {
+        if let Some(row) = self.nodes.find(path).and_then(|id| self.nodes.row_of(id)) {
+            self.state.list_state.select(Some(row));
+        }
+    }
+
+    /// Drop the entry at `path` and any of its expanded contents from the tree, leaving the rest
+    /// of the tree as it was. A cursor inside it moves to the next entry in the same directory,
+    /// or the previous one, or the directory itself if it is now empty.
+    pub fn remove_entry(&mut self, path: &Path) {
+        let Some(id) = self.nodes.find(path) else {
+            return;
+        };
+        let cursor = self.selected_id().ok();
+        let landing = match cursor {
+            Some(cursor) if self.nodes.is_within(cursor, id) => self
+                .nodes
+                .sibling(id, 1)
+                .or_else(|| self.nodes.sibling(id, -1))
+                .or(self.nodes.get(id).parent)
+                .filter(|landing| *landing != self.nodes.root()),
+            other => other,
+        };
+        let landing = landing.map(|landing| self.nodes.obj(landing).path.clone());
+        self.nodes.remove(id);
+        self.drop_stale_loads();
+        match landing {
+            Some(landing) => self.select_path(&landing),
+            None => self.state.list_state.select(Some(0)),
+        }
+        self.state.unselect(path);
+        // the same row may now show a different file
+        self.state.prev_idx = usize::MAX;
+    }
+
+    /// Point each renamed entry, and any of its expanded contents, to its new location without
+    /// regenerating anything. The renames were done together, so they may swap or pass along names.
+    pub fn rename_entries(&mut self, renames: &[(PathBuf, PathBuf)]) {
+        self.state.paths_renamed(renames);
+        // everything is looked up before anything changes, as a swap reuses the names
+        let (in_place, elsewhere): (Vec<_>, Vec<_>) = renames
+            .iter()
+            .filter_map(|(from, to)| Some((self.nodes.find(from)?, from, to)))
+            .partition(|(_, from, to)| from.parent() == to.parent());
+        self.keeping_cursor(|tree| {
+            // moved to another directory, which may not even be open
+            for (_, from, _) in &elsewhere {
+                tree.remove_entry(from);
             }
-            TreeAction::GenerateChild(idx) => {
-                if let FileObjType::Directory(DirectoryStatus::Collapsed) =
-                    self.linear_list[idx].object_type
-                {
-                    self.linear_list[idx].object_type =
-                        FileObjType::Directory(DirectoryStatus::Open);
+            let in_place: Vec<(NodeId, &Path)> = in_place
+                .iter()
+                .map(|(id, _, to)| (*id, to.as_path()))
+                .collect();
+            tree.nodes.rename(&in_place);
+            // the new names may sort somewhere else among their siblings
+            if let [(id, _)] = in_place[..] {
+                if let Some(parent) = tree.nodes.get(id).parent {
+                    let pos = tree.sorted_position(parent, tree.nodes.obj(id), Some(id));
+                    tree.nodes.move_to(id, pos);
                 }
-                let path = self.linear_list[idx].path.clone();
-                let depth = self.linear_list[idx].depth + 1;
-                let list: Vec<FileObj> = self.generate_level(path.as_path(), depth);
-                self.linear_list[idx].sub_items_size = list.len();
-                self.insert_list(list, idx + 1);
-                // kinda hacky but ok, perform the move after regeneration, because move_sub_dir
-                // returned with the GenerateChild action the first time
-                self.state.move_sub_dir(&self.linear_list);
+            } else if !in_place.is_empty() {
+                let sort = tree.sort;
+                tree.nodes.sort_by(|a, b| sort.compare(a, b));
+            }
+            for (_, _, to) in &elsewhere {
+                tree.insert_entry(to);
             }
-            TreeAction::ShiftIntoChild => {
-                // remove all left-most level items
-                // self.linear_list.retain(|item| item.depth != 0);
+        });
+    }
 
-                let mut head = if let Some(idx) = self.state.parent_indices.first() {
-                    *idx
-                } else {
-                    return;
-                };
-                // set path
-                self.root_path = self.linear_list.get(head).unwrap().path.clone();
-
-                let mut i = 0usize;
-                let mut j = self.linear_list.len();
-                let mut searching = true;
-                // error!("Head: {head}");
-                while i < j {
-                    // handle skipping over the subdir contents being kept
-                    if i == head && searching {
-                        searching = false;
-                        self.linear_list.remove(i);
-                        j -= 1;
-                        while self.linear_list.get(i).unwrap().depth != 0 && i < j {
-                            i += 1;
-                        }
-                        error!("impossible");
-                        continue;
-                    }
+    /// Paths of every entry in the same directory as the selected one
+    pub fn sibling_paths(&self) -> Vec<PathBuf> {
+        let Some(parent) = self
+            .selected_id()
+            .ok()
+            .and_then(|id| self.nodes.get(id).parent)
+        else {
+            return Vec::new();
+        };
+        self.nodes
+            .get(parent)
+            .children
+            .iter()
+            .map(|child| self.nodes.obj(*child).path.clone())
+            .collect()
+    }
 
-                    // delete contents of open directories when needed
-                    if let FileObjType::Directory(DirectoryStatus::Open) =
-                        self.linear_list.get(i).unwrap().object_type
-                    {
-                        let to = self.linear_list.get(i).unwrap().sub_items_size;
-                        error!("To: {}", to);
-                        self.linear_list.drain(i..i + to + 1);
-                        j -= to + 1;
-                        error!("Here");
-                        if searching {
-                            head -= to + 1;
-                        }
-                    };
-                    if self.linear_list.get(i).unwrap().depth == 0 {
-                        self.linear_list.remove(i);
-                        j -= 1;
-                        if searching {
-                            head -= 1;
-                        }
-                        error!("dont");
-                    }
+    /// Enter or leave visual selection mode
+    pub fn toggle_visual(&mut self, kind: VisualKind) {
+        self.state.toggle_visual(&self.nodes, kind);
+    }
+
+    /// Take the paths a batch operation should act on
+    pub fn take_selection(&mut self) -> Vec<PathBuf> {
+        self.state.take_selection(&self.nodes)
+    }
+
+    /// Return reference to the FileObj under the cursor
+    pub fn get_selected_item(&self) -> Result<&FileObj> {
+        Ok(self.nodes.obj(self.selected_id()?))
+    }
+
+    /// Move the cursor, loading directories or shifting the root as needed
+    pub fn ft_move(&mut self, direction: NavDirection) -> Result<()> {
+        let Ok(id) = self.selected_id() else {
+            // only moving out of an empty root makes sense
+            if let NavDirection::OutOfDir = direction {
+                return self.shift_root_up();
+            }
+            return Ok(());
+        };
+        match direction {
+            NavDirection::Up => self.select_node(self.nodes.sibling(id, -1)),
+            NavDirection::Down => self.select_node(self.nodes.sibling(id, 1)),
+            NavDirection::IntoDir => {
+                if self.nodes.obj(id).object_type.is_collapsed() {
+                    self.start_load(id, true)?;
+                } else {
+                    self.select_node(self.nodes.get(id).children.first().copied());
+                }
+            }
+            NavDirection::OutOfDir => match self.nodes.get(id).parent {
+                Some(parent) if parent != self.nodes.root() => self.select_node(Some(parent)),
+                _ => self.shift_root_up()?,
+            },
+            NavDirection::ZoomIn => {
+                // the directory directly under the root that the cursor is inside of
+                let top = self
+                    .nodes
+                    .ancestors(id)
+                    .take_while(|ancestor| *ancestor != self.nodes.root())
+                    .last();
+                if let Some(top) = top {
+                    self.keeping_cursor(|tree| tree.nodes.zoom_into(top));
+                    self.drop_stale_loads();
                 }
-                // adjust depth
-                self.linear_list.iter_mut().for_each(|item| item.depth -= 1);
-
-                // adjust parent indices values because of the list changes
-                let shift = self.state.parent_indices.remove(0);
-                self.state
-                    .parent_indices
-                    .iter_mut()
-                    .for_each(|idx| *idx -= shift + 1);
-                // selected index shift
-                let old_idx = self.state.list_state.selected().unwrap();
-                self.state.list_state.select(Some(old_idx - shift - 1));
             }
-            TreeAction::None => {}
         }
+        Ok(())
     }
 
-    fn generate_level(&mut self, root: &Path, new_depth: usize) -> Vec<FileObj> {
-        let mut list = Vec::<FileObj>::new();
-        let iterator = match fs::read_dir(root) {
-            Ok(val) => val,
-            Err(e) => {
-                println!("Reading Error: {e}");
-                return Vec::<FileObj>::new();
-            }
+    /// Put the cursor on `id`, if there is one
+    fn select_node(&mut self, id: Option<NodeId>) {
+        if let Some(row) = id.and_then(|id| self.nodes.row_of(id)) {
+            self.state.list_state.select(Some(row));
+        }
+    }
+
+    /// Make the root's parent the root, with the cursor on the old root
+    fn shift_root_up(&mut self) -> Result<()> {
+        let old_root = self.nodes.root();
+        let path = self.root_path().to_path_buf();
+        let Some(parent) = path.parent() else {
+            return Ok(());
         };
-        for (i, entry) in iterator.enumerate() {
+        let mut entries = self.generate_level(parent)?;
+        // the current root stays in the tree even if it would be filtered out
+        if !entries.iter().any(|item| item.path == path) {
+            entries.push(self.nodes.obj(old_root).clone());
+            self.sort.sort(&mut entries);
+        }
+        self.nodes
+            .reroot_up(root_obj(parent.to_path_buf()), entries);
+        self.select_node(Some(old_root));
+        Ok(())
+    }
+
+    /// List the contents of `root`. Entries that can't be read are left out and recorded in
+    /// `skipped`.
+    fn generate_level(&mut self, root: &Path) -> Result<Vec<FileObj>> {
+        let mut list = Vec::<FileObj>::new();
+        let iterator = fs::read_dir(root).at(root)?;
+        for entry in iterator {
             let entry = match entry {
                 Ok(en) => en,
                 Err(e) => {
-                    println!("Entry Error: {e}");
+                    self.skipped.push(Error::Io {
+                        path: Some(root.to_path_buf()),
+                        source: e,
+                    });
                     continue;
                 }
             };
             let path = entry.path();
-            let file_type = if path.is_dir() {
-                FileObjType::Directory(DirectoryStatus::Collapsed)
-            } else {
-                FileObjType::File
-            };
-            let item_name = entry.file_name().into_string();
-            let item_name = match item_name {
-                Ok(name) => name,
+            let read = entry
+                .metadata()
+                .and_then(|meta| Ok((FileObjType::of(&path, &meta)?, meta)));
+            let (file_type, meta) = match read {
+                Ok(read) => read,
                 Err(e) => {
-                    println!("Error converting filename: {:?}", e);
-                    continue; // skip invalid filename entries
+                    self.skipped.push(Error::Io {
+                        path: Some(path),
+                        source: e,
+                    });
+                    continue;
                 }
             };
-            let new_obj = FileObj {
-                sub_items_size: 0,
-                object_type: file_type,
-                name: item_name,
-                depth: new_depth,
-                path,
-            };
-            list.insert(i, new_obj);
+            if !self.filter.allows(&path, file_type.dir_status().is_some()) {
+                continue;
+            }
+            list.push(FileObj::new(file_type, entry.file_name(), path).metadata(meta));
         }
-        // error!("{:?}", list);
-        list.to_vec()
+        self.sort.sort(&mut list);
+        Ok(list)
     }
+}
 
-    /// Regenerates the list used for the filetree
-    fn regen_tree(&mut self, direction: NavDirection) {
-        let old_root = self.root_path.clone();
-        // path of selected file before regen
-        let cur_selected_path = self.get_selected_item().path.clone();
-        let cur_selected_parent = match cur_selected_path.parent() {
-            Some(path) => path,
-            None => return,
-        };
+/// Entry for a directory the tree is rooted at
+fn root_obj(path: PathBuf) -> FileObj {
+    let name = path.file_name().unwrap_or_default().to_os_string();
+    let metadata = fs::metadata(&path).ok();
+    FileObj {
+        metadata,
+        ..FileObj::new(FileObjType::Directory(DirectoryStatus::Open), name, path)
+    }
+}
 
-        let new_root_path: &Path = match direction {
-            NavDirection::IntoDir => {
-                // &cur_selected_path
-                let next_root_idx = self.state.parent_indices[0];
-                &self.linear_list[next_root_idx].path.clone()
-            }
-            NavDirection::OutOfDir => match old_root.parent() {
-                Some(valid_path) => {
-                    if valid_path.to_str() != Some("") {
-                        valid_path
-                    } else {
-                        return;
+/// Text for a file name that can be shown and edited. Bytes that aren't valid UTF-8 and control
+/// characters are written as `\xNN`, and backslashes are doubled so `parse_name` can undo it.
+pub fn display_name(name: &OsStr) -> String {
+    let mut text = String::new();
+    for chunk in name.as_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => text.push_str("\\\\"),
+                c if c.is_control() => {
+                    let mut buf = [0; 4];
+                    for byte in c.encode_utf8(&mut buf).bytes() {
+                        text.push_str(&format!("\\x{:02x}", byte));
                     }
                 }
-                None => return,
-            },
-            // this really should not ever happen lol
-            _ => return,
-        };
+                c => text.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            text.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    text
+}
 
-        // expensive, sad
-        let mut old_parents_path: Vec<PathBuf> = self
-            .state
-            .parent_indices
+/// File name from text written the way `display_name` shows it
+pub fn parse_name(text: &str) -> OsString {
+    let mut bytes = Vec::new();
+    let mut rest = text;
+    while let Some(at) = rest.find('\\') {
+        bytes.extend_from_slice(&rest.as_bytes()[..at]);
+        let escape = &rest[at..];
+        if let Some(after) = escape.strip_prefix("\\\\") {
+            bytes.push(b'\\');
+            rest = after;
+        } else if let Some(byte) = escape
+            .get(2..4)
+            .filter(|hex| escape.starts_with("\\x") && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            bytes.push(byte);
+            rest = &escape[4..];
+        } else {
+            // not an escape, kept as typed
+            bytes.push(b'\\');
+            rest = &escape[1..];
+        }
+    }
+    bytes.extend_from_slice(rest.as_bytes());
+    OsString::from_vec(bytes)
+}
+
+/// Where `path` ends up when `from` is moved to `to`, if it is `from` or inside it
+pub fn rebase_path(path: &Path, from: &Path, to: &Path) -> Option<PathBuf> {
+    let rest = path.strip_prefix(from).ok()?;
+    if rest.as_os_str().is_empty() {
+        Some(to.to_path_buf())
+    } else {
+        Some(to.join(rest))
+    }
+}
+
+/// Where `path` ends up after `renames` were done together, if one of them takes it along
+pub fn rebase_paths(path: &Path, renames: &[(PathBuf, PathBuf)]) -> Option<PathBuf> {
+    renames
+        .iter()
+        .find_map(|(from, to)| rebase_path(path, from, to))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Tree showing `path`, with nothing open
+    fn tree_at(path: &Path) -> FileTree {
+        let mut tree = FileTree::default();
+        tree.set_root(path.to_path_buf()).unwrap();
+        tree
+    }
+
+    /// Names of the entries shown under the root, in order
+    fn shown_names(tree: &FileTree) -> Vec<String> {
+        tree.nodes
+            .get(tree.nodes.root())
+            .children
             .iter()
-            .skip(1)
-            .map(|idx| self.linear_list[*idx].path.clone())
-            .collect();
+            .map(|id| tree.nodes.obj(*id).name.to_string_lossy().into_owned())
+            .collect()
+    }
 
-        // this will be the newest parent, forcing it to be added
-        old_parents_path.push(cur_selected_path.clone());
-        self.linear_list = Vec::new();
-        self.state = FileTreeState::default();
-        // let _ = self.get_files(new_root_path, 3);
-
-        let mut new_parents: Vec<usize> = Vec::new();
-        if let NavDirection::IntoDir = direction {
-            for (i, item) in self.linear_list.iter().enumerate() {
-                if item.path == old_parents_path[0] {
-                    new_parents.push(i);
-                    old_parents_path.remove(0);
-                    // have found all parents
-                    if old_parents_path.is_empty() {
-                        break;
-                    }
-                }
-            }
+    fn round_trip(name: &[u8]) {
+        let name = OsStr::from_bytes(name);
+        let text = display_name(name);
+        assert_eq!(parse_name(&text), name, "{:?} shown as {:?}", name, text);
+    }
+
+    #[test]
+    fn renames_swapped_entries_together() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        for name in ["a", "b", "c"] {
+            fs::create_dir(dir.join(name)).unwrap();
         }
-        self.state.parent_indices = new_parents;
-
-        // set pointer state appropriately
-        for (i, item) in self.linear_list.iter().enumerate() {
-            match direction {
-                NavDirection::IntoDir => {
-                    if item.path == cur_selected_path {
-                        // don't go into empty dirs
-                        let idx = if item.sub_items_size > 0 { i + 1 } else { i };
-                        self.state.list_state.select(Some(idx));
-                        return;
-                    }
-                }
-                NavDirection::OutOfDir => {
-                    if item.path == cur_selected_parent {
-                        self.state.list_state.select(Some(i));
-                        return;
-                    }
-                }
-                _ => {}
-            }
+        let mut tree = tree_at(dir);
+        tree.rename_entries(&[
+            (dir.join("a"), dir.join("b")),
+            (dir.join("b"), dir.join("a")),
+            (dir.join("c"), dir.join("d")),
+        ]);
+        assert_eq!(shown_names(&tree), ["a", "b", "d"]);
+        for name in ["a", "b", "d"] {
+            let id = tree.nodes.find(&dir.join(name)).unwrap();
+            assert_eq!(tree.nodes.obj(id).path, dir.join(name));
         }
+        assert!(tree.nodes.find(&dir.join("c")).is_none());
+    }
+
+    #[test]
+    fn names_round_trip() {
+        round_trip(b"plain.txt");
+        round_trip("ünïcödé 日本".as_bytes());
+        round_trip(b"back\\slash\\");
+        round_trip(b"\\x41 looks like an escape");
+        round_trip(b"tab\there\nnewline\x7f");
+        round_trip(b"invalid \xff\xfe utf-8");
+        round_trip(b"cut short \xe6\x97");
+        round_trip(b"\x80");
+    }
+
+    #[test]
+    fn shows_escapes() {
+        assert_eq!(display_name(OsStr::from_bytes(b"a\xffb")), "a\\xffb");
+        assert_eq!(display_name(OsStr::from_bytes(b"a\tb")), "a\\x09b");
+        assert_eq!(display_name(OsStr::new("a\\b")), "a\\\\b");
+    }
+
+    #[test]
+    fn parses_only_real_escapes() {
+        assert_eq!(parse_name("\\x41\\xFf"), OsStr::from_bytes(b"A\xff"));
+        // not two hex digits, kept as typed
+        assert_eq!(parse_name("\\x+f"), OsStr::new("\\x+f"));
+        assert_eq!(parse_name("\\x-1"), OsStr::new("\\x-1"));
+        assert_eq!(parse_name("\\xg0"), OsStr::new("\\xg0"));
+        assert_eq!(parse_name("end\\x4"), OsStr::new("end\\x4"));
+        assert_eq!(parse_name("\\xé1"), OsStr::new("\\xé1"));
+        assert_eq!(parse_name("lone \\ slash\\"), OsStr::new("lone \\ slash\\"));
+    }
+
+    #[test]
+    fn refuses_symlink_cycles() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("p/x")).unwrap();
+        fs::create_dir_all(dir.join("q/y")).unwrap();
+        symlink(dir.join("p"), dir.join("p/x/up")).unwrap();
+        // each link leads into the directory holding the other
+        symlink(dir.join("q/y"), dir.join("p/x/there")).unwrap();
+        symlink(dir.join("p/x"), dir.join("q/y/back")).unwrap();
+        let mut tree = tree_at(&dir.join("p"));
+
+        assert!(matches!(
+            tree.reveal(&dir.join("p/x/up/x")),
+            Err(Error::SymlinkCycle(_))
+        ));
+        // only shown inside its target through the first link
+        assert!(tree.reveal(&dir.join("p/x/there/none")).is_ok());
+        assert!(matches!(
+            tree.reveal(&dir.join("p/x/there/back/x")),
+            Err(Error::SymlinkCycle(_))
+        ));
     }
 }
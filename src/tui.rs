@@ -43,6 +43,22 @@ impl Tui {
         Ok(())
     }
 
+    /// Hand the terminal back to the shell, e.g. while an external editor runs
+    pub fn suspend(&mut self) -> io::Result<()> {
+        Self::reset()?;
+        self.terminal.show_cursor()?;
+        Ok(())
+    }
+
+    /// Take the terminal back after `suspend`
+    pub fn resume(&mut self) -> io::Result<()> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stderr(), EnterAlternateScreen, EnableMouseCapture)?;
+        self.terminal.hide_cursor()?;
+        self.terminal.clear()?;
+        Ok(())
+    }
+
     fn reset() -> io::Result<()> {
         terminal::disable_raw_mode()?;
         execute!(io::stderr(), LeaveAlternateScreen, DisableMouseCapture)?;
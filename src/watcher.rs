@@ -0,0 +1,172 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    time::{Duration, Instant},
+};
+
+use notify::{
+    event::{ModifyKind, RenameMode},
+    Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+
+use crate::{
+    error::{Error, Result},
+    tree::rebase_path,
+};
+
+/// Quiet time after the last event before changes are applied, so a burst like an extracted
+/// archive is handled in one go
+const DEBOUNCE: Duration = Duration::from_millis(150);
+/// Longest changes are held back while events keep coming
+const MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// Names of files whose rules decide which entries are hidden
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+/// What changed on disk since the last time changes were taken
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// Entries renamed within the watched directories, in the order it happened
+    pub renames: Vec<(PathBuf, PathBuf)>,
+    /// Directories whose listing needs comparing against the tree
+    pub dirs: HashSet<PathBuf>,
+    /// Events were lost, so every shown directory needs comparing
+    pub everything: bool,
+    /// An ignore file was created, edited or removed, so cached rules are stale
+    pub ignore_rules: bool,
+}
+
+/// Watches the directories shown in the tree, without recursing into collapsed ones
+#[derive(Debug)]
+pub struct TreeWatcher {
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    watched: HashSet<PathBuf>,
+    changes: Changes,
+    /// When the first and the latest event of the current burst came in
+    burst: Option<(Instant, Instant)>,
+    /// Watching failed, most likely from running out of inotify watches. Only reported once.
+    failed: bool,
+}
+
+impl TreeWatcher {
+    pub fn new() -> Result<Self> {
+        let (tx, events) = mpsc::channel();
+        let watcher = notify::recommended_watcher(tx).map_err(watch_error)?;
+        Ok(Self {
+            watcher,
+            events,
+            watched: HashSet::new(),
+            changes: Changes::default(),
+            burst: None,
+            failed: false,
+        })
+    }
+
+    /// Watch exactly `dirs`, adding and removing watches for the ones that changed since the
+    /// last call
+    pub fn sync(&mut self, dirs: HashSet<PathBuf>) -> Result<()> {
+        for dir in self.watched.difference(&dirs) {
+            // the watch is already gone if the directory was deleted or moved
+            let _ = self.watcher.unwatch(dir);
+        }
+        self.watched.retain(|dir| dirs.contains(dir));
+        let mut result = Ok(());
+        for dir in dirs {
+            if self.watched.contains(&dir) {
+                continue;
+            }
+            match self.watcher.watch(&dir, RecursiveMode::NonRecursive) {
+                Ok(_) => {
+                    self.watched.insert(dir);
+                }
+                // gone before it could be watched, the parent's listing will drop it
+                Err(e) if matches!(e.kind, notify::ErrorKind::PathNotFound) => {}
+                Err(e) if !self.failed => {
+                    self.failed = true;
+                    result = Err(watch_error(e));
+                }
+                Err(_) => {}
+            }
+        }
+        result
+    }
+
+    /// Collect events that came in, and hand over the changes once things have been quiet for a
+    /// moment
+    pub fn poll(&mut self) -> Option<Changes> {
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                Ok(event) => self.record(event),
+                // whatever happened is unknown, compare everything
+                Err(_) => self.changes.everything = true,
+            }
+            let now = Instant::now();
+            self.burst = Some(match self.burst {
+                Some((first, _)) => (first, now),
+                None => (now, now),
+            });
+        }
+        let (first, latest) = self.burst?;
+        if latest.elapsed() < DEBOUNCE && first.elapsed() < MAX_DELAY {
+            return None;
+        }
+        self.burst = None;
+        Some(std::mem::take(&mut self.changes))
+    }
+
+    /// Whether changes are waiting for their burst to end, so the caller should poll again soon
+    pub fn is_pending(&self) -> bool {
+        self.burst.is_some()
+    }
+
+    fn record(&mut self, event: Event) {
+        if event.need_rescan() {
+            self.changes.everything = true;
+        }
+        if event.paths.iter().any(|path| is_ignore_file(path)) {
+            self.changes.ignore_rules = true;
+        }
+        match event.kind {
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                if let [from, to] = &event.paths[..] {
+                    // changes recorded inside a renamed directory happened at its new path now
+                    self.changes.dirs = self
+                        .changes
+                        .dirs
+                        .drain()
+                        .map(|dir| rebase_path(&dir, from, to).unwrap_or(dir))
+                        .collect();
+                    self.changes.renames.push((from.clone(), to.clone()));
+                }
+            }
+            // contents and metadata changes only matter for the sizes and times shown
+            EventKind::Access(_) => return,
+            _ => {}
+        }
+        for path in &event.paths {
+            if let Some(parent) = path.parent() {
+                self.changes.dirs.insert(parent.to_path_buf());
+            }
+        }
+    }
+}
+
+fn is_ignore_file(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| IGNORE_FILES.iter().any(|ignore| name == *ignore))
+}
+
+fn watch_error(error: notify::Error) -> Error {
+    match error.kind {
+        notify::ErrorKind::Io(source) => Error::Io {
+            path: error.paths.into_iter().next(),
+            source,
+        },
+        notify::ErrorKind::MaxFilesWatch => Error::Other(
+            "Too many directories to watch, raise fs.inotify.max_user_watches".to_string(),
+        ),
+        kind => Error::Other(format!("Watching failed: {:?}", kind)),
+    }
+}